@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// 激活码
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct License {
+    pub code: String,
+    // 过期时间（秒级时间戳），None 表示永久有效
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub auto_switch: bool,
+    // 绑定的 Token ID
+    #[serde(default)]
+    pub token_ids: Vec<String>,
+}
+
+// Token 池中的账号
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    pub id: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "default_true")]
+    pub is_valid: bool,
+    #[serde(default)]
+    pub quota_used: Option<i64>,
+    #[serde(default)]
+    pub quota_total: Option<i64>,
+    pub access_token: String,
+    pub refresh_token: String,
+    // Token 在服务器上的更新时间（/tokens/check 返回）
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+// /client/version 返回的版本信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub version: String,
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub size: Option<i64>,
+    #[serde(default)]
+    pub changelog: Option<String>,
+    #[serde(default)]
+    pub force_update: bool,
+    #[serde(default)]
+    pub download_url: Option<String>,
+    // 安装包 SHA-256（hex），为空时按下载内容计算
+    #[serde(default)]
+    pub sha256: Option<String>,
+    // Ed25519 签名（base64），为空时用 mock 的签名私钥生成
+    #[serde(default)]
+    pub signature: Option<String>,
+    // 提供增量补丁的基础版本
+    #[serde(default)]
+    pub patch_from: Option<PatchSource>,
+}
+
+// 增量补丁的基础版本：mock 按 download_bytes 生成新旧文件内容并计算补丁
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchSource {
+    pub version: String,
+    pub filename: String,
+    pub size: i64,
+}
+
+// /client/announcements 返回的公告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Announcement {
+    pub id: String,
+    // info / warning / critical
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    // 生效/失效时间（秒级时间戳），None 表示不限
+    #[serde(default)]
+    pub starts_at: Option<i64>,
+    #[serde(default)]
+    pub ends_at: Option<i64>,
+    // 适用的客户端版本范围（含边界），None 表示不限
+    #[serde(default)]
+    pub min_version: Option<String>,
+    #[serde(default)]
+    pub max_version: Option<String>,
+}
+
+// 可预置的服务器数据
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub licenses: Vec<License>,
+    #[serde(default)]
+    pub tokens: Vec<Token>,
+    // 为空时 /client/version 返回 hasUpdate: false
+    #[serde(default)]
+    pub version: Option<VersionInfo>,
+    // beta 频道的版本，为空时 beta 频道返回正式版
+    #[serde(default)]
+    pub beta_version: Option<VersionInfo>,
+    // 原样返回，由客户端按时间和版本过滤
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    // 会话有效期（秒）
+    #[serde(default = "default_session_ttl")]
+    pub session_ttl: i64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_severity() -> String {
+    "info".to_string()
+}
+
+fn default_session_ttl() -> i64 {
+    24 * 3600
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        Fixtures {
+            licenses: Vec::new(),
+            tokens: Vec::new(),
+            version: None,
+            beta_version: None,
+            announcements: Vec::new(),
+            session_ttl: default_session_ttl(),
+        }
+    }
+}
+
+impl Fixtures {
+    // 内置示例数据：一个普通激活码、一个自动切换激活码、三个账号
+    pub fn sample() -> Self {
+        let token = |id: &str, email: &str, used: i64| Token {
+            id: id.to_string(),
+            email: Some(email.to_string()),
+            name: Some(email.split('@').next().unwrap_or(id).to_string()),
+            is_valid: true,
+            quota_used: Some(used),
+            quota_total: Some(1000),
+            access_token: format!("access-{}", id),
+            refresh_token: format!("refresh-{}", id),
+            updated_at: 1_700_000_000,
+        };
+        Fixtures {
+            licenses: vec![
+                License {
+                    code: "TEST-AAAA-BBBB-CCCC".to_string(),
+                    expires_at: None,
+                    auto_switch: false,
+                    token_ids: vec!["token-1".to_string(), "token-2".to_string()],
+                },
+                License {
+                    code: "AUTO-AAAA-BBBB-CCCC".to_string(),
+                    expires_at: None,
+                    auto_switch: true,
+                    token_ids: vec!["token-3".to_string()],
+                },
+            ],
+            tokens: vec![
+                token("token-1", "alice@example.com", 120),
+                token("token-2", "bob@example.com", 640),
+                token("token-3", "carol@example.com", 10),
+            ],
+            version: None,
+            beta_version: None,
+            announcements: vec![Announcement {
+                id: "welcome".to_string(),
+                severity: "info".to_string(),
+                title: "欢迎使用".to_string(),
+                body: "这是 mock 服务器的示例公告".to_string(),
+                starts_at: None,
+                ends_at: None,
+                min_version: None,
+                max_version: None,
+            }],
+            session_ttl: default_session_ttl(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("读取 fixtures 失败 {}: {}", path.display(), e))?;
+        serde_json::from_str(&content).map_err(|e| format!("fixtures 格式错误: {}", e))
+    }
+
+    pub fn license(&self, code: &str) -> Option<&License> {
+        self.licenses.iter().find(|l| l.code == code)
+    }
+
+    pub fn token(&self, id: &str) -> Option<&Token> {
+        self.tokens.iter().find(|t| t.id == id)
+    }
+}
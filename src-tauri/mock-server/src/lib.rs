@@ -0,0 +1,1084 @@
+//! ATM Tray Mock Server
+//! 实现客户端使用的服务器协议，用于本地开发和测试
+//!
+//! 签名和加密直接复用客户端的 crypto.rs，保证与客户端协议一致
+
+#[allow(dead_code)]
+#[path = "../../src/crypto.rs"]
+mod crypto;
+mod fixtures;
+
+pub use fixtures::{Announcement, Fixtures, License, PatchSource, Token, VersionInfo};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{FromRequest, OriginalUri, Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// 与客户端 security::verify_timestamp 一致的时间窗口
+const TIMESTAMP_WINDOW: i64 = 300;
+
+// 故障注入配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Fault {
+    // 直接返回该状态码（如 401、500、503）
+    #[serde(default)]
+    pub status: Option<u16>,
+    // 响应前延迟（毫秒）
+    #[serde(default)]
+    pub delay_ms: u64,
+    // 返回截断的 JSON
+    #[serde(default)]
+    pub malformed: bool,
+    // 加密数据使用错误长度的 IV
+    #[serde(default)]
+    pub bad_iv: bool,
+    // 篡改 AES-GCM 认证标签
+    #[serde(default)]
+    pub tampered_tag: bool,
+    // 篡改响应 MAC
+    #[serde(default)]
+    pub bad_mac: bool,
+    // 响应 MAC 使用过期的时间戳
+    #[serde(default)]
+    pub stale_mac: bool,
+    // 随故障状态码返回的 Retry-After（秒）
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+    // 下载接口发送该字节数后停止发送数据（连接保持打开，模拟卡住的下载）
+    #[serde(default)]
+    pub stall_after: Option<usize>,
+    // 生效次数，None 表示一直生效
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    code: String,
+    device_id: String,
+    expires_at: i64,
+}
+
+struct Inner {
+    fixtures: Fixtures,
+    // 激活码 -> 绑定的设备
+    bindings: HashMap<String, String>,
+    sessions: HashMap<String, Session>,
+    // 路由名 -> 故障，"*" 对所有路由生效
+    faults: HashMap<String, Fault>,
+    hits: HashMap<String, u32>,
+    // 处理完成（延迟结束后生成了响应）的次数；客户端中途断开时处理随之中止，不计入
+    completed: HashMap<String, u32>,
+    // 返回 304 Not Modified 的次数
+    not_modified: HashMap<String, u32>,
+    // 返回 206 Partial Content 的次数
+    partial: HashMap<String, u32>,
+    // 时间窗口内已使用的 v2 随机数 -> 请求时间戳
+    nonces: HashMap<String, i64>,
+    // 迁移完成后拒绝 v1 签名
+    require_v2: bool,
+    // 服务器时钟相对本机的偏差（秒），模拟客户端时钟不准
+    clock_offset: i64,
+    // 推送通道：token_updated 广播给所有 WebSocket 连接
+    push: tokio::sync::broadcast::Sender<String>,
+    // WebSocket 客户端的订阅记录（按时间顺序）
+    subscriptions: Vec<String>,
+    // 收到的请求：(路径, X-Request-ID)，按时间顺序
+    request_ids: Vec<(String, String)>,
+    // 在标准输出打印请求日志
+    log_requests: bool,
+}
+
+// 服务器状态（测试代码通过它注入故障、统计请求次数）
+#[derive(Clone)]
+pub struct MockState(Arc<Mutex<Inner>>);
+
+impl MockState {
+    pub fn new(fixtures: Fixtures) -> Self {
+        MockState(Arc::new(Mutex::new(Inner {
+            fixtures,
+            bindings: HashMap::new(),
+            sessions: HashMap::new(),
+            faults: HashMap::new(),
+            hits: HashMap::new(),
+            completed: HashMap::new(),
+            not_modified: HashMap::new(),
+            partial: HashMap::new(),
+            nonces: HashMap::new(),
+            require_v2: false,
+            clock_offset: 0,
+            push: tokio::sync::broadcast::channel(16).0,
+            subscriptions: Vec::new(),
+            request_ids: Vec::new(),
+            log_requests: false,
+        })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 路由名与客户端 retry::send 使用的名称一致，如 "tokens"、"auth/activate"
+    pub fn set_fault(&self, route: &str, fault: Fault) {
+        self.lock().faults.insert(route.to_string(), fault);
+    }
+
+    pub fn clear_faults(&self) {
+        self.lock().faults.clear();
+    }
+
+    pub fn hits(&self, route: &str) -> u32 {
+        self.lock().hits.get(route).copied().unwrap_or(0)
+    }
+
+    pub fn completed(&self, route: &str) -> u32 {
+        self.lock().completed.get(route).copied().unwrap_or(0)
+    }
+
+    pub fn reset_hits(&self) {
+        let mut inner = self.lock();
+        inner.hits.clear();
+        inner.completed.clear();
+        inner.not_modified.clear();
+        inner.partial.clear();
+    }
+
+    pub fn not_modified_hits(&self, route: &str) -> u32 {
+        self.lock().not_modified.get(route).copied().unwrap_or(0)
+    }
+
+    pub fn partial_hits(&self, route: &str) -> u32 {
+        self.lock().partial.get(route).copied().unwrap_or(0)
+    }
+
+    // 只接受 v2 签名（模拟迁移完成后的服务器）
+    pub fn require_signature_v2(&self, required: bool) {
+        self.lock().require_v2 = required;
+    }
+
+    // 服务器时钟偏差：签名时间戳校验、响应 MAC 时间戳和 Date 响应头都按偏差后的时间
+    pub fn set_clock_offset(&self, secs: i64) {
+        self.lock().clock_offset = secs;
+    }
+
+    fn now(&self) -> i64 {
+        self.lock().now()
+    }
+
+    // 模拟服务器上的 token 凭据更新：刷新 updated_at 并推送给订阅者，返回在线连接数
+    pub fn push_token_updated(&self, token_id: &str) -> usize {
+        let mut inner = self.lock();
+        let updated_at = now();
+        if let Some(token) = inner.fixtures.tokens.iter_mut().find(|t| t.id == token_id) {
+            token.updated_at = token.updated_at.max(updated_at);
+        }
+        inner.push.send(token_id.to_string()).unwrap_or(0)
+    }
+
+    pub fn push_subscriptions(&self) -> Vec<String> {
+        self.lock().subscriptions.clone()
+    }
+
+    // 路径以 path 结尾的请求携带的 X-Request-ID（按时间顺序，未携带的请求不记录）
+    pub fn request_ids(&self, path: &str) -> Vec<String> {
+        self.lock().request_ids.iter().filter(|(p, _)| p.ends_with(path)).map(|(_, id)| id.clone()).collect()
+    }
+
+    // 每个请求打印一行日志（含请求 ID），便于按客户端报告的请求 ID 查找
+    pub fn log_requests(&self, enabled: bool) {
+        self.lock().log_requests = enabled;
+    }
+
+    // 运行时修改预置数据（如更新 token、发布新版本）
+    pub fn with_fixtures<R>(&self, f: impl FnOnce(&mut Fixtures) -> R) -> R {
+        f(&mut self.lock().fixtures)
+    }
+
+    // 直接签发会话（跳过激活流程）
+    pub fn issue_session(&self, code: &str, device_id: &str) -> String {
+        let mut inner = self.lock();
+        let token = uuid::Uuid::new_v4().to_string();
+        let expires_at = now() + inner.fixtures.session_ttl;
+        inner.bindings.insert(code.to_string(), device_id.to_string());
+        inner.sessions.insert(
+            token.clone(),
+            Session { code: code.to_string(), device_id: device_id.to_string(), expires_at },
+        );
+        token
+    }
+
+    // 记录一次请求并取出本次生效的故障
+    fn begin(&self, route: &str) -> Fault {
+        let mut inner = self.lock();
+        *inner.hits.entry(route.to_string()).or_insert(0) += 1;
+        let key = if inner.faults.contains_key(route) { route } else { "*" };
+        let Some(fault) = inner.faults.get_mut(key) else {
+            return Fault::default();
+        };
+        let active = fault.clone();
+        if let Some(times) = fault.times.as_mut() {
+            *times = times.saturating_sub(1);
+            if *times == 0 {
+                inner.faults.remove(key);
+            }
+        }
+        active
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+impl Inner {
+    // 服务器时钟（含模拟的偏差）
+    fn now(&self) -> i64 {
+        now() + self.clock_offset
+    }
+}
+
+type Rejection = (StatusCode, Value);
+
+fn reject(status: StatusCode, code: &str, error: &str) -> Rejection {
+    (status, json!({ "success": false, "code": code, "error": error }))
+}
+
+// 统一处理故障注入：计数、延迟、状态码、截断响应体
+// nonce 为请求携带的随机数，不为 None 时对响应签名（Factory 替身不签名）
+async fn respond<F>(state: &MockState, route: &str, nonce: Option<&str>, handler: F) -> Response
+where
+    F: FnOnce(&mut Inner, &Fault) -> Result<Value, Rejection>,
+{
+    let fault = state.begin(route);
+    if fault.delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(fault.delay_ms)).await;
+    }
+    *state.lock().completed.entry(route.to_string()).or_insert(0) += 1;
+    if let Some(status) = fault.status {
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response =
+            (status, Json(json!({ "success": false, "code": "INJECTED_FAULT", "error": "injected fault" })))
+                .into_response();
+        if let Some(secs) = fault.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+        }
+        return response;
+    }
+
+    let (status, body) = match handler(&mut state.lock(), &fault) {
+        Ok(body) => (StatusCode::OK, body),
+        Err(rejection) => rejection,
+    };
+    // 304 没有响应体，MAC 覆盖状态码和空内容
+    let mut text = if status == StatusCode::NOT_MODIFIED { String::new() } else { body.to_string() };
+    if fault.malformed {
+        text.truncate(text.len() / 2);
+    }
+    let mut builder = Response::builder().status(status).header(header::CONTENT_TYPE, "application/json");
+    if let Some(nonce) = nonce {
+        let server_now = state.now();
+        let timestamp = if fault.stale_mac { server_now - 2 * TIMESTAMP_WINDOW } else { server_now };
+        let mut mac = crypto::generate_response_mac(status.as_u16(), timestamp, nonce, text.as_bytes());
+        if fault.bad_mac {
+            mac = crypto::generate_response_mac(status.as_u16(), timestamp, nonce, b"tampered");
+        }
+        builder = builder
+            .header("X-Response-Timestamp", timestamp)
+            .header("X-Response-Signature", mac);
+    }
+    builder.body(Body::from(text)).unwrap_or_default()
+}
+
+fn verify_timestamp(inner: &Inner, timestamp: i64) -> Result<(), Rejection> {
+    if (inner.now() - timestamp).abs() >= TIMESTAMP_WINDOW {
+        return Err(reject(StatusCode::UNAUTHORIZED, "TIMESTAMP_EXPIRED", "请求已过期"));
+    }
+    Ok(())
+}
+
+// 签名校验所需的原始请求（路径取 nest 之前的完整路径，与客户端签名一致）
+struct Signed {
+    method: Method,
+    path: String,
+    query: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Signed {
+    fn header(&self, name: &str) -> &str {
+        self.headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default()
+    }
+}
+
+impl<S: Send + Sync> FromRequest<S> for Signed {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let (parts, body) = req.into_parts();
+        let uri = parts.extensions.get::<OriginalUri>().map(|u| u.0.clone()).unwrap_or(parts.uri);
+        let body = axum::body::to_bytes(body, 1024 * 1024)
+            .await
+            .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+        Ok(Signed {
+            method: parts.method,
+            path: uri.path().to_string(),
+            query: uri.query().unwrap_or_default().to_string(),
+            headers: parts.headers,
+            body,
+        })
+    }
+}
+
+// 校验 v2 签名；请求未带版本头时返回 false，由调用方按 v1 校验
+fn verify_v2(inner: &mut Inner, req: &Signed) -> Result<bool, Rejection> {
+    match req.header("x-signature-version") {
+        "" if inner.require_v2 => {
+            return Err(reject(StatusCode::UNAUTHORIZED, "SIGNATURE_VERSION_UNSUPPORTED", "请升级客户端"))
+        }
+        "" => return Ok(false),
+        v if v == crypto::SIGNATURE_VERSION => {}
+        _ => return Err(reject(StatusCode::UNAUTHORIZED, "SIGNATURE_VERSION_UNSUPPORTED", "不支持的签名版本")),
+    }
+
+    let timestamp: i64 = req.header("x-timestamp").parse().unwrap_or_default();
+    verify_timestamp(inner, timestamp)?;
+    let nonce = req.header("x-nonce");
+    let signed = crypto::SignedRequest {
+        method: req.method.as_str(),
+        path: &req.path,
+        query: &req.query,
+        body: &req.body,
+        timestamp,
+        nonce,
+        device_id: req.header("x-device-id"),
+    };
+    if nonce.is_empty() || !crypto::verify_signature_v2(&signed, req.header("x-signature")) {
+        return Err(reject(StatusCode::UNAUTHORIZED, "INVALID_SIGNATURE", "签名无效"));
+    }
+
+    // 同一随机数在时间窗口内只能使用一次
+    let now = inner.now();
+    inner.nonces.retain(|_, ts| (now - *ts).abs() < TIMESTAMP_WINDOW);
+    if inner.nonces.insert(nonce.to_string(), timestamp).is_some() {
+        return Err(reject(StatusCode::UNAUTHORIZED, "NONCE_REUSED", "重复的请求"));
+    }
+    Ok(true)
+}
+
+// 校验会话请求，返回会话信息
+// v1 签名内容为 v1_data，为 None 时为 session_token 本身
+fn authenticate(inner: &mut Inner, req: &Signed, v1_data: Option<&str>) -> Result<Session, Rejection> {
+    let session_token = req.header("authorization").strip_prefix("Bearer ").unwrap_or_default();
+    let device_id = req.header("x-device-id");
+
+    if !verify_v2(inner, req)? {
+        let timestamp: i64 = req.header("x-timestamp").parse().unwrap_or_default();
+        verify_timestamp(inner, timestamp)?;
+        let data = v1_data.unwrap_or(session_token);
+        if !crypto::verify_signature(data, timestamp, device_id, req.header("x-signature")) {
+            return Err(reject(StatusCode::UNAUTHORIZED, "INVALID_SIGNATURE", "签名无效"));
+        }
+    }
+
+    let session = inner
+        .sessions
+        .get(session_token)
+        .filter(|s| s.device_id == device_id && s.expires_at > now())
+        .cloned()
+        .ok_or_else(|| reject(StatusCode::UNAUTHORIZED, "SESSION_INVALID", "会话无效或已过期"))?;
+    Ok(session)
+}
+
+#[derive(Debug, Deserialize)]
+struct CodeRequest {
+    code: String,
+    device_id: String,
+    timestamp: i64,
+    signature: String,
+}
+
+// 解析并校验激活码请求（v2 签名在请求头，v1 签名在请求体）
+fn verify_code_request(inner: &mut Inner, req: &Signed) -> Result<CodeRequest, Rejection> {
+    let body: CodeRequest = serde_json::from_slice(&req.body)
+        .map_err(|e| reject(StatusCode::BAD_REQUEST, "INVALID_REQUEST", &e.to_string()))?;
+    if verify_v2(inner, req)? {
+        if req.header("x-device-id") != body.device_id {
+            return Err(reject(StatusCode::UNAUTHORIZED, "INVALID_SIGNATURE", "签名无效"));
+        }
+        return Ok(body);
+    }
+    verify_timestamp(inner, body.timestamp)?;
+    if !crypto::verify_signature(&body.code, body.timestamp, &body.device_id, &body.signature) {
+        return Err(reject(StatusCode::UNAUTHORIZED, "INVALID_SIGNATURE", "签名无效"));
+    }
+    Ok(body)
+}
+
+fn encrypt(fault: &Fault, plaintext: &str) -> Result<(String, String, String), Rejection> {
+    let (data, mut iv, mut tag) = crypto::encrypt_payload(plaintext)
+        .map_err(|e| reject(StatusCode::INTERNAL_SERVER_ERROR, "ENCRYPT_FAILED", &e))?;
+    if fault.bad_iv {
+        iv.truncate(16);
+    }
+    if fault.tampered_tag {
+        // 翻转第一个字节
+        let first = u8::from_str_radix(&tag[..2], 16).unwrap_or(0) ^ 0xFF;
+        tag.replace_range(..2, &format!("{:02x}", first));
+    }
+    Ok((data, iv, tag))
+}
+
+async fn activate(State(state): State<MockState>, signed: Signed) -> Response {
+    respond(&state, "auth/activate", Some(signed.header("x-nonce")), |inner, _| {
+        let req = verify_code_request(inner, &signed)?;
+        let license = inner
+            .fixtures
+            .license(&req.code)
+            .cloned()
+            .ok_or_else(|| reject(StatusCode::OK, "LICENSE_NOT_FOUND", "激活码无效"))?;
+        if license.expires_at.is_some_and(|e| e <= now()) {
+            return Err(reject(StatusCode::OK, "LICENSE_EXPIRED", "激活码已过期"));
+        }
+        match inner.bindings.get(&req.code) {
+            Some(device) if device != &req.device_id => {
+                return Err(reject(StatusCode::OK, "DEVICE_MISMATCH", "激活码已绑定其他设备"));
+            }
+            _ => {}
+        }
+        inner.bindings.insert(req.code.clone(), req.device_id.clone());
+
+        let session_token = uuid::Uuid::new_v4().to_string();
+        let mut expires_at = now() + inner.fixtures.session_ttl;
+        if let Some(license_expiry) = license.expires_at {
+            expires_at = expires_at.min(license_expiry);
+        }
+        inner.sessions.insert(
+            session_token.clone(),
+            Session { code: req.code.clone(), device_id: req.device_id.clone(), expires_at },
+        );
+
+        Ok(json!({
+            "success": true,
+            "session_token": session_token,
+            "expires_at": expires_at,
+            "quota": license.token_ids.len(),
+            "auto_switch": license.auto_switch
+        }))
+    })
+    .await
+}
+
+async fn heartbeat(State(state): State<MockState>, signed: Signed) -> Response {
+    respond(&state, "auth/heartbeat", Some(signed.header("x-nonce")), |inner, _| {
+        let session = authenticate(inner, &signed, Some("heartbeat"))?;
+        Ok(json!({ "valid": true, "expires_at": session.expires_at }))
+    })
+    .await
+}
+
+async fn unbind(State(state): State<MockState>, signed: Signed) -> Response {
+    respond(&state, "auth/unbind", Some(signed.header("x-nonce")), |inner, _| {
+        let req = verify_code_request(inner, &signed)?;
+        if inner.bindings.get(&req.code) != Some(&req.device_id) {
+            return Err(reject(StatusCode::OK, "NOT_BOUND", "该设备未绑定此激活码"));
+        }
+        inner.bindings.remove(&req.code);
+        inner.sessions.retain(|_, s| s.code != req.code);
+        Ok(json!({ "success": true }))
+    })
+    .await
+}
+
+// 条件请求的校验值：ETag 取明文列表的哈希，Last-Modified 取 token 的最近更新时间
+struct Validators {
+    etag: String,
+    last_modified: String,
+}
+
+fn http_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+// If-None-Match 优先；没有时按 If-Modified-Since 判断
+fn not_modified(signed: &Signed, validators: &Validators, updated_at: i64) -> bool {
+    let if_none_match = signed.header("if-none-match");
+    if !if_none_match.is_empty() {
+        return if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == validators.etag
+        });
+    }
+    let if_modified_since = signed.header("if-modified-since");
+    if if_modified_since.is_empty() {
+        return false;
+    }
+    chrono::DateTime::parse_from_rfc2822(&if_modified_since.replace("GMT", "+0000"))
+        .is_ok_and(|since| updated_at <= since.timestamp())
+}
+
+async fn token_list(State(state): State<MockState>, signed: Signed) -> Response {
+    let mut validators = None;
+    let mut response = respond(&state, "tokens", Some(signed.header("x-nonce")), |inner, fault| {
+        let session = authenticate(inner, &signed, None)?;
+        let license = inner.fixtures.license(&session.code).cloned().unwrap_or_else(|| License {
+            code: session.code.clone(),
+            expires_at: None,
+            auto_switch: false,
+            token_ids: Vec::new(),
+        });
+        let tokens: Vec<&Token> = license.token_ids.iter().filter_map(|id| inner.fixtures.token(id)).collect();
+        let updated_at = tokens.iter().map(|t| t.updated_at).max().unwrap_or(0);
+        let plaintext = Value::Array(
+            tokens
+                .iter()
+                .map(|t| {
+                    json!({
+                        "id": t.id,
+                        "email": t.email,
+                        "name": t.name,
+                        "is_valid": t.is_valid,
+                        "quota_used": t.quota_used,
+                        "quota_total": t.quota_total
+                    })
+                })
+                .collect(),
+        )
+        .to_string();
+
+        use sha2::Digest as _;
+        let current = Validators {
+            etag: format!("\"{}\"", hex::encode(&sha2::Sha256::digest(plaintext.as_bytes())[..16])),
+            last_modified: http_date(updated_at),
+        };
+        let unchanged = not_modified(&signed, &current, updated_at);
+        validators = Some(current);
+        if unchanged {
+            *inner.not_modified.entry("tokens".to_string()).or_insert(0) += 1;
+            return Err((StatusCode::NOT_MODIFIED, Value::Null));
+        }
+
+        let (data, iv, tag) = encrypt(fault, &plaintext)?;
+        Ok(json!({ "success": true, "data": data, "iv": iv, "tag": tag }))
+    })
+    .await;
+
+    if let Some(validators) = validators {
+        let headers = response.headers_mut();
+        if let Ok(etag) = validators.etag.parse() {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Ok(last_modified) = validators.last_modified.parse() {
+            headers.insert(header::LAST_MODIFIED, last_modified);
+        }
+    }
+    response
+}
+
+#[derive(Debug, Deserialize)]
+struct ActivateTokenRequest {
+    token_id: String,
+}
+
+// 校验会话是否有权访问该 token
+fn licensed_token(inner: &Inner, session: &Session, token_id: &str) -> Result<Token, Rejection> {
+    let allowed = inner
+        .fixtures
+        .license(&session.code)
+        .is_some_and(|l| l.token_ids.iter().any(|id| id == token_id));
+    inner
+        .fixtures
+        .token(token_id)
+        .filter(|_| allowed)
+        .cloned()
+        .ok_or_else(|| reject(StatusCode::OK, "TOKEN_NOT_FOUND", "账号不存在或无权访问"))
+}
+
+async fn activate_token(State(state): State<MockState>, signed: Signed) -> Response {
+    respond(&state, "tokens/activate", Some(signed.header("x-nonce")), |inner, fault| {
+        let session = authenticate(inner, &signed, None)?;
+        let req: ActivateTokenRequest = serde_json::from_slice(&signed.body)
+            .map_err(|e| reject(StatusCode::BAD_REQUEST, "INVALID_REQUEST", &e.to_string()))?;
+        let token = licensed_token(inner, &session, &req.token_id)?;
+        let (access_token, access_iv, access_tag) = encrypt(fault, &token.access_token)?;
+        let (refresh_token, refresh_iv, refresh_tag) = encrypt(fault, &token.refresh_token)?;
+        Ok(json!({
+            "success": true,
+            "email": token.email,
+            "access_token": access_token,
+            "access_iv": access_iv,
+            "access_tag": access_tag,
+            "refresh_token": refresh_token,
+            "refresh_iv": refresh_iv,
+            "refresh_tag": refresh_tag
+        }))
+    })
+    .await
+}
+
+async fn check_token(
+    State(state): State<MockState>,
+    Path(token_id): Path<String>,
+    signed: Signed,
+) -> Response {
+    respond(&state, "tokens/check", Some(signed.header("x-nonce")), |inner, _| {
+        let session = authenticate(inner, &signed, None)?;
+        let token = licensed_token(inner, &session, &token_id)?;
+        Ok(json!({ "success": true, "updated_at": token.updated_at }))
+    })
+    .await
+}
+
+// 更新包签名私钥（固定种子，仅用于本地调试和测试）
+const UPDATE_SIGNING_SEED: [u8; 32] = [0x42; 32];
+
+fn update_signing_key() -> ed25519_dalek::SigningKey {
+    ed25519_dalek::SigningKey::from_bytes(&UPDATE_SIGNING_SEED)
+}
+
+// mock 签名私钥对应的公钥（base64），客户端 local 环境默认使用
+pub fn update_public_key() -> String {
+    use base64::Engine as _;
+    base64::engine::general_purpose::STANDARD.encode(update_signing_key().verifying_key().as_bytes())
+}
+
+// 用 mock 私钥对更新包签名（base64）
+pub fn sign_update(version: &str, filename: &str, sha256_hex: &str) -> String {
+    use base64::Engine as _;
+    use ed25519_dalek::Signer as _;
+    let message = crypto::update_signing_message(version, filename, sha256_hex);
+    base64::engine::general_purpose::STANDARD.encode(update_signing_key().sign(message.as_bytes()).to_bytes())
+}
+
+// 增量补丁：gzip 压缩的 bsdiff 补丁，把基础版本的文件变为当前版本的文件
+pub fn patch_bytes(old: &[u8], new: &[u8]) -> Vec<u8> {
+    use std::io::Write as _;
+    let mut diff = Vec::new();
+    bsdiff::diff(old, new, &mut diff).expect("生成补丁失败");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&diff).expect("压缩补丁失败");
+    encoder.finish().expect("压缩补丁失败")
+}
+
+fn version_patch(v: &VersionInfo) -> Option<Vec<u8>> {
+    let source = v.patch_from.as_ref()?;
+    let filename = v.filename.as_ref()?;
+    let old = download_bytes(&source.filename, source.size.max(0) as usize);
+    let new = download_bytes(filename, v.size.unwrap_or(0).max(0) as usize);
+    Some(patch_bytes(&old, &new))
+}
+
+// current 为客户端当前版本，与补丁的基础版本一致时返回补丁信息；channel=beta 时优先返回 beta_version
+async fn client_version(
+    State(state): State<MockState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let nonce = headers.get("x-nonce").and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let current = query.get("current").cloned().unwrap_or_default();
+    let beta = query.get("channel").is_some_and(|c| c == "beta");
+    respond(&state, "client/version", Some(nonce), |inner, _| {
+        let fixtures = &inner.fixtures;
+        let version = fixtures.beta_version.as_ref().filter(|_| beta).or(fixtures.version.as_ref());
+        Ok(match version {
+            Some(v) => {
+                let mut v = v.clone();
+                // 未预置摘要和签名时按实际下载内容生成
+                if let Some(filename) = &v.filename {
+                    let sha256 = v.sha256.get_or_insert_with(|| {
+                        use sha2::Digest as _;
+                        let body = download_bytes(filename, v.size.unwrap_or(0).max(0) as usize);
+                        hex::encode(sha2::Sha256::digest(&body))
+                    });
+                    if v.signature.is_none() {
+                        v.signature = Some(sign_update(&v.version, filename, sha256));
+                    }
+                }
+                let mut body = serde_json::to_value(&v).unwrap_or_default();
+                body["hasUpdate"] = json!(true);
+                if let Some(object) = body.as_object_mut() {
+                    object.remove("patchFrom");
+                }
+                if let Some(source) = v.patch_from.as_ref().filter(|s| s.version == current) {
+                    let size = version_patch(&v).map_or(0, |p| p.len());
+                    body["patch"] = json!({
+                        "from": source.version,
+                        "downloadUrl": format!("/client/patch/{}.patch", v.version),
+                        "size": size
+                    });
+                }
+                body
+            }
+            None => json!({ "hasUpdate": false }),
+        })
+    })
+    .await
+}
+
+async fn client_announcements(State(state): State<MockState>, headers: HeaderMap) -> Response {
+    let nonce = headers.get("x-nonce").and_then(|v| v.to_str().ok()).unwrap_or_default();
+    respond(&state, "client/announcements", Some(nonce), |inner, _| {
+        Ok(json!({ "success": true, "data": inner.fixtures.announcements }))
+    })
+    .await
+}
+
+// 已发布的版本（正式版和 beta 版）
+fn published(fixtures: &Fixtures) -> impl Iterator<Item = &VersionInfo> {
+    fixtures.version.iter().chain(fixtures.beta_version.iter())
+}
+
+async fn download_patch(State(state): State<MockState>, Path(filename): Path<String>) -> Response {
+    let fault = state.begin("client/patch");
+    if let Some(status) = fault.status {
+        return StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR).into_response();
+    }
+    let version = published(&state.lock().fixtures).find(|v| format!("{}.patch", v.version) == filename).cloned();
+    match version.as_ref().and_then(version_patch) {
+        Some(mut patch) => {
+            if fault.malformed {
+                patch.truncate(patch.len() / 2);
+            }
+            Response::builder()
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(patch))
+                .unwrap_or_default()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// 下载文件内容：按文件名生成确定性的字节序列，长度取版本信息中的 size
+pub fn download_bytes(filename: &str, size: usize) -> Vec<u8> {
+    let seed = filename.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+    (0..size).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+// 下载文件的 ETag：内容哈希，版本信息变化（如 size）后随之变化
+fn download_etag(body: &[u8]) -> String {
+    use sha2::Digest as _;
+    format!("\"{}\"", hex::encode(&sha2::Sha256::digest(body)[..16]))
+}
+
+// 解析 "bytes=N-"（只支持客户端续传使用的开放区间）
+fn range_start(headers: &HeaderMap) -> Option<usize> {
+    let value = headers.get(header::RANGE)?.to_str().ok()?;
+    value.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()
+}
+
+// 支持 Range / If-Range 续传；If-Range 与当前 ETag 不一致时返回完整文件
+async fn download(State(state): State<MockState>, Path(filename): Path<String>, headers: HeaderMap) -> Response {
+    let fault = state.begin("client/download");
+    if fault.delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(fault.delay_ms)).await;
+    }
+    if let Some(status) = fault.status {
+        return StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR).into_response();
+    }
+    let size = {
+        let inner = state.lock();
+        let fixtures = &inner.fixtures;
+        let size = published(fixtures)
+            .find(|v| v.filename.as_deref() == Some(filename.as_str()))
+            .or(fixtures.version.as_ref())
+            .and_then(|v| v.size);
+        size.unwrap_or(0)
+    };
+    let body = download_bytes(&filename, size.max(0) as usize);
+    let etag = download_etag(&body);
+    let total = body.len();
+
+    let if_range_matches = headers
+        .get(header::IF_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|v| v == etag);
+    let range = range_start(&headers).filter(|_| if_range_matches);
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::ETAG, &etag)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let body = match range {
+        Some(start) if start >= total => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", total))
+                .body(Body::empty())
+                .unwrap_or_default();
+        }
+        Some(start) => {
+            *state.lock().partial.entry("client/download".to_string()).or_insert(0) += 1;
+            builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, total - 1, total));
+            body[start..].to_vec()
+        }
+        None => body,
+    };
+    builder = builder.header(header::CONTENT_LENGTH, body.len());
+
+    match fault.stall_after {
+        // 先发送部分数据，之后不再发送也不关闭连接
+        Some(n) if n < body.len() => {
+            use futures_util::StreamExt as _;
+            let head = futures_util::stream::iter([Ok::<_, std::io::Error>(body[..n].to_vec())]);
+            builder.body(Body::from_stream(head.chain(futures_util::stream::pending()))).unwrap_or_default()
+        }
+        _ => builder.body(Body::from(body)).unwrap_or_default(),
+    }
+}
+
+// Factory 订阅查询接口的替身（按 access_token 找到账号）
+async fn factory_subscription(State(state): State<MockState>, headers: HeaderMap) -> Response {
+    respond(&state, "factory/subscription", None, |inner, _| {
+        let access_token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        let token = inner
+            .fixtures
+            .tokens
+            .iter()
+            .find(|t| t.access_token == access_token)
+            .ok_or_else(|| reject(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "invalid access token"))?;
+        Ok(json!({
+            "premiumUsage": token.quota_used.unwrap_or(0),
+            // 未设置额度时返回 null（模拟上游字段缺失）
+            "premiumLimit": token.quota_total
+        }))
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+struct FaultRequest {
+    #[serde(default = "default_route")]
+    route: String,
+    #[serde(flatten)]
+    fault: Fault,
+}
+
+fn default_route() -> String {
+    "*".to_string()
+}
+
+// 管理接口：运行时注入/清除故障、查看请求统计
+async fn admin_set_fault(State(state): State<MockState>, Json(req): Json<FaultRequest>) -> impl IntoResponse {
+    state.set_fault(&req.route, req.fault);
+    Json(json!({ "success": true }))
+}
+
+async fn admin_clear_faults(State(state): State<MockState>) -> impl IntoResponse {
+    state.clear_faults();
+    Json(json!({ "success": true }))
+}
+
+async fn admin_hits(State(state): State<MockState>) -> impl IntoResponse {
+    Json(json!(state.lock().hits))
+}
+
+#[derive(Debug, Deserialize)]
+struct PushRequest {
+    token_id: String,
+}
+
+async fn admin_push(State(state): State<MockState>, Json(req): Json<PushRequest>) -> impl IntoResponse {
+    let clients = state.push_token_updated(&req.token_id);
+    Json(json!({ "success": true, "clients": clients }))
+}
+
+// ==================== 推送通道 ====================
+// 握手请求与 HTTP 接口相同，使用会话 + v2 签名认证
+// 客户端消息: {"type":"subscribe","token_id":"..."} / {"type":"ping"}
+// 服务器消息: {"type":"token_updated","token_id":"..."} / {"type":"pong"}
+
+async fn push_socket(State(state): State<MockState>, upgrade: WebSocketUpgrade, signed: Signed) -> Response {
+    let fault = state.begin("ws");
+    if let Some(status) = fault.status {
+        return StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR).into_response();
+    }
+    let (session, updates) = {
+        let mut inner = state.lock();
+        match authenticate(&mut inner, &signed, None) {
+            Ok(session) => (session, inner.push.subscribe()),
+            Err((status, body)) => return (status, Json(body)).into_response(),
+        }
+    };
+    upgrade.on_upgrade(move |socket| push_session(state, session, socket, updates))
+}
+
+async fn push_session(
+    state: MockState,
+    session: Session,
+    mut socket: WebSocket,
+    mut updates: tokio::sync::broadcast::Receiver<String>,
+) {
+    let mut subscribed: Option<String> = None;
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let msg: Value = serde_json::from_str(text.as_str()).unwrap_or_default();
+                    match msg["type"].as_str() {
+                        Some("ping") => Some(json!({ "type": "pong" })),
+                        Some("subscribe") => {
+                            let token_id = msg["token_id"].as_str().unwrap_or_default();
+                            let mut inner = state.lock();
+                            // 只能订阅会话所属激活码下的 token
+                            if licensed_token(&inner, &session, token_id).is_ok() {
+                                inner.subscriptions.push(token_id.to_string());
+                                subscribed = Some(token_id.to_string());
+                            }
+                            None
+                        }
+                        _ => None,
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => None,
+            },
+            update = updates.recv() => match update {
+                Ok(token_id) if subscribed.as_deref() == Some(token_id.as_str()) => {
+                    Some(json!({ "type": "token_updated", "token_id": token_id }))
+                }
+                Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => None,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if let Some(reply) = reply {
+            if socket.send(Message::Text(reply.to_string().into())).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+pub fn router(state: MockState) -> Router {
+    let api = Router::new()
+        .route("/auth/activate", post(activate))
+        .route("/auth/heartbeat", post(heartbeat))
+        .route("/auth/unbind", post(unbind))
+        .route("/tokens", get(token_list))
+        .route("/tokens/activate", post(activate_token))
+        .route("/tokens/check/{id}", get(check_token))
+        .route("/client/version", get(client_version))
+        .route("/client/announcements", get(client_announcements))
+        .route("/client/download/{filename}", get(download))
+        .route("/client/patch/{filename}", get(download_patch));
+
+    Router::new()
+        .nest("/api/v1", api)
+        .route("/factory/subscription", post(factory_subscription))
+        .route("/__mock/faults", post(admin_set_fault).delete(admin_clear_faults))
+        .route("/__mock/hits", get(admin_hits))
+        .route("/__mock/push", post(admin_push))
+        .route("/ws", get(push_socket))
+        .layer(axum::middleware::map_response_with_state(state.clone(), date_header))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), request_log))
+        .with_state(state)
+}
+
+// 所有响应的 Date 头都按服务器时钟（含模拟的偏差），客户端据此计算时钟偏差
+async fn date_header(State(state): State<MockState>, mut response: Response) -> Response {
+    if let Ok(value) = http_date(state.now()).parse() {
+        response.headers_mut().insert(header::DATE, value);
+    }
+    response
+}
+
+// 记录请求携带的 X-Request-ID 并在响应中原样返回
+async fn request_log(State(state): State<MockState>, request: Request, next: axum::middleware::Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let request_id = request.headers().get("X-Request-ID").and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    if let Some(id) = &request_id {
+        state.lock().request_ids.push((path.clone(), id.clone()));
+    }
+
+    let mut response = next.run(request).await;
+    if let Some(value) = request_id.as_deref().and_then(|id| id.parse().ok()) {
+        response.headers_mut().insert("X-Request-ID", value);
+    }
+    if state.lock().log_requests {
+        println!("{} {} {} request_id={}", method, path, response.status().as_u16(), request_id.as_deref().unwrap_or("-"));
+    }
+    response
+}
+
+// 运行中的服务器，drop 时自动停止
+pub struct MockServer {
+    addr: SocketAddr,
+    state: MockState,
+    handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl MockServer {
+    // 在随机端口启动（测试用）
+    pub async fn start(fixtures: Fixtures) -> std::io::Result<Self> {
+        Self::bind(SocketAddr::from(([127, 0, 0, 1], 0)), fixtures).await
+    }
+
+    pub async fn bind(addr: SocketAddr, fixtures: Fixtures) -> std::io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = MockState::new(fixtures);
+        let app = router(state.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(MockServer { addr, state, handle: Some(handle) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // 客户端使用的 API 基础地址
+    pub fn api_base(&self) -> String {
+        format!("http://{}/api/v1", self.addr)
+    }
+
+    // 推送通道地址
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}/ws", self.addr)
+    }
+
+    // Factory 订阅查询地址
+    pub fn factory_url(&self) -> String {
+        format!("http://{}/factory/subscription", self.addr)
+    }
+
+    pub fn state(&self) -> &MockState {
+        &self.state
+    }
+
+    // 阻塞直到服务器退出
+    pub async fn wait(mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.await;
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.handle {
+            handle.abort();
+        }
+    }
+}
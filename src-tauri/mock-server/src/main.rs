@@ -0,0 +1,70 @@
+//! ATM Tray Mock Server
+//!
+//! 使用方法: mock-server [--addr 127.0.0.1:3000] [--fixtures fixtures.json]
+//!                       [--fail-status 401] [--delay-ms 3000] [--malformed]
+//!                       [--require-signature-v2] [--clock-offset 600]
+//!
+//! 客户端配合 ATM_SERVER_PROFILE=local 使用
+
+use atm_mock_server::{Fault, Fixtures, MockServer};
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+fn usage() -> ! {
+    eprintln!("用法: mock-server [--addr 127.0.0.1:3000] [--fixtures fixtures.json]");
+    eprintln!("                  [--fail-status <状态码>] [--delay-ms <毫秒>] [--malformed]");
+    eprintln!("                  [--require-signature-v2] [--clock-offset 600]");
+    std::process::exit(1);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let mut fixtures_path: Option<PathBuf> = None;
+    let mut fault = Fault::default();
+    let mut require_v2 = false;
+    let mut clock_offset: i64 = 0;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--addr" => addr = value().parse().unwrap_or_else(|_| usage()),
+            "--fixtures" => fixtures_path = Some(PathBuf::from(value())),
+            "--fail-status" => fault.status = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--delay-ms" => fault.delay_ms = value().parse().unwrap_or_else(|_| usage()),
+            "--malformed" => fault.malformed = true,
+            "--require-signature-v2" => require_v2 = true,
+            "--clock-offset" => clock_offset = value().parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    let fixtures = match fixtures_path {
+        Some(path) => Fixtures::load(&path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => Fixtures::sample(),
+    };
+
+    let server = MockServer::bind(addr, fixtures).await.unwrap_or_else(|e| {
+        eprintln!("监听 {} 失败: {}", addr, e);
+        std::process::exit(1);
+    });
+    if fault.status.is_some() || fault.delay_ms > 0 || fault.malformed {
+        server.state().set_fault("*", fault);
+    }
+    server.state().require_signature_v2(require_v2);
+    server.state().set_clock_offset(clock_offset);
+    server.state().log_requests(true);
+
+    println!("ATM Mock Server");
+    println!("===============");
+    println!("API 地址: {}", server.api_base());
+    println!("故障注入: POST/DELETE http://{}/__mock/faults", server.addr());
+    println!("请求统计: GET http://{}/__mock/hits", server.addr());
+    println!("推送通道: {}（推送更新: POST http://{}/__mock/push {{\"token_id\": \"...\"}}）", server.ws_url(), server.addr());
+    server.wait().await;
+}
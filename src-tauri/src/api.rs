@@ -1,27 +1,221 @@
-use reqwest::Client;
+use reqwest::{Client, ClientBuilder, Method, RequestBuilder};
+use rustls::RootCertStore;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use crate::cancel;
+use crate::clock;
 use crate::crypto;
-use std::time::Duration;
+use crate::endpoints;
+use crate::error::ApiError;
+use crate::metrics;
+use crate::pinning;
+use crate::profile;
+use crate::proxy::{self, ProxyConfig};
+use crate::security;
+use crate::singleflight::Group;
+use crate::subscription::{Subscription, UsageSummary};
+use crate::retry::{self, RetryPolicy};
+use crate::version::UpdateChannel;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 use futures_util::StreamExt;
 use std::io::Write;
 
-// 服务器地址（运行时解密）
+// 本次请求使用的服务器地址（按地址健康状况在主地址和备用地址中选择）
 #[inline(never)]
 pub fn get_api_base() -> String {
-    crypto::get_api_url()
+    endpoints::select(&api_bases())
 }
 
-// 全局复用的 HTTP Client
+// 当前服务器环境的所有地址（主地址在前，生产环境地址运行时解密）
+fn api_bases() -> Vec<String> {
+    #[cfg(test)]
+    if let Some(bases) = TEST_OVERRIDE.with(|o| {
+        o.borrow().as_ref().map(|o| std::iter::once(o.api_base.clone()).chain(o.fallback_bases.clone()).collect())
+    }) {
+        return bases;
+    }
+    profile::active().api_bases()
+}
+
+// Factory 订阅查询地址
+fn get_factory_url() -> String {
+    #[cfg(test)]
+    if let Some(url) = TEST_OVERRIDE.with(|o| o.borrow().as_ref().map(|o| o.factory_url.clone())) {
+        return url;
+    }
+    crypto::get_factory_api_url()
+}
+
+// 出站连接设置：代理、信任根和证书固定值
+// 固定值只用于我们自己的服务器（API 和更新下载），Factory 等第三方接口只做标准证书校验
+#[derive(Debug, Clone)]
+pub(crate) struct Transport {
+    pub proxy: ProxyConfig,
+    pub roots: RootCertStore,
+    pub pins: Vec<String>,
+}
+
+impl Transport {
+    // 已保存的代理设置和当前服务器环境的固定值，使用内置信任根
+    fn load() -> Self {
+        Transport {
+            proxy: proxy::load(),
+            roots: pinning::builtin_roots(),
+            pins: profile::active().spki_pins,
+        }
+    }
+
+    // 相同的代理和信任根，不固定证书
+    fn unpinned(&self) -> Self {
+        Transport { pins: Vec::new(), ..self.clone() }
+    }
+}
+
+fn transport() -> Transport {
+    #[cfg(test)]
+    if let Some(transport) = TEST_OVERRIDE.with(|o| o.borrow().as_ref().map(|o| o.transport.clone())) {
+        return transport;
+    }
+    Transport::load()
+}
+
+// 共享的 HTTP Client：我们的服务器和 Factory 各一个（Client 内部是 Arc，clone 开销很小）
+#[derive(Debug, Clone)]
+pub(crate) struct Clients {
+    api: Client,
+    factory: Client,
+}
+
+impl Clients {
+    pub(crate) fn build(transport: &Transport) -> Self {
+        Clients {
+            api: build_client(transport),
+            factory: build_client(&transport.unpinned()),
+        }
+    }
+}
+
+fn clients() -> Clients {
+    #[cfg(test)]
+    if let Some(clients) = TEST_OVERRIDE.with(|o| o.borrow().as_ref().map(|o| o.clients.clone())) {
+        return clients;
+    }
+    HTTP_CLIENTS.read().map(|c| c.clone()).unwrap_or_else(|_| Clients::build(&Transport::load()))
+}
+
+// 请求我们自己的服务器（证书固定）
+fn http_client() -> Client {
+    clients().api
+}
+
+// 请求 Factory 接口（第三方证书，不固定）
+fn factory_client() -> Client {
+    clients().factory
+}
+
+// 测试时替换服务器地址和出站连接设置（仅对当前线程生效）
+#[cfg(test)]
+pub(crate) struct TestOverride {
+    pub api_base: String,
+    pub fallback_bases: Vec<String>,
+    pub factory_url: String,
+    pub transport: Transport,
+    pub clients: Clients,
+}
+
+#[cfg(test)]
+thread_local! {
+    pub(crate) static TEST_OVERRIDE: std::cell::RefCell<Option<TestOverride>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+// 全局复用的 HTTP Client（代理设置变更后重建）
 lazy_static::lazy_static! {
-    static ref HTTP_CLIENT: Client = Client::builder()
-        .use_rustls_tls()
+    static ref HTTP_CLIENTS: RwLock<Clients> = RwLock::new(Clients::build(&Transport::load()));
+    // 会话 token -> 最近一次获取的解密后 Token 列表（仅保存在内存中）
+    static ref TOKEN_LIST_CACHE: RwLock<HashMap<String, CachedTokenList>> = RwLock::new(HashMap::new());
+    // 定时刷新、推送和手动操作经常重叠：同一会话代中相同会话和 token 的并发请求只发送一次，共享结果
+    static ref TOKEN_LISTS: Group<Vec<TokenInfo>> = Group::new();
+    static ref TOKEN_ACTIVATIONS: Group<(String, String)> = Group::new();
+    static ref TOKEN_CHECKS: Group<i64> = Group::new();
+}
+
+// 条件请求缓存：服务器返回 304 时直接使用，不再下载和解密
+#[derive(Debug, Clone)]
+struct CachedTokenList {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    tokens: Vec<TokenInfo>,
+}
+
+fn build_client(transport: &Transport) -> Client {
+    configure(Client::builder(), transport)
         .timeout(Duration::from_secs(15))
         .connect_timeout(Duration::from_secs(8))
         .pool_max_idle_per_host(5)
         .tcp_keepalive(Duration::from_secs(30))
         .build()
-        .unwrap_or_else(|_| Client::new());
+        .unwrap_or_else(|_| Client::new())
+}
+
+// 代理设置变更后调用，之后的请求使用新设置
+pub fn rebuild_http_client() {
+    let clients = Clients::build(&Transport::load());
+    if let Ok(mut guard) = HTTP_CLIENTS.write() {
+        *guard = clients;
+    }
+}
+
+// 所有出站请求共用的配置：TLS（证书固定值由 transport 决定）+ 代理
+fn configure(builder: ClientBuilder, transport: &Transport) -> ClientBuilder {
+    let builder = with_tls(builder, transport);
+    match transport.proxy.apply(builder) {
+        Ok(builder) => builder,
+        Err(e) => {
+            // 保存时已校验，这里只是兜底
+            #[cfg(debug_assertions)]
+            println!("[Proxy] 代理配置无效，使用系统代理: {}", e);
+            #[cfg(not(debug_assertions))]
+            let _ = e;
+            with_tls(Client::builder(), transport)
+        }
+    }
+}
+
+// 按信任根和证书固定值配置 TLS（固定值在解析服务器环境时已校验）
+fn with_tls(builder: ClientBuilder, transport: &Transport) -> ClientBuilder {
+    match pinning::parse_pins(&transport.pins).and_then(|pins| pinning::tls_config_with_roots(transport.roots.clone(), pins)) {
+        Ok(config) => builder.use_preconfigured_tls(config),
+        Err(e) => {
+            #[cfg(debug_assertions)]
+            println!("[TLS] 证书固定配置无效，使用默认校验: {}", e);
+            #[cfg(not(debug_assertions))]
+            let _ = e;
+            builder.use_rustls_tls()
+        }
+    }
+}
+
+// 记录一次接口调用的结果和总耗时（含重试）
+// 每次调用生成一个请求 ID，所有尝试都通过 X-Request-ID 发送，失败时附加到返回的错误上
+async fn tracked<T, Fut>(endpoint: &str, fut: Fut) -> Result<T, ApiError>
+where
+    Fut: std::future::Future<Output = Result<T, ApiError>>,
+{
+    let request_id = security::generate_request_id();
+    let started = Instant::now();
+    let result = retry::with_request_id(request_id.clone(), fut)
+        .await
+        .map_err(|e| e.with_request_id(&request_id));
+    #[cfg(debug_assertions)]
+    match &result {
+        Ok(_) => println!("[API] {} [{}] 成功，{}ms", endpoint, request_id, started.elapsed().as_millis()),
+        Err(e) => println!("[API] {} [{}] 失败: {} ({:?})，{}ms", endpoint, request_id, e, e.detail(), started.elapsed().as_millis()),
+    }
+    metrics::record(endpoint, started.elapsed(), result.as_ref().err());
+    result
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,6 +233,8 @@ pub struct ActivateResponse {
     pub expires_at: Option<i64>,
     pub quota: Option<i32>,
     pub error: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,      // 服务器错误码
     pub auto_switch: Option<bool>,
 }
 
@@ -49,6 +245,8 @@ pub struct TokenListResponse {
     pub iv: Option<String>,
     pub tag: Option<String>,
     pub error: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,180 +270,328 @@ pub struct ActivateTokenResponse {
     pub refresh_iv: Option<String>,
     pub refresh_tag: Option<String>,
     pub error: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
-pub async fn activate_license(code: &str, device_id: &str) -> Result<ActivateResponse, String> {
-    let client = &*HTTP_CLIENT;
-    let url = format!("{}/auth/activate", get_api_base());
-    
-    // 重试3次
-    for attempt in 1..=3 {
-        let timestamp = chrono::Utc::now().timestamp();
-        let signature = crypto::generate_signature(code, timestamp, device_id);
-        
-        let request = ActivateRequest {
-            code: code.to_string(),
-            device_id: device_id.to_string(),
-            timestamp,
-            signature,
-        };
-        
-        #[cfg(debug_assertions)]
-        println!("[API] 请求 URL: {} (尝试 {}/3)", &url, attempt);
-        
-        match client.post(&url).json(&request).send().await {
-            Ok(response) => {
-                if !response.status().is_success() {
-                    let status = response.status();
-                    let _text = response.text().await.unwrap_or_default();
-                    return Err(format!("服务器错误 ({})", status.as_u16()));
+// 非成功状态码映射为错误（401 视为会话过期）
+fn status_error(status: reqwest::StatusCode) -> ApiError {
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        ApiError::SessionExpired
+    } else {
+        ApiError::Http(status.as_u16())
+    }
+}
+
+// 解密服务器下发的数据，三个字段缺一不可
+fn decrypt_field(
+    data: Option<String>,
+    iv: Option<String>,
+    tag: Option<String>,
+    name: &str,
+) -> Result<String, ApiError> {
+    let data = data.ok_or_else(|| ApiError::Parse(format!("missing {}", name)))?;
+    let iv = iv.ok_or_else(|| ApiError::Parse(format!("missing {} iv", name)))?;
+    let tag = tag.ok_or_else(|| ApiError::Parse(format!("missing {} tag", name)))?;
+    crypto::decrypt_payload(&data, &iv, &tag).map_err(ApiError::Crypto)
+}
+
+// 读取 JSON 响应体（第三方接口，不校验 MAC）
+async fn read_json<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T, ApiError> {
+    let bytes = response.bytes().await?;
+    serde_json::from_slice(&bytes).map_err(|e| ApiError::Parse(e.to_string()))
+}
+
+// 校验响应 MAC 后读取 JSON 响应体
+// nonce 为最后一次尝试发送的随机数，防止用其他请求的响应冒充
+async fn read_verified_json<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+    nonce: &str,
+) -> Result<T, ApiError> {
+    let bytes = read_verified_bytes(response, nonce).await?;
+    serde_json::from_slice(&bytes).map_err(|e| ApiError::Parse(e.to_string()))
+}
+
+// 校验响应 MAC 并返回响应体（304 等空响应体同样需要校验）
+async fn read_verified_bytes(response: reqwest::Response, nonce: &str) -> Result<Vec<u8>, ApiError> {
+    let status = response.status().as_u16();
+    let url = response.url().to_string();
+    let header = |name: &str| {
+        response.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string())
+    };
+    let timestamp = header("X-Response-Timestamp").and_then(|v| v.parse::<i64>().ok());
+    let signature = header("X-Response-Signature");
+    let bytes = response.bytes().await?;
+
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+        return Err(ApiError::ResponseVerification("missing response signature".to_string()));
+    };
+    if !security::verify_timestamp(&url, timestamp) {
+        return Err(ApiError::ResponseVerification("stale response".to_string()));
+    }
+    if !crypto::verify_response_mac(status, timestamp, nonce, &bytes, &signature) {
+        return Err(ApiError::ResponseVerification("response signature mismatch".to_string()));
+    }
+    Ok(bytes.into())
+}
+
+// v2 签名请求头（HTTP 请求和推送通道握手共用）
+pub(crate) fn signature_headers(
+    method: &str,
+    url: &str,
+    body: &[u8],
+    nonce: &str,
+    device_id: &str,
+) -> Vec<(&'static str, String)> {
+    let timestamp = clock::now(url);
+    let (path, query) = match reqwest::Url::parse(url) {
+        Ok(parsed) => (parsed.path().to_string(), parsed.query().unwrap_or_default().to_string()),
+        Err(_) => (String::new(), String::new()),
+    };
+    let signature = crypto::generate_signature_v2(&crypto::SignedRequest {
+        method,
+        path: &path,
+        query: &query,
+        body,
+        timestamp,
+        nonce,
+        device_id,
+    });
+    vec![
+        ("X-Device-ID", device_id.to_string()),
+        ("X-Timestamp", timestamp.to_string()),
+        ("X-Nonce", nonce.to_string()),
+        ("X-Signature-Version", crypto::SIGNATURE_VERSION.to_string()),
+        ("X-Signature", signature),
+    ]
+}
+
+// 构造带 v2 签名的请求
+// 签名覆盖方法、路径、查询参数和最终发送的请求体字节
+// 每次尝试都应使用新的 nonce，服务器会拒绝重复的 nonce
+fn signed_request(
+    client: &Client,
+    method: Method,
+    url: &str,
+    body: Option<Vec<u8>>,
+    nonce: &str,
+    device_id: &str,
+) -> RequestBuilder {
+    let headers = signature_headers(method.as_str(), url, body.as_deref().unwrap_or_default(), nonce, device_id);
+    let builder = headers
+        .into_iter()
+        .fold(client.request(method, url), |builder, (name, value)| builder.header(name, value));
+    match body {
+        Some(body) => builder.header("Content-Type", "application/json").body(body),
+        None => builder,
+    }
+}
+
+// 会话认证请求
+fn session_request(
+    client: &Client,
+    method: Method,
+    url: &str,
+    body: Option<Vec<u8>>,
+    nonce: &str,
+    session_token: &str,
+    device_id: &str,
+) -> RequestBuilder {
+    signed_request(client, method, url, body, nonce, device_id)
+        .header("Authorization", format!("Bearer {}", session_token))
+}
+
+// 激活码请求（激活和解绑共用）
+// 请求体保留 v1 签名字段，迁移期间旧版服务器仍可校验
+fn code_request(client: &Client, url: &str, nonce: &str, code: &str, device_id: &str) -> RequestBuilder {
+    let body = serde_json::to_vec(&signed_code_request(url, code, device_id)).unwrap_or_default();
+    signed_request(client, Method::POST, url, Some(body), nonce, device_id)
+}
+
+fn signed_code_request(url: &str, code: &str, device_id: &str) -> ActivateRequest {
+    let timestamp = clock::now(url);
+    ActivateRequest {
+        code: code.to_string(),
+        device_id: device_id.to_string(),
+        timestamp,
+        signature: crypto::generate_signature(code, timestamp, device_id),
+    }
+}
+
+pub async fn activate_license(code: &str, device_id: &str) -> Result<ActivateResponse, ApiError> {
+    tracked("auth/activate", async move {
+        let client = &http_client();
+    
+        // 同一设备重复激活同一激活码是幂等的，可以安全重试
+        let mut nonce = String::new();
+        let response = retry::send("auth/activate", &RetryPolicy::IDEMPOTENT, || {
+            nonce = crypto::generate_nonce();
+            let url = format!("{}/auth/activate", get_api_base());
+            code_request(client, &url, &nonce, code, device_id)
+        }).await?;
+    
+        if !response.status().is_success() {
+            return Err(ApiError::Http(response.status().as_u16()));
+        }
+        read_verified_json(response, &nonce).await
+    }).await
+}
+
+// scope 为调用者的会话代：会话代变化时请求中止，返回 ApiError::Cancelled
+pub async fn get_token_list(session_token: &str, device_id: &str, scope: &cancel::Scope) -> Result<Vec<TokenInfo>, ApiError> {
+    let (session_token, device_id) = (session_token.to_string(), device_id.to_string());
+    TOKEN_LISTS
+        .run(format!("tokens:{}", session_token), scope, || async move { fetch_token_list(&session_token, &device_id).await })
+        .await
+}
+
+async fn fetch_token_list(session_token: &str, device_id: &str) -> Result<Vec<TokenInfo>, ApiError> {
+    tracked("tokens", async move {
+        let client = &http_client();
+        let cached = cached_token_list(session_token);
+    
+        // 有缓存时带上校验值，列表未变化时服务器返回 304
+        let mut nonce = String::new();
+        let response = retry::send("tokens", &RetryPolicy::IDEMPOTENT, || {
+            nonce = crypto::generate_nonce();
+            let url = format!("{}/tokens", get_api_base());
+            let mut request = session_request(client, Method::GET, &url, None, &nonce, session_token, device_id);
+            if let Some(cached) = &cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag);
                 }
-                return response.json().await.map_err(|_| "数据解析失败".to_string());
-            }
-            Err(e) => {
-                #[cfg(debug_assertions)]
-                println!("[API] 请求失败 (尝试 {}/3): {:?}", attempt, e);
-                
-                if attempt == 3 {
-                    return Err("网络连接失败，请检查网络".to_string());
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
                 }
-                // 等待1秒后重试
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            request
+        }).await?;
+    
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                read_verified_bytes(response, &nonce).await?;
+                return Ok(cached.tokens);
             }
         }
-    }
     
-    Err("网络连接失败".to_string())
-}
-
-pub async fn get_token_list(session_token: &str, device_id: &str) -> Result<Vec<TokenInfo>, String> {
-    let client = &*HTTP_CLIENT;
-    let url = format!("{}/tokens", get_api_base());
-    
-    // 重试3次
-    for attempt in 1..=3 {
-        let timestamp = chrono::Utc::now().timestamp();
-        let signature = crypto::generate_signature(session_token, timestamp, device_id);
-        
-        let result = client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", session_token))
-            .header("X-Device-ID", device_id)
-            .header("X-Timestamp", timestamp.to_string())
-            .header("X-Signature", &signature)
-            .send()
-            .await;
-        
-        match result {
-            Ok(response) => {
-                if !response.status().is_success() {
-                    let status = response.status();
-                    if status.as_u16() == 401 {
-                        return Err("SESSION_EXPIRED".to_string());
-                    }
-                    let _text = response.text().await.unwrap_or_default();
-                    return Err(format!("服务器错误 ({})", status.as_u16()));
-                }
-                
-                let resp: TokenListResponse = response.json().await.map_err(|_| "数据解析失败".to_string())?;
-                
-                if !resp.success {
-                    return Err(resp.error.unwrap_or_else(|| "未知错误".to_string()));
-                }
-                
-                // 解密数据
-                let encrypted_data = resp.data.ok_or("无数据")?;
-                let iv = resp.iv.ok_or("缺少IV")?;
-                let tag = resp.tag.ok_or("缺少Tag")?;
-                
-                let decrypted = crypto::decrypt_payload(&encrypted_data, &iv, &tag)?;
-                let tokens: Vec<TokenInfo> = serde_json::from_str(&decrypted)
-                    .map_err(|_| "数据解析失败".to_string())?;
-                
-                return Ok(tokens);
+        if !response.status().is_success() {
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+                forget_token_list(session_token);
             }
-            Err(_) => {
-                if attempt == 3 {
-                    return Err("网络连接失败".to_string());
-                }
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            return Err(status_error(response.status()));
+        }
+    
+        let header = |name: reqwest::header::HeaderName| {
+            response.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string())
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let resp: TokenListResponse = read_verified_json(response, &nonce).await?;
+    
+        if !resp.success {
+            return Err(ApiError::server(resp.code, resp.error, "未知错误"));
+        }
+    
+        // 解密数据
+        let decrypted = decrypt_field(resp.data, resp.iv, resp.tag, "data")?;
+        let tokens: Vec<TokenInfo> = serde_json::from_str(&decrypted).map_err(|e| ApiError::Parse(e.to_string()))?;
+        if etag.is_some() || last_modified.is_some() {
+            if let Ok(mut cache) = TOKEN_LIST_CACHE.write() {
+                cache.insert(
+                    session_token.to_string(),
+                    CachedTokenList { etag, last_modified, tokens: tokens.clone() },
+                );
             }
         }
+        Ok(tokens)
+    }).await
+}
+
+fn cached_token_list(session_token: &str) -> Option<CachedTokenList> {
+    TOKEN_LIST_CACHE.read().ok()?.get(session_token).cloned()
+}
+
+fn forget_token_list(session_token: &str) {
+    if let Ok(mut cache) = TOKEN_LIST_CACHE.write() {
+        cache.remove(session_token);
     }
-    
-    Err("网络连接失败".to_string())
 }
 
 pub async fn activate_token(
     session_token: &str,
     token_id: &str,
     device_id: &str,
-) -> Result<(String, String), String> {
-    let client = &*HTTP_CLIENT;
-    let timestamp = chrono::Utc::now().timestamp();
-    // 签名格式与 verifySignature 中间件一致
-    let signature = crypto::generate_signature(session_token, timestamp, device_id);
-    
-    let response = client
-        .post(format!("{}/tokens/activate", get_api_base()))
-        .header("Authorization", format!("Bearer {}", session_token))
-        .header("X-Device-ID", device_id)
-        .header("X-Timestamp", timestamp.to_string())
-        .header("X-Signature", &signature)
-        .json(&serde_json::json!({ "token_id": token_id }))
-        .send()
+    scope: &cancel::Scope,
+) -> Result<(String, String), ApiError> {
+    let key = format!("tokens/activate:{}:{}", session_token, token_id);
+    let (session_token, token_id, device_id) = (session_token.to_string(), token_id.to_string(), device_id.to_string());
+    TOKEN_ACTIVATIONS
+        .run(key, scope, || async move { fetch_token_credentials(&session_token, &token_id, &device_id).await })
         .await
-        .map_err(|_| "网络连接失败".to_string())?;
-    
-    if !response.status().is_success() {
-        let status = response.status();
-        if status.as_u16() == 401 {
-            return Err("SESSION_EXPIRED".to_string());
+}
+
+async fn fetch_token_credentials(
+    session_token: &str,
+    token_id: &str,
+    device_id: &str,
+) -> Result<(String, String), ApiError> {
+    tracked("tokens/activate", async move {
+        let client = &http_client();
+        let body = serde_json::json!({ "token_id": token_id }).to_string().into_bytes();
+    
+        // 重复获取同一 token 的凭据不会改变服务器状态，可以重试
+        let mut nonce = String::new();
+        let response = retry::send("tokens/activate", &RetryPolicy::IDEMPOTENT, || {
+            nonce = crypto::generate_nonce();
+            let url = format!("{}/tokens/activate", get_api_base());
+            session_request(client, Method::POST, &url, Some(body.clone()), &nonce, session_token, device_id)
+        }).await?;
+    
+        if !response.status().is_success() {
+            return Err(status_error(response.status()));
         }
-        let _text = response.text().await.unwrap_or_default();
-        return Err(format!("服务器错误 ({})", status.as_u16()));
-    }
     
-    let resp: ActivateTokenResponse = response.json().await.map_err(|_| "数据解析失败".to_string())?;
+        let resp: ActivateTokenResponse = read_verified_json(response, &nonce).await?;
     
-    if !resp.success {
-        return Err(resp.error.unwrap_or_else(|| "未知错误".to_string()));
-    }
+        if !resp.success {
+            return Err(ApiError::server(resp.code, resp.error, "未知错误"));
+        }
     
-    // 解密 access_token
-    let access_encrypted = resp.access_token.ok_or("缺少access_token")?;
-    let access_iv = resp.access_iv.ok_or("缺少access_iv")?;
-    let access_tag = resp.access_tag.ok_or("缺少access_tag")?;
-    let access_token = crypto::decrypt_payload(&access_encrypted, &access_iv, &access_tag)?;
+        // 解密 access_token
+        let access_token = decrypt_field(resp.access_token, resp.access_iv, resp.access_tag, "access_token")?;
     
-    // 解密 refresh_token
-    let refresh_encrypted = resp.refresh_token.ok_or("缺少refresh_token")?;
-    let refresh_iv = resp.refresh_iv.ok_or("缺少refresh_iv")?;
-    let refresh_tag = resp.refresh_tag.ok_or("缺少refresh_tag")?;
-    let refresh_token = crypto::decrypt_payload(&refresh_encrypted, &refresh_iv, &refresh_tag)?;
+        // 解密 refresh_token
+        let refresh_token = decrypt_field(resp.refresh_token, resp.refresh_iv, resp.refresh_tag, "refresh_token")?;
     
-    Ok((access_token, refresh_token))
+        Ok((access_token, refresh_token))
+    }).await
 }
 
-pub async fn get_subscription(access_token: &str) -> Result<Value, String> {
-    let client = &*HTTP_CLIENT;
-    
-    let response = client
-        .post(crypto::get_factory_api_url())
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Type", "application/json")
-        .header("Accept", "*/*")
-        .header("x-factory-client", "web-app")
-        .body("{}")
-        .send()
-        .await
-        .map_err(|_| "网络连接失败".to_string())?;
-    
-    if !response.status().is_success() {
-        return Err("查询失败".to_string());
-    }
+// 查询订阅余额，返回保留全部字段的订阅数据和归一化的余额信息
+pub async fn get_subscription(access_token: &str) -> Result<(Subscription, UsageSummary), ApiError> {
+    tracked("factory/subscription", async move {
+        let client = &factory_client();
+        let url = get_factory_url();
+    
+        let response = retry::send("factory/subscription", &RetryPolicy::IDEMPOTENT, || {
+            client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", access_token))
+                .header("Content-Type", "application/json")
+                .header("Accept", "*/*")
+                .header("x-factory-client", "web-app")
+                .body("{}")
+        }).await?;
+    
+        // 401 只表示 Factory 拒绝了该 token 的访问凭据，不是 ATM 会话过期，不能映射为 SessionExpired
+        if !response.status().is_success() {
+            return Err(ApiError::Http(response.status().as_u16()));
+        }
     
-    response.json().await.map_err(|_| "数据解析失败".to_string())
+        let subscription = Subscription::from_value(read_json(response).await?)?;
+        let summary = subscription.summary()?;
+        Ok((subscription, summary))
+    }).await
 }
 
 #[derive(Debug, Deserialize)]
@@ -254,60 +600,59 @@ pub struct HeartbeatResponse {
     pub expires_at: Option<i64>,
 }
 
-pub async fn heartbeat(session_token: &str, device_id: &str) -> Result<HeartbeatResponse, String> {
-    let client = &*HTTP_CLIENT;
-    let timestamp = chrono::Utc::now().timestamp();
-    let signature = crypto::generate_signature("heartbeat", timestamp, device_id);
-    
-    let response = client
-        .post(format!("{}/auth/heartbeat", get_api_base()))
-        .header("Authorization", format!("Bearer {}", session_token))
-        .header("X-Device-ID", device_id)
-        .header("X-Timestamp", timestamp.to_string())
-        .header("X-Signature", &signature)
-        .send()
-        .await
-        .map_err(|_| "网络连接失败".to_string())?;
-    
-    if !response.status().is_success() {
-        return Ok(HeartbeatResponse { valid: false, expires_at: None });
-    }
+pub async fn heartbeat(session_token: &str, device_id: &str) -> Result<HeartbeatResponse, ApiError> {
+    tracked("auth/heartbeat", async move {
+        let client = &http_client();
+    
+        let mut nonce = String::new();
+        let response = retry::send("auth/heartbeat", &RetryPolicy::BACKGROUND, || {
+            nonce = crypto::generate_nonce();
+            let url = format!("{}/auth/heartbeat", get_api_base());
+            session_request(client, Method::POST, &url, None, &nonce, session_token, device_id)
+        }).await?;
+    
+        if !response.status().is_success() {
+            return Ok(HeartbeatResponse { valid: false, expires_at: None });
+        }
     
-    response.json::<HeartbeatResponse>().await
-        .map_err(|_| "数据解析失败".to_string())
+        read_verified_json(response, &nonce).await
+    }).await
 }
 
 // 解除设备绑定
-pub async fn unbind_device(code: &str, device_id: &str) -> Result<bool, String> {
-    let client = &*HTTP_CLIENT;
-    let timestamp = chrono::Utc::now().timestamp();
-    let signature = crypto::generate_signature(code, timestamp, device_id);
-    
-    let request = ActivateRequest {
-        code: code.to_string(),
-        device_id: device_id.to_string(),
-        timestamp,
-        signature,
-    };
-    
-    let response = client
-        .post(format!("{}/auth/unbind", get_api_base()))
-        .json(&request)
-        .send()
-        .await
-        .map_err(|_| "网络连接失败".to_string())?;
-    
-    if !response.status().is_success() {
-        return Err("请求失败".to_string());
-    }
+pub async fn unbind_device(code: &str, device_id: &str) -> Result<bool, ApiError> {
+    tracked("auth/unbind", async move {
+        let client = &http_client();
+    
+        // 解绑不可盲目重试：只有请求确定未送达服务器时才重发
+        let mut nonce = String::new();
+        let response = retry::send("auth/unbind", &RetryPolicy::NON_IDEMPOTENT, || {
+            nonce = crypto::generate_nonce();
+            let url = format!("{}/auth/unbind", get_api_base());
+            code_request(client, &url, &nonce, code, device_id)
+        }).await?;
+    
+        if !response.status().is_success() {
+            return Err(ApiError::Http(response.status().as_u16()));
+        }
     
-    let resp: serde_json::Value = response.json().await.map_err(|_| "数据解析失败".to_string())?;
+        let resp: Value = read_verified_json(response, &nonce).await?;
     
-    if resp.get("success").and_then(|v| v.as_bool()) == Some(true) {
-        Ok(true)
-    } else {
-        Err(resp.get("error").and_then(|v| v.as_str()).unwrap_or("解绑失败").to_string())
-    }
+        if resp.get("success").and_then(|v| v.as_bool()) == Some(true) {
+            Ok(true)
+        } else {
+            Err(server_error_from(&resp, "解绑失败"))
+        }
+    }).await
+}
+
+// 从通用 JSON 响应中提取服务器错误
+fn server_error_from(resp: &Value, fallback: &str) -> ApiError {
+    ApiError::server(
+        resp.get("code").and_then(|v| v.as_str()).map(|s| s.to_string()),
+        resp.get("error").and_then(|v| v.as_str()).map(|s| s.to_string()),
+        fallback,
+    )
 }
 
 // 检查服务器上 token 的更新时间
@@ -315,35 +660,42 @@ pub async fn check_token_version(
     session_token: &str,
     token_id: &str,
     device_id: &str,
-) -> Result<i64, String> {
-    let client = &*HTTP_CLIENT;
-    let timestamp = chrono::Utc::now().timestamp();
-    let signature = crypto::generate_signature(session_token, timestamp, device_id);
-    
-    let response = client
-        .get(format!("{}/tokens/check/{}", get_api_base(), token_id))
-        .header("Authorization", format!("Bearer {}", session_token))
-        .header("X-Device-ID", device_id)
-        .header("X-Timestamp", timestamp.to_string())
-        .header("X-Signature", &signature)
-        .send()
+    scope: &cancel::Scope,
+) -> Result<i64, ApiError> {
+    let key = format!("tokens/check:{}:{}", session_token, token_id);
+    let (session_token, token_id, device_id) = (session_token.to_string(), token_id.to_string(), device_id.to_string());
+    TOKEN_CHECKS
+        .run(key, scope, || async move { fetch_token_version(&session_token, &token_id, &device_id).await })
         .await
-        .map_err(|_| "网络连接失败".to_string())?;
-    
-    if !response.status().is_success() {
-        if response.status().as_u16() == 401 {
-            return Err("SESSION_EXPIRED".to_string());
+}
+
+async fn fetch_token_version(
+    session_token: &str,
+    token_id: &str,
+    device_id: &str,
+) -> Result<i64, ApiError> {
+    tracked("tokens/check", async move {
+        let client = &http_client();
+    
+        let mut nonce = String::new();
+        let response = retry::send("tokens/check", &RetryPolicy::BACKGROUND, || {
+            nonce = crypto::generate_nonce();
+            let url = format!("{}/tokens/check/{}", get_api_base(), token_id);
+            session_request(client, Method::GET, &url, None, &nonce, session_token, device_id)
+        }).await?;
+    
+        if !response.status().is_success() {
+            return Err(status_error(response.status()));
         }
-        return Err("检查失败".to_string());
-    }
     
-    let resp: Value = response.json().await.map_err(|_| "数据解析失败".to_string())?;
+        let resp: Value = read_verified_json(response, &nonce).await?;
     
-    if resp.get("success").and_then(|v| v.as_bool()) != Some(true) {
-        return Err(resp.get("error").and_then(|v| v.as_str()).unwrap_or("未知错误").to_string());
-    }
+        if resp.get("success").and_then(|v| v.as_bool()) != Some(true) {
+            return Err(server_error_from(&resp, "未知错误"));
+        }
     
-    Ok(resp.get("updated_at").and_then(|v| v.as_i64()).unwrap_or(0))
+        Ok(resp.get("updated_at").and_then(|v| v.as_i64()).unwrap_or(0))
+    }).await
 }
 
 // 检查客户端更新
@@ -359,77 +711,304 @@ pub struct UpdateInfo {
     pub force_update: Option<bool>,
     #[serde(rename = "downloadUrl")]
     pub download_url: Option<String>,
+    // 安装包 SHA-256（hex）
+    pub sha256: Option<String>,
+    // 对 (version, filename, sha256) 的 Ed25519 签名（base64）
+    pub signature: Option<String>,
+    // 从客户端当前版本升级的增量补丁（服务器没有对应补丁时为空）
+    #[serde(default)]
+    pub patch: Option<PatchInfo>,
+}
+
+// 增量补丁：gzip 压缩的 bsdiff 补丁，应用后的结果按 sha256 校验，补丁本身不签名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchInfo {
+    // 补丁的基础版本
+    pub from: String,
+    pub download_url: String,
+    pub size: Option<i64>,
 }
 
-pub async fn check_update() -> Result<UpdateInfo, String> {
-    let client = &*HTTP_CLIENT;
+// current_version 为客户端当前版本，服务器据此决定是否提供增量补丁
+// channel 为更新频道，beta 频道同时返回测试版
+pub async fn check_update(current_version: &str, channel: UpdateChannel) -> Result<UpdateInfo, ApiError> {
+    tracked("client/version", async move {
+        let client = &http_client();
+        let query = [("current", current_version), ("channel", channel.as_str())];
+    
+        // 版本检查无需会话，只携带 nonce 供服务器签名响应
+        // downloadUrl 决定要运行的可执行文件，必须校验响应 MAC
+        let mut nonce = String::new();
+        let response = retry::send("client/version", &RetryPolicy::BACKGROUND, || {
+            nonce = crypto::generate_nonce();
+            client.get(format!("{}/client/version", get_api_base())).query(&query).header("X-Nonce", &nonce)
+        }).await?;
+    
+        if !response.status().is_success() {
+            return Err(ApiError::Http(response.status().as_u16()));
+        }
     
+        read_verified_json(response, &nonce).await
+    }).await
+}
+
+// 服务器公告
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Critical,
+    // 未知级别按普通公告处理，兼容服务器新增的级别（serde 要求放在最后）
+    #[serde(other)]
+    Info,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Announcement {
+    pub id: String,
+    pub severity: Severity,
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    // 生效/失效时间（秒级时间戳），None 表示不限
+    #[serde(default)]
+    pub starts_at: Option<i64>,
+    #[serde(default)]
+    pub ends_at: Option<i64>,
+    // 适用的客户端版本范围（含边界），None 表示不限
+    #[serde(default)]
+    pub min_version: Option<String>,
+    #[serde(default)]
+    pub max_version: Option<String>,
+}
+
+// 获取公告列表（不过滤，由调用方按时间、版本和已关闭记录过滤）
+pub async fn get_announcements() -> Result<Vec<Announcement>, ApiError> {
+    tracked("client/announcements", async move {
+        let client = &http_client();
+
+        let mut nonce = String::new();
+        let response = retry::send("client/announcements", &RetryPolicy::BACKGROUND, || {
+            nonce = crypto::generate_nonce();
+            client.get(format!("{}/client/announcements", get_api_base())).header("X-Nonce", &nonce)
+        }).await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::Http(response.status().as_u16()));
+        }
+
+        let resp: Value = read_verified_json(response, &nonce).await?;
+        if resp.get("success").and_then(|v| v.as_bool()) != Some(true) {
+            return Err(server_error_from(&resp, "获取公告失败"));
+        }
+
+        serde_json::from_value(resp.get("data").cloned().unwrap_or_else(|| Value::Array(Vec::new())))
+            .map_err(|e| ApiError::Parse(e.to_string()))
+    }).await
+}
+
+// 通过指定代理测试与服务器的连接，返回状态码和耗时（毫秒）
+// 收到任何 HTTP 响应即说明代理可用（407 表示代理认证失败）
+pub async fn test_connection(proxy: &ProxyConfig) -> Result<(u16, u128), ApiError> {
+    proxy.validate().map_err(ApiError::Network)?;
+    let client = configure(Client::builder(), &Transport { proxy: proxy.clone(), ..transport() })
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let url = format!("{}/client/version", get_api_base());
+
+    let started = Instant::now();
     let response = client
-        .get(format!("{}/client/version", get_api_base()))
+        .get(&url)
+        .header("X-Nonce", crypto::generate_nonce())
+        .header(retry::REQUEST_ID_HEADER, security::generate_request_id())
+        .timeout(Duration::from_secs(10))
         .send()
-        .await
-        .map_err(|_| "网络连接失败".to_string())?;
-    
-    if !response.status().is_success() {
-        return Err("检查更新失败".to_string());
+        .await?;
+    let status = response.status();
+    if status == reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+        return Err(ApiError::Http(status.as_u16()));
     }
-    
-    response.json::<UpdateInfo>().await
-        .map_err(|_| "数据解析失败".to_string())
+    Ok((status.as_u16(), started.elapsed().as_millis()))
+}
+
+// 断点续传的进度记录，与 .part 文件一起保存
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct PartialDownload {
+    url: String,
+    // 服务器返回的 ETag（续传时作为 If-Range）
+    etag: Option<String>,
+    // 文件总大小
+    total: Option<u64>,
+}
+
+fn part_paths(save_path: &std::path::Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let mut part = save_path.as_os_str().to_owned();
+    part.push(".part");
+    let mut meta = part.clone();
+    meta.push(".json");
+    (part.into(), meta.into())
+}
+
+// 读取可续传的进度：地址一致且 .part 文件存在时返回已下载字节数
+fn load_partial(part: &std::path::Path, meta: &std::path::Path, url: &str) -> Option<(PartialDownload, u64)> {
+    let partial: PartialDownload = serde_json::from_slice(&std::fs::read(meta).ok()?).ok()?;
+    let len = std::fs::metadata(part).ok()?.len();
+    (partial.url == url && partial.total.is_none_or(|total| len <= total)).then_some((partial, len))
+}
+
+fn discard_partial(part: &std::path::Path, meta: &std::path::Path) {
+    let _ = std::fs::remove_file(part);
+    let _ = std::fs::remove_file(meta);
+}
+
+// 解析 Content-Range: bytes start-end/total
+fn content_range(response: &reqwest::Response) -> Option<(u64, Option<u64>)> {
+    let value = response.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.parse().ok()?;
+    Some((start, total.parse().ok()))
 }
 
+// 下载更新文件：先写入 <save_path>.part，完成后重命名
+// 中断后再次调用（或自动重试）时用 Range 从断点继续，ETag 或文件大小变化时重新下载
 pub async fn download_update<F>(
     download_url: &str, 
     save_path: &std::path::Path,
     progress_callback: F,
-) -> Result<(), String> 
+) -> Result<(), ApiError> 
 where
     F: Fn(u64, u64) + Send + 'static,
 {
-    // 为下载创建专用客户端（无总超时，只有连接超时）
-    let client = Client::builder()
-        .use_rustls_tls()
+    tracked("client/download", async move {
+        let client = download_client(&transport())?;
+    
+        // 构建完整 URL
+        let full_url = if download_url.starts_with("http") {
+            download_url.to_string()
+        } else {
+            format!("{}{}", get_api_base(), download_url)
+        };
+
+        let policy = &RetryPolicy::DOWNLOAD;
+        let started = Instant::now();
+        let mut attempt: u32 = 0;
+        loop {
+            attempt += 1;
+            match download_once(&client, &full_url, save_path, &progress_callback).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if !retry::pause("client/download", policy, attempt, started, &e).await {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }).await
+}
+
+// 下载专用客户端（无总超时，只有连接超时），证书固定和代理与共享客户端一致
+fn download_client(transport: &Transport) -> Result<Client, ApiError> {
+    Ok(configure(Client::builder(), transport)
         .connect_timeout(Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("创建下载客户端失败: {}", e))?;
-    
-    // 构建完整 URL
-    let full_url = if download_url.starts_with("http") {
-        download_url.to_string()
-    } else if download_url.starts_with("/client/") {
-        format!("{}{}", get_api_base(), download_url)
-    } else {
-        format!("{}{}", get_api_base(), download_url)
-    };
-    
-    let response = client
-        .get(&full_url)
-        .send()
+        .build()?)
+}
+
+// 一次下载尝试：有可用的 .part 文件时续传
+async fn download_once<F>(
+    client: &Client,
+    url: &str,
+    save_path: &std::path::Path,
+    progress_callback: &F,
+) -> Result<(), ApiError>
+where
+    F: Fn(u64, u64),
+{
+    let (part, meta) = part_paths(save_path);
+    let resume = load_partial(&part, &meta, url).filter(|(_, len)| *len > 0);
+    if resume.is_none() {
+        discard_partial(&part, &meta);
+    }
+
+    // 数据停滞超时，同时作为等待响应头的超时
+    let stall_timeout = retry::scaled(RetryPolicy::DOWNLOAD.attempt_timeout);
+    let mut request = retry::tag(client.get(url));
+    if let Some((partial, len)) = &resume {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", len));
+        // 只有强 ETag 可以用于 If-Range，否则依靠 Content-Range 中的总大小校验
+        if let Some(etag) = partial.etag.as_ref().filter(|e| !e.starts_with("W/")) {
+            request = request.header(reqwest::header::IF_RANGE, etag);
+        }
+    }
+    let response = tokio::time::timeout(stall_timeout, request.send())
         .await
-        .map_err(|e| format!("下载失败: {}", e))?;
-    
-    if !response.status().is_success() {
-        return Err("下载失败: 服务器错误".to_string());
+        .map_err(|_| ApiError::Timeout)??;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // 断点超出文件大小（服务器文件已变化），下次从头下载
+        discard_partial(&part, &meta);
+        return Err(ApiError::Network("续传位置无效，重新下载".to_string()));
     }
-    
-    // 获取文件总大小
-    let total_size = response.content_length().unwrap_or(0);
-    
-    // 创建文件
-    let mut file = std::fs::File::create(save_path)
-        .map_err(|e| format!("创建文件失败: {}", e))?;
-    
-    // 流式下载
-    let mut downloaded: u64 = 0;
+    if !status.is_success() {
+        return Err(ApiError::Http(status.as_u16()));
+    }
+
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    let (mut downloaded, total) = match (&resume, status) {
+        (Some((partial, len)), reqwest::StatusCode::PARTIAL_CONTENT) => {
+            let (start, total) = content_range(&response).unwrap_or((u64::MAX, None));
+            let etag_changed = partial.etag.is_some() && etag.is_some() && partial.etag != etag;
+            let total_changed = partial.total.is_some() && total.is_some() && partial.total != total;
+            if start != *len || etag_changed || total_changed {
+                discard_partial(&part, &meta);
+                return Err(ApiError::Network("服务器文件已变化，重新下载".to_string()));
+            }
+            (*len, total.or(partial.total))
+        }
+        // 服务器返回完整文件（不支持 Range 或 If-Range 不匹配），从头写入
+        _ => (0, response.content_length()),
+    };
+
+    let record = PartialDownload { url: url.to_string(), etag, total };
+    std::fs::write(&meta, serde_json::to_vec(&record).unwrap_or_default())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(downloaded > 0)
+        .truncate(downloaded == 0)
+        .open(&part)?;
+
+    let total_size = total.unwrap_or(0);
+    progress_callback(downloaded, total_size);
+
+    // 流式下载，超过 stall_timeout 没有收到数据视为连接卡住
     let mut stream = response.bytes_stream();
-    
-    while let Some(chunk_result) = stream.next().await {
-        let chunk = chunk_result.map_err(|e| format!("下载中断: {}", e))?;
-        file.write_all(&chunk).map_err(|e| format!("写入失败: {}", e))?;
+    while let Some(chunk_result) = tokio::time::timeout(stall_timeout, stream.next())
+        .await
+        .map_err(|_| ApiError::Timeout)?
+    {
+        let chunk = chunk_result?;
+        file.write_all(&chunk)?;
         downloaded += chunk.len() as u64;
         progress_callback(downloaded, total_size);
     }
-    
-    file.flush().map_err(|e| format!("保存失败: {}", e))?;
+    file.flush()?;
+    drop(file);
+
+    if total.is_some_and(|total| downloaded != total) {
+        // 连接提前结束，保留 .part 供下次续传
+        return Err(ApiError::Network(format!("下载不完整: {}/{}", downloaded, total_size)));
+    }
+    std::fs::rename(&part, save_path)?;
+    let _ = std::fs::remove_file(&meta);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
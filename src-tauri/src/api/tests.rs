@@ -0,0 +1,1483 @@
+// api.rs 集成测试：每个用例启动独立的进程内 mock 服务器
+// 重试等待时间在测试构建下按 retry::TIME_SCALE 缩短
+
+use super::*;
+use atm_mock_server::{Fault, Fixtures, MockServer, PatchSource, VersionInfo};
+use atm_mock_server::Announcement as MockAnnouncement;
+use crate::version::UpdateChannel;
+
+const DEVICE_ID: &str = "test-device";
+const LICENSE: &str = "TEST-AAAA-BBBB-CCCC";
+
+// 启动 mock 服务器，并让当前线程的 API 请求指向它
+async fn setup() -> MockServer {
+    let server = MockServer::start(Fixtures::sample()).await.expect("启动 mock 服务器失败");
+    // 直连，不受运行环境的代理环境变量影响
+    let transport = Transport {
+        proxy: ProxyConfig { mode: proxy::ProxyMode::None, ..Default::default() },
+        roots: pinning::builtin_roots(),
+        pins: Vec::new(),
+    };
+    TEST_OVERRIDE.with(|o| {
+        *o.borrow_mut() = Some(TestOverride {
+            api_base: server.api_base(),
+            fallback_bases: Vec::new(),
+            factory_url: server.factory_url(),
+            clients: Clients::build(&transport),
+            transport,
+        })
+    });
+    server
+}
+
+// 修改当前线程的出站连接设置，共享客户端随之重建
+fn use_transport(update: impl FnOnce(&mut Transport)) {
+    TEST_OVERRIDE.with(|o| {
+        let mut o = o.borrow_mut();
+        let o = o.as_mut().unwrap();
+        update(&mut o.transport);
+        o.clients = Clients::build(&o.transport);
+    });
+}
+
+fn fault(status: u16) -> Fault {
+    Fault { status: Some(status), ..Default::default() }
+}
+
+fn session(server: &MockServer) -> String {
+    server.state().issue_session(LICENSE, DEVICE_ID)
+}
+
+#[tokio::test]
+async fn activate_license_success() {
+    let server = setup().await;
+    let resp = activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    assert!(resp.success);
+    assert!(resp.session_token.is_some());
+    assert_eq!(resp.quota, Some(2));
+    assert_eq!(resp.auto_switch, Some(false));
+    assert_eq!(server.state().hits("auth/activate"), 1);
+}
+
+#[tokio::test]
+async fn activate_license_server_error_code() {
+    let _server = setup().await;
+    let resp = activate_license("NOPE-0000-0000-0000", DEVICE_ID).await.unwrap();
+    assert!(!resp.success);
+    assert_eq!(resp.code.as_deref(), Some("LICENSE_NOT_FOUND"));
+}
+
+#[tokio::test]
+async fn activate_license_retries_5xx_until_exhausted() {
+    let server = setup().await;
+    server.state().set_fault("auth/activate", fault(500));
+    let err = activate_license(LICENSE, DEVICE_ID).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Http(500)), "{:?}", err);
+    assert_eq!(server.state().hits("auth/activate"), RetryPolicy::IDEMPOTENT.max_attempts);
+}
+
+#[tokio::test]
+async fn request_id_is_shared_by_retries_and_returned_with_error() {
+    let server = setup().await;
+    server.state().set_fault("auth/activate", fault(500));
+    let err = activate_license(LICENSE, DEVICE_ID).await.unwrap_err();
+    let request_id = err.request_id().expect("错误应附带请求 ID").to_string();
+
+    // 所有重试使用同一个请求 ID
+    let sent = server.state().request_ids("/auth/activate");
+    assert_eq!(sent.len() as u32, RetryPolicy::IDEMPOTENT.max_attempts);
+    assert!(sent.iter().all(|id| *id == request_id), "{:?}", sent);
+
+    // 返回给前端的错误中包含请求 ID
+    let response = err.to_response();
+    assert_eq!(response["errorCode"], "HTTP_ERROR");
+    assert_eq!(response["requestId"], request_id.as_str());
+
+    // 每次接口调用生成新的请求 ID
+    server.state().clear_faults();
+    activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    let sent = server.state().request_ids("/auth/activate");
+    assert_eq!(sent.len() as u32, RetryPolicy::IDEMPOTENT.max_attempts + 1);
+    assert_ne!(sent.last(), Some(&request_id));
+}
+
+#[tokio::test]
+async fn activate_license_recovers_after_transient_failure() {
+    let server = setup().await;
+    server.state().set_fault("auth/activate", Fault { times: Some(2), ..fault(502) });
+    let resp = activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    assert!(resp.success);
+    assert_eq!(server.state().hits("auth/activate"), 3);
+}
+
+#[tokio::test]
+async fn activate_license_truncated_json() {
+    let server = setup().await;
+    server.state().set_fault("auth/activate", Fault { malformed: true, ..Default::default() });
+    let err = activate_license(LICENSE, DEVICE_ID).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Parse(_)), "{:?}", err);
+    assert_eq!(server.state().hits("auth/activate"), 1);
+}
+
+#[tokio::test]
+async fn get_token_list_success() {
+    let server = setup().await;
+    let tokens = get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    let ids: Vec<&str> = tokens.iter().map(|t| t.id.as_str()).collect();
+    assert_eq!(ids, ["token-1", "token-2"]);
+    assert_eq!(tokens[1].quota_used, Some(640));
+}
+
+#[tokio::test]
+async fn get_token_list_invalid_session_is_not_retried() {
+    let server = setup().await;
+    let err = get_token_list("bogus-session", DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(err.is_session_expired(), "{:?}", err);
+    assert_eq!(server.state().hits("tokens"), 1);
+}
+
+#[tokio::test]
+async fn get_token_list_injected_401() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", fault(401));
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(err.is_session_expired(), "{:?}", err);
+    assert_eq!(server.state().hits("tokens"), 1);
+}
+
+#[tokio::test]
+async fn get_token_list_bad_iv() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", Fault { bad_iv: true, ..Default::default() });
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Crypto(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn get_token_list_tampered_tag() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", Fault { tampered_tag: true, ..Default::default() });
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Crypto(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn get_token_list_timeout() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", Fault { delay_ms: 2000, ..Default::default() });
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Timeout), "{:?}", err);
+    // 单次超时后总时限只够再试一到两次
+    let hits = server.state().hits("tokens");
+    assert!((2..=3).contains(&hits), "hits = {}", hits);
+}
+
+#[tokio::test]
+async fn get_token_list_honors_short_retry_after() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", Fault { retry_after: Some(1), times: Some(1), ..fault(429) });
+    let tokens = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(server.state().hits("tokens"), 2);
+}
+
+#[tokio::test]
+async fn get_token_list_gives_up_on_long_retry_after() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", Fault { retry_after: Some(3600), ..fault(503) });
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Http(503)), "{:?}", err);
+    assert_eq!(server.state().hits("tokens"), 1);
+}
+
+#[tokio::test]
+async fn activate_token_success() {
+    let server = setup().await;
+    let (access, refresh) = activate_token(&session(&server), "token-2", DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(access, "access-token-2");
+    assert_eq!(refresh, "refresh-token-2");
+}
+
+#[tokio::test]
+async fn activate_token_not_licensed() {
+    let server = setup().await;
+    let err = activate_token(&session(&server), "token-3", DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    match err.kind() {
+        ApiError::Server { code, .. } => assert_eq!(code.as_deref(), Some("TOKEN_NOT_FOUND")),
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn activate_token_tampered_tag() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens/activate", Fault { tampered_tag: true, ..Default::default() });
+    let err = activate_token(&token, "token-1", DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Crypto(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn get_subscription_success() {
+    let _server = setup().await;
+    let (data, summary) = get_subscription("access-token-1").await.unwrap();
+    assert_eq!(data.premium_usage, Some(120));
+    assert_eq!(summary.used, 120);
+    assert_eq!(summary.limit, 1000);
+    assert_eq!(summary.remaining, 880);
+    assert!(!summary.over_limit);
+}
+
+#[tokio::test]
+async fn get_subscription_missing_limit_is_parse_error() {
+    let server = setup().await;
+    server.state().with_fixtures(|f| f.tokens[0].quota_total = None);
+    let err = get_subscription("access-token-1").await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Parse(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn get_subscription_unauthorized() {
+    let server = setup().await;
+    let err = get_subscription("invalid").await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Http(401)), "{:?}", err);
+    assert!(!err.is_session_expired());
+    assert_eq!(server.state().hits("factory/subscription"), 1);
+}
+
+#[tokio::test]
+async fn heartbeat_success() {
+    let server = setup().await;
+    let resp = heartbeat(&session(&server), DEVICE_ID).await.unwrap();
+    assert!(resp.valid);
+    assert!(resp.expires_at.is_some());
+}
+
+#[tokio::test]
+async fn heartbeat_failure_reports_invalid() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("auth/heartbeat", fault(500));
+    let resp = heartbeat(&token, DEVICE_ID).await.unwrap();
+    assert!(!resp.valid);
+    assert_eq!(server.state().hits("auth/heartbeat"), RetryPolicy::BACKGROUND.max_attempts);
+}
+
+#[tokio::test]
+async fn unbind_device_success() {
+    let server = setup().await;
+    let token = session(&server);
+    assert!(unbind_device(LICENSE, DEVICE_ID).await.unwrap());
+    // 解绑后原会话失效
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(err.is_session_expired(), "{:?}", err);
+}
+
+#[tokio::test]
+async fn unbind_device_not_bound() {
+    let _server = setup().await;
+    let err = unbind_device(LICENSE, DEVICE_ID).await.unwrap_err();
+    match err.kind() {
+        ApiError::Server { code, .. } => assert_eq!(code.as_deref(), Some("NOT_BOUND")),
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unbind_device_5xx_is_not_retried() {
+    let server = setup().await;
+    session(&server);
+    server.state().set_fault("auth/unbind", fault(503));
+    let err = unbind_device(LICENSE, DEVICE_ID).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Http(503)), "{:?}", err);
+    assert_eq!(server.state().hits("auth/unbind"), 1);
+}
+
+#[tokio::test]
+async fn check_token_version_success() {
+    let server = setup().await;
+    let updated_at = check_token_version(&session(&server), "token-1", DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(updated_at, 1_700_000_000);
+}
+
+#[tokio::test]
+async fn check_token_version_truncated_json() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens/check", Fault { malformed: true, ..Default::default() });
+    let err = check_token_version(&token, "token-1", DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Parse(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn concurrent_token_checks_share_one_request() {
+    let server = setup().await;
+    let token = session(&server);
+    let scope = cancel::session_scope();
+    server.state().set_fault("tokens/check", Fault { delay_ms: 100, ..Default::default() });
+    let (a, b, c) = tokio::join!(
+        check_token_version(&token, "token-1", DEVICE_ID, &scope),
+        check_token_version(&token, "token-1", DEVICE_ID, &scope),
+        check_token_version(&token, "token-1", DEVICE_ID, &scope),
+    );
+    assert_eq!((a.unwrap(), b.unwrap(), c.unwrap()), (1_700_000_000, 1_700_000_000, 1_700_000_000));
+    assert_eq!(server.state().hits("tokens/check"), 1);
+
+    // 不同 token 分别请求，完成后的调用重新请求
+    let (a, b) = tokio::join!(
+        check_token_version(&token, "token-1", DEVICE_ID, &scope),
+        check_token_version(&token, "token-2", DEVICE_ID, &scope),
+    );
+    assert!(a.is_ok() && b.is_ok());
+    assert_eq!(server.state().hits("tokens/check"), 3);
+}
+
+#[tokio::test]
+async fn concurrent_token_activations_share_result_and_error() {
+    let server = setup().await;
+    let token = session(&server);
+    let scope = cancel::session_scope();
+    server.state().set_fault("tokens/activate", Fault { delay_ms: 100, ..Default::default() });
+    let (a, b) = tokio::join!(
+        activate_token(&token, "token-2", DEVICE_ID, &scope),
+        activate_token(&token, "token-2", DEVICE_ID, &scope),
+    );
+    assert_eq!(a.unwrap(), ("access-token-2".to_string(), "refresh-token-2".to_string()));
+    assert_eq!(b.unwrap(), ("access-token-2".to_string(), "refresh-token-2".to_string()));
+    assert_eq!(server.state().hits("tokens/activate"), 1);
+
+    // 失败时所有调用者收到同一个错误（请求 ID 相同）
+    server.state().reset_hits();
+    let (a, b) = tokio::join!(
+        activate_token(&token, "token-3", DEVICE_ID, &scope),
+        activate_token(&token, "token-3", DEVICE_ID, &scope),
+    );
+    let (a, b) = (a.unwrap_err(), b.unwrap_err());
+    assert_eq!(a.code(), "SERVER_ERROR");
+    assert_eq!(a.request_id(), b.request_id());
+    assert_eq!(server.state().hits("tokens/activate"), 1);
+}
+
+#[tokio::test]
+async fn generation_change_aborts_shared_request() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens/check", Fault { delay_ms: 300, ..Default::default() });
+    // 局部的会话代，不影响并行运行的其他用例
+    let generation = cancel::Generation::new();
+    let scope = generation.scope();
+    let logout = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        generation.bump();
+    };
+    let (result, ()) = tokio::join!(check_token_version(&token, "token-1", DEVICE_ID, &scope), logout);
+    assert!(matches!(result, Err(ApiError::Cancelled)), "{:?}", result);
+
+    // 请求随执行一起中止：服务器收到了请求，但连接在响应前断开，处理没有完成
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(server.state().hits("tokens/check"), 1);
+    assert_eq!(server.state().completed("tokens/check"), 0);
+
+    // 新的会话代重新请求，不等待旧的执行
+    server.state().clear_faults();
+    assert!(check_token_version(&token, "token-1", DEVICE_ID, &generation.scope()).await.is_ok());
+    assert_eq!(server.state().completed("tokens/check"), 1);
+}
+
+// 添加备用地址（另一个 mock 服务器）
+async fn with_secondary() -> MockServer {
+    let secondary = MockServer::start(Fixtures::sample()).await.expect("启动 mock 服务器失败");
+    TEST_OVERRIDE.with(|o| o.borrow_mut().as_mut().unwrap().fallback_bases = vec![secondary.api_base()]);
+    secondary
+}
+
+#[tokio::test]
+async fn fails_over_to_secondary_and_returns_to_recovered_primary() {
+    let primary = setup().await;
+    let secondary = with_secondary().await;
+    primary.state().set_fault("auth/activate", fault(502));
+
+    // 主地址连续失败后立即改用备用地址，签名按备用地址重新生成
+    let resp = activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    assert!(resp.success);
+    assert_eq!(primary.state().hits("auth/activate"), 2);
+    assert_eq!(secondary.state().hits("auth/activate"), 1);
+    assert_eq!(endpoints::snapshot(&api_bases(), true)["selected"], 1);
+
+    // 暂停期间直接使用备用地址
+    primary.state().clear_faults();
+    activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    assert_eq!(primary.state().hits("auth/activate"), 2);
+    assert_eq!(secondary.state().hits("auth/activate"), 2);
+
+    // 暂停结束后重新使用主地址
+    tokio::time::sleep(retry::scaled(endpoints::COOLDOWN) + Duration::from_millis(50)).await;
+    assert_eq!(endpoints::snapshot(&api_bases(), true)["selected"], 0);
+    activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    assert_eq!(primary.state().hits("auth/activate"), 3);
+    assert_eq!(secondary.state().hits("auth/activate"), 2);
+}
+
+#[tokio::test]
+async fn unreachable_primary_fails_over() {
+    let secondary = setup().await;
+    // 已关闭的端口：连接被拒绝
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    TEST_OVERRIDE.with(|o| {
+        let o = &mut *o.borrow_mut();
+        let o = o.as_mut().unwrap();
+        o.fallback_bases = vec![o.api_base.clone()];
+        o.api_base = format!("http://{}/api/v1", closed);
+    });
+
+    let info = check_update("2.2.1", UpdateChannel::Stable).await.unwrap();
+    assert!(!info.has_update);
+    assert_eq!(secondary.state().hits("client/version"), 1);
+    let snapshot = endpoints::snapshot(&api_bases(), true);
+    assert_eq!(snapshot["selected"], 1);
+    assert_eq!(snapshot["endpoints"][0]["healthy"], false);
+    assert_eq!(snapshot["endpoints"][1]["url"], secondary.api_base().as_str());
+}
+
+#[tokio::test]
+async fn signed_requests_compensate_clock_skew() {
+    let server = setup().await;
+    server.state().set_clock_offset(600);
+
+    // 首次请求的时间戳超出服务器窗口，按 401 响应的 Date 校正后重新签名重试
+    let resp = activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    assert!(resp.success);
+    assert_eq!(server.state().hits("auth/activate"), 2);
+    let skew = crate::clock::skew(&server.api_base());
+    assert!((skew.offset_secs - 600).abs() <= 2, "{:?}", skew);
+    assert_eq!(skew.resyncs, 1);
+
+    // 之后的请求直接使用校正后的时间戳，响应 MAC 的时效检查同样按服务器时间
+    let token = session(&server);
+    get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(server.state().hits("tokens"), 1);
+    assert_eq!(crate::clock::skew(&server.api_base()).resyncs, 1);
+}
+
+#[tokio::test]
+async fn session_401_is_not_retried_as_clock_skew() {
+    let server = setup().await;
+    let err = get_token_list("expired-session", DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(err.is_session_expired(), "{:?}", err);
+    assert_eq!(server.state().hits("tokens"), 1);
+    assert_eq!(crate::clock::skew(&server.api_base()).resyncs, 0);
+}
+
+#[tokio::test]
+async fn third_party_date_does_not_affect_clock() {
+    let _server = setup().await;
+    // Factory 的时钟与我们的服务器无关，401 也不按时钟偏差重新签名
+    let factory = MockServer::start(Fixtures::sample()).await.expect("启动 mock 服务器失败");
+    factory.state().set_clock_offset(600);
+    factory.state().set_fault("factory/subscription", fault(401));
+    TEST_OVERRIDE.with(|o| o.borrow_mut().as_mut().unwrap().factory_url = factory.factory_url());
+
+    assert!(get_subscription("access-token-1").await.is_err());
+    assert_eq!(factory.state().hits("factory/subscription"), 1);
+    let skew = crate::clock::skew(&factory.factory_url());
+    assert!(skew.offset_secs.abs() <= 2, "{:?}", skew);
+    assert_eq!(skew.resyncs, 0);
+}
+
+#[tokio::test]
+async fn check_update_without_new_version() {
+    let _server = setup().await;
+    let info = check_update("2.2.1", UpdateChannel::Stable).await.unwrap();
+    assert!(!info.has_update);
+}
+
+#[tokio::test]
+async fn check_update_and_download() {
+    let server = setup().await;
+    server.state().with_fixtures(|f| {
+        f.version = Some(VersionInfo {
+            version: "9.9.9".to_string(),
+            filename: Some("ATM-Tray-9.9.9.exe".to_string()),
+            size: Some(64 * 1024),
+            changelog: Some("测试版本".to_string()),
+            force_update: false,
+            download_url: Some("/client/download/ATM-Tray-9.9.9.exe".to_string()),
+            sha256: None,
+            signature: None,
+            patch_from: None,
+        })
+    });
+
+    let info = check_update("2.2.1", UpdateChannel::Stable).await.unwrap();
+    assert!(info.has_update);
+    assert_eq!(info.version.as_deref(), Some("9.9.9"));
+
+    let dir = std::env::temp_dir().join(format!("atm-api-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("update.exe");
+    let progress = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let reported = progress.clone();
+    download_update(info.download_url.as_deref().unwrap(), &path, move |done, _| {
+        reported.store(done, std::sync::atomic::Ordering::SeqCst);
+    })
+    .await
+    .unwrap();
+
+    let expected = atm_mock_server::download_bytes("ATM-Tray-9.9.9.exe", 64 * 1024);
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+    assert_eq!(progress.load(std::sync::atomic::Ordering::SeqCst), expected.len() as u64);
+
+    // 版本信息的签名和下载文件的摘要都能通过校验
+    let release = crate::update::verify_release(&info, Some(&atm_mock_server::update_public_key())).unwrap();
+    crate::update::verify_file(&path, &release).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn get_announcements_parses_fields() {
+    let server = setup().await;
+    server.state().with_fixtures(|f| {
+        f.announcements.push(MockAnnouncement {
+            id: "maintenance".to_string(),
+            severity: "critical".to_string(),
+            title: "维护通知".to_string(),
+            body: "今晚维护".to_string(),
+            starts_at: Some(1_700_000_000),
+            ends_at: Some(1_800_000_000),
+            min_version: Some("2.0.0".to_string()),
+            max_version: Some("2.9.9".to_string()),
+        });
+        // 未知级别按普通公告处理
+        f.announcements.push(MockAnnouncement {
+            id: "future".to_string(),
+            severity: "emergency".to_string(),
+            title: "新级别".to_string(),
+            body: String::new(),
+            starts_at: None,
+            ends_at: None,
+            min_version: None,
+            max_version: None,
+        });
+    });
+
+    let announcements = get_announcements().await.unwrap();
+    let ids: Vec<&str> = announcements.iter().map(|a| a.id.as_str()).collect();
+    assert_eq!(ids, ["welcome", "maintenance", "future"]);
+    let maintenance = &announcements[1];
+    assert_eq!(maintenance.severity, Severity::Critical);
+    assert_eq!(maintenance.ends_at, Some(1_800_000_000));
+    assert_eq!(maintenance.min_version.as_deref(), Some("2.0.0"));
+    assert_eq!(announcements[2].severity, Severity::Info);
+}
+
+#[tokio::test]
+async fn get_announcements_rejects_bad_mac() {
+    let server = setup().await;
+    server.state().set_fault("client/announcements", Fault { bad_mac: true, ..Default::default() });
+    let err = get_announcements().await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+}
+
+fn publish_update(server: &MockServer, size: i64) {
+    server.state().with_fixtures(|f| {
+        f.version = Some(VersionInfo {
+            version: "9.9.9".to_string(),
+            filename: Some("ATM-Tray-9.9.9.exe".to_string()),
+            size: Some(size),
+            changelog: None,
+            force_update: false,
+            download_url: Some("/client/download/ATM-Tray-9.9.9.exe".to_string()),
+            sha256: None,
+            signature: None,
+            patch_from: None,
+        })
+    });
+}
+
+#[tokio::test]
+async fn check_update_advertises_patch_for_matching_version() {
+    let server = setup().await;
+    publish_update(&server, 8 * 1024);
+    server.state().with_fixtures(|f| {
+        if let Some(v) = f.version.as_mut() {
+            v.patch_from = Some(PatchSource {
+                version: "2.2.1".to_string(),
+                filename: "ATM-Tray-2.2.1.exe".to_string(),
+                size: 6 * 1024,
+            });
+        }
+    });
+
+    // 当前版本不是补丁的基础版本时只提供完整安装包
+    assert!(check_update("2.2.0", UpdateChannel::Stable).await.unwrap().patch.is_none());
+
+    let info = check_update("2.2.1", UpdateChannel::Stable).await.unwrap();
+    let patch = info.patch.clone().unwrap();
+    assert_eq!(patch.from, "2.2.1");
+
+    // 补丁应用到旧版本后与完整安装包一致，并通过签名摘要校验
+    let dir = download_dir();
+    let current = dir.join("current.exe");
+    std::fs::write(&current, atm_mock_server::download_bytes("ATM-Tray-2.2.1.exe", 6 * 1024)).unwrap();
+    let patch_path = dir.join("update.patch");
+    download_update(&patch.download_url, &patch_path, |_, _| {}).await.unwrap();
+    assert_eq!(patch.size, Some(std::fs::metadata(&patch_path).unwrap().len() as i64));
+
+    let release = crate::update::verify_release(&info, Some(&atm_mock_server::update_public_key())).unwrap();
+    let output = dir.join("update.exe");
+    crate::update::apply_patch(&current, &patch_path, &output, &release).unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), atm_mock_server::download_bytes("ATM-Tray-9.9.9.exe", 8 * 1024));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn check_update_beta_channel() {
+    let server = setup().await;
+    publish_update(&server, 4 * 1024);
+    server.state().with_fixtures(|f| {
+        f.beta_version = Some(VersionInfo {
+            version: "10.0.0-beta.1".to_string(),
+            filename: Some("ATM-Tray-10.0.0-beta.1.exe".to_string()),
+            size: Some(8 * 1024),
+            changelog: None,
+            force_update: false,
+            download_url: Some("/client/download/ATM-Tray-10.0.0-beta.1.exe".to_string()),
+            sha256: None,
+            signature: None,
+            patch_from: None,
+        })
+    });
+
+    // 正式版频道不会收到测试版
+    let stable = check_update("2.2.1", UpdateChannel::Stable).await.unwrap();
+    assert_eq!(stable.version.as_deref(), Some("9.9.9"));
+
+    let beta = check_update("2.2.1", UpdateChannel::Beta).await.unwrap();
+    assert_eq!(beta.version.as_deref(), Some("10.0.0-beta.1"));
+    let release = crate::update::verify_release(&beta, Some(&atm_mock_server::update_public_key())).unwrap();
+
+    let dir = download_dir();
+    let path = dir.join("update.exe");
+    download_update(beta.download_url.as_deref().unwrap(), &path, |_, _| {}).await.unwrap();
+    crate::update::verify_file(&path, &release).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn download_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("atm-api-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn download_update_resumes_after_stall() {
+    let server = setup().await;
+    publish_update(&server, 256 * 1024);
+    // 第一次连接发送 100KB 后卡住
+    server.state().set_fault("client/download", Fault { stall_after: Some(100 * 1024), times: Some(1), ..Default::default() });
+
+    let dir = download_dir();
+    let path = dir.join("update.exe");
+    let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let reported = progress.clone();
+    download_update("/client/download/ATM-Tray-9.9.9.exe", &path, move |done, total| {
+        reported.lock().unwrap().push((done, total));
+    })
+    .await
+    .unwrap();
+
+    let expected = atm_mock_server::download_bytes("ATM-Tray-9.9.9.exe", 256 * 1024);
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+    assert_eq!(server.state().hits("client/download"), 2);
+    assert_eq!(server.state().partial_hits("client/download"), 1);
+    assert!(!part_paths(&path).0.exists());
+    assert!(!part_paths(&path).1.exists());
+    // 续传的进度从已下载的字节数开始，总大小不变
+    let progress = progress.lock().unwrap();
+    let resumed = progress.iter().rev().find(|(done, _)| *done == 100 * 1024).unwrap();
+    assert_eq!(resumed.1, 256 * 1024);
+    assert!(progress.windows(2).all(|w| w[0].0 <= w[1].0), "{:?}", progress);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn download_update_resumes_existing_part_file() {
+    let server = setup().await;
+    publish_update(&server, 128 * 1024);
+    let dir = download_dir();
+    let path = dir.join("update.exe");
+    let (part, meta) = part_paths(&path);
+    // 上次运行中断留下的部分文件（没有 ETag 时按总大小校验）
+    let expected = atm_mock_server::download_bytes("ATM-Tray-9.9.9.exe", 128 * 1024);
+    std::fs::write(&part, &expected[..40 * 1024]).unwrap();
+    let previous = PartialDownload {
+        url: format!("{}/client/download/ATM-Tray-9.9.9.exe", server.api_base()),
+        etag: None,
+        total: Some(128 * 1024),
+    };
+    std::fs::write(&meta, serde_json::to_vec(&previous).unwrap()).unwrap();
+
+    let first = std::sync::Arc::new(std::sync::Mutex::new(None));
+    let reported = first.clone();
+    download_update("/client/download/ATM-Tray-9.9.9.exe", &path, move |done, _| {
+        reported.lock().unwrap().get_or_insert(done);
+    })
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+    assert_eq!(server.state().partial_hits("client/download"), 1);
+    assert_eq!(*first.lock().unwrap(), Some(40 * 1024));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn download_update_restarts_when_file_changed() {
+    let server = setup().await;
+    publish_update(&server, 64 * 1024);
+    let dir = download_dir();
+    let path = dir.join("update.exe");
+    let (part, meta) = part_paths(&path);
+    // 旧版本文件的部分内容，ETag 与服务器当前文件不一致
+    std::fs::write(&part, vec![0u8; 10 * 1024]).unwrap();
+    let stale = PartialDownload {
+        url: format!("{}/client/download/ATM-Tray-9.9.9.exe", server.api_base()),
+        etag: Some("\"stale\"".to_string()),
+        total: Some(64 * 1024),
+    };
+    std::fs::write(&meta, serde_json::to_vec(&stale).unwrap()).unwrap();
+
+    download_update("/client/download/ATM-Tray-9.9.9.exe", &path, |_, _| {}).await.unwrap();
+    let expected = atm_mock_server::download_bytes("ATM-Tray-9.9.9.exe", 64 * 1024);
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+    assert_eq!(server.state().partial_hits("client/download"), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn download_update_http_error() {
+    let server = setup().await;
+    server.state().set_fault("client/download", fault(404));
+    let path = std::env::temp_dir().join(format!("atm-api-test-{}.exe", uuid::Uuid::new_v4()));
+    let err = download_update("/client/download/missing.exe", &path, |_, _| {}).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Http(404)), "{:?}", err);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn signature_v2_accepted_by_v2_only_server() {
+    let server = setup().await;
+    server.state().require_signature_v2(true);
+    let resp = activate_license(LICENSE, DEVICE_ID).await.unwrap();
+    let token = resp.session_token.unwrap();
+    assert_eq!(get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap().len(), 2);
+    assert!(heartbeat(&token, DEVICE_ID).await.unwrap().valid);
+    assert!(unbind_device(LICENSE, DEVICE_ID).await.unwrap());
+}
+
+#[tokio::test]
+async fn signature_v2_replayed_request_is_rejected() {
+    let server = setup().await;
+    let url = format!("{}/tokens", server.api_base());
+    let nonce = crypto::generate_nonce();
+    let request = session_request(&http_client(), Method::GET, &url, None, &nonce, &session(&server), DEVICE_ID);
+    let replay = request.try_clone().unwrap();
+
+    assert_eq!(request.send().await.unwrap().status(), 200);
+    let response = replay.send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "NONCE_REUSED");
+}
+
+#[tokio::test]
+async fn signature_v2_covers_body() {
+    let server = setup().await;
+    let url = format!("{}/tokens/activate", server.api_base());
+    let body = br#"{"token_id":"token-1"}"#.to_vec();
+    // 签名针对 token-1，实际发送 token-2
+    let nonce = crypto::generate_nonce();
+    let response = session_request(&http_client(), Method::POST, &url, Some(body), &nonce, &session(&server), DEVICE_ID)
+        .body(r#"{"token_id":"token-2"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "INVALID_SIGNATURE");
+}
+
+#[tokio::test]
+async fn signature_v2_covers_path() {
+    let server = setup().await;
+    let token = session(&server);
+    let signed_url = format!("{}/tokens/check/token-1", server.api_base());
+    let nonce = crypto::generate_nonce();
+    let request = session_request(&http_client(), Method::GET, &signed_url, None, &nonce, &token, DEVICE_ID)
+        .build()
+        .unwrap();
+    let mut moved = request;
+    moved.url_mut().set_path("/api/v1/tokens/check/token-2");
+    let response = http_client().execute(moved).await.unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn signature_v1_accepted_during_migration() {
+    let server = setup().await;
+    let token = session(&server);
+    let url = format!("{}/tokens", server.api_base());
+    let v1 = |client: &Client| {
+        let timestamp = chrono::Utc::now().timestamp();
+        client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("X-Device-ID", DEVICE_ID)
+            .header("X-Timestamp", timestamp.to_string())
+            .header("X-Signature", crypto::generate_signature(&token, timestamp, DEVICE_ID))
+    };
+
+    assert_eq!(v1(&http_client()).send().await.unwrap().status(), 200);
+    server.state().require_signature_v2(true);
+    let response = v1(&http_client()).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "SIGNATURE_VERSION_UNSUPPORTED");
+}
+
+#[tokio::test]
+async fn check_update_rejects_tampered_response_mac() {
+    let server = setup().await;
+    server.state().set_fault("client/version", Fault { bad_mac: true, ..Default::default() });
+    let err = check_update("2.2.1", UpdateChannel::Stable).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+    // 校验失败不重试
+    assert_eq!(server.state().hits("client/version"), 1);
+}
+
+#[tokio::test]
+async fn heartbeat_rejects_stale_response_mac() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("auth/heartbeat", Fault { stale_mac: true, ..Default::default() });
+    let err = heartbeat(&token, DEVICE_ID).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn check_token_version_rejects_tampered_response_mac() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens/check", Fault { bad_mac: true, ..Default::default() });
+    let err = check_token_version(&token, "token-1", DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn response_mac_is_bound_to_request_nonce() {
+    let server = setup().await;
+    let url = format!("{}/auth/heartbeat", server.api_base());
+    let nonce = crypto::generate_nonce();
+    let response = session_request(&http_client(), Method::POST, &url, None, &nonce, &session(&server), DEVICE_ID)
+        .send()
+        .await
+        .unwrap();
+    let err = read_verified_json::<Value>(response, &crypto::generate_nonce()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn unsigned_response_is_rejected() {
+    let server = setup().await;
+    // Factory 替身不签名响应
+    let response = http_client()
+        .post(server.factory_url())
+        .header("Authorization", "Bearer access-token-1")
+        .send()
+        .await
+        .unwrap();
+    let err = read_verified_json::<Value>(response, &crypto::generate_nonce()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+}
+
+// ==================== 证书固定 ====================
+
+// 在 mock 服务器前加一层 TLS：自签名 CA 签发 localhost 证书，解密后转发到 mock 服务器
+struct TlsFront {
+    api_base: String,
+    ca_cert: rustls::pki_types::CertificateDer<'static>,
+    leaf_pin: String,
+    ca_pin: String,
+    handshakes: std::sync::Arc<std::sync::atomic::AtomicU32>,
+    _task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for TlsFront {
+    fn drop(&mut self) {
+        self._task.abort();
+    }
+}
+
+fn pin_of(spki_der: &[u8]) -> String {
+    use base64::Engine as _;
+    use sha2::Digest as _;
+    base64::engine::general_purpose::STANDARD.encode(sha2::Sha256::digest(spki_der))
+}
+
+async fn tls_front(server: &MockServer) -> TlsFront {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::sync::atomic::Ordering;
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let leaf_key = KeyPair::generate().unwrap();
+    let leaf = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&leaf_key, &ca, &ca_key)
+        .unwrap();
+
+    let config = rustls::ServerConfig::builder_with_provider(std::sync::Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(
+        vec![leaf.der().clone()],
+        rustls::pki_types::PrivateKeyDer::Pkcs8(leaf_key.serialize_der().into()),
+    )
+    .unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let upstream = server.addr();
+    let handshakes = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+    let counter = handshakes.clone();
+    let task = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(mut tls) = acceptor.accept(stream).await else {
+                    return;
+                };
+                if let Ok(mut backend) = tokio::net::TcpStream::connect(upstream).await {
+                    let _ = tokio::io::copy_bidirectional(&mut tls, &mut backend).await;
+                }
+            });
+        }
+    });
+
+    TlsFront {
+        api_base: format!("https://localhost:{}/api/v1", port),
+        ca_cert: ca.der().clone(),
+        leaf_pin: pin_of(&leaf_key.public_key_der()),
+        ca_pin: pin_of(&ca_key.public_key_der()),
+        handshakes,
+        _task: task,
+    }
+}
+
+// 让当前线程的请求通过 TLS 前端，并只信任测试 CA
+fn use_pinned_client(front: &TlsFront, pins: &[String]) {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(front.ca_cert.clone()).unwrap();
+    use_transport(|t| {
+        t.roots = roots;
+        t.pins = pins.to_vec();
+    });
+    TEST_OVERRIDE.with(|o| o.borrow_mut().as_mut().unwrap().api_base = front.api_base.clone());
+}
+
+fn handshakes(front: &TlsFront) -> u32 {
+    front.handshakes.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn pinning_accepts_matching_leaf_key() {
+    let server = setup().await;
+    let front = tls_front(&server).await;
+    use_pinned_client(&front, std::slice::from_ref(&front.leaf_pin));
+    let tokens = get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(tokens.len(), 2);
+}
+
+#[tokio::test]
+async fn pinning_accepts_trust_anchor_and_backup_pin() {
+    let server = setup().await;
+    let front = tls_front(&server).await;
+    // 主固定值失效时备份固定值（这里是 CA 公钥）仍然生效
+    let stale = pin_of(b"rotated-away key");
+    use_pinned_client(&front, &[stale, format!("sha256/{}", front.ca_pin)]);
+    assert!(!check_update("2.2.1", UpdateChannel::Stable).await.unwrap().has_update);
+}
+
+#[tokio::test]
+async fn pinning_rejects_unknown_key_without_retry() {
+    let server = setup().await;
+    let front = tls_front(&server).await;
+    use_pinned_client(&front, &[pin_of(b"some other key")]);
+    let err = get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::CertificatePin), "{:?}", err);
+    assert_eq!(err.code(), "CERT_PIN_MISMATCH");
+    assert_eq!(handshakes(&front), 1);
+    assert_eq!(server.state().hits("tokens"), 0);
+}
+
+#[tokio::test]
+async fn pinning_applies_to_downloads() {
+    let server = setup().await;
+    let front = tls_front(&server).await;
+    use_pinned_client(&front, &[pin_of(b"some other key")]);
+    let path = std::env::temp_dir().join(format!("atm-api-test-{}.exe", uuid::Uuid::new_v4()));
+    let err = download_update("/client/download/pinned.exe", &path, |_, _| {}).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::CertificatePin), "{:?}", err);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn pinning_still_requires_trusted_chain() {
+    let server = setup().await;
+    let front = tls_front(&server).await;
+    // 固定值匹配但证书不受信任：标准校验先失败，不视为固定值错误
+    use_transport(|t| t.pins = vec![front.leaf_pin.clone()]);
+    TEST_OVERRIDE.with(|o| o.borrow_mut().as_mut().unwrap().api_base = front.api_base.clone());
+    let err = check_update("2.2.1", UpdateChannel::Stable).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Network(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn pinning_does_not_apply_to_factory() {
+    let server = setup().await;
+    let front = tls_front(&server).await;
+    // Factory 的证书链不包含我们的固定值：只做标准证书校验
+    use_pinned_client(&front, &[pin_of(b"some other key")]);
+    let factory_url = front.api_base.replace("/api/v1", "/factory/subscription");
+    TEST_OVERRIDE.with(|o| o.borrow_mut().as_mut().unwrap().factory_url = factory_url);
+    let (_, summary) = get_subscription("access-token-1").await.unwrap();
+    assert_eq!(summary.limit, 1000);
+    assert_eq!(server.state().hits("factory/subscription"), 1);
+
+    // 我们自己的服务器仍然校验固定值
+    let err = get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::CertificatePin), "{:?}", err);
+}
+
+#[test]
+fn production_pins_are_valid() {
+    let pins = pinning::parse_pins(&pinning::production_pins()).unwrap();
+    assert!(pins.len() >= 2, "需要主固定值和备份固定值");
+}
+
+// ==================== 代理 ====================
+
+// 测试用代理服务器：记录每个连接的请求目标，校验认证后转发到目标地址
+struct TestProxy {
+    port: u16,
+    seen: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    _task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for TestProxy {
+    fn drop(&mut self) {
+        self._task.abort();
+    }
+}
+
+impl TestProxy {
+    fn seen(&self) -> Vec<String> {
+        self.seen.lock().unwrap().clone()
+    }
+
+    fn config(&self, scheme: proxy::ProxyScheme, username: &str, password: &str) -> ProxyConfig {
+        ProxyConfig {
+            mode: proxy::ProxyMode::Manual,
+            scheme,
+            host: "127.0.0.1".to_string(),
+            port: self.port,
+            username: Some(username.to_string()),
+            password: Some(password.to_string()),
+            no_proxy: None,
+        }
+    }
+}
+
+const PROXY_USER: &str = "corp";
+const PROXY_PASS: &str = "p@ss word";
+
+async fn spawn_proxy<F, Fut>(handler: F) -> TestProxy
+where
+    F: Fn(tokio::net::TcpStream, std::sync::Arc<std::sync::Mutex<Vec<String>>>) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = std::io::Result<()>> + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = seen.clone();
+    let task = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handler(stream, log.clone()));
+        }
+    });
+    TestProxy { port, seen, _task: task }
+}
+
+// HTTP 正向代理：要求 Proxy-Authorization，转发绝对路径形式的请求
+async fn http_proxy() -> TestProxy {
+    use base64::Engine as _;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let expected = format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", PROXY_USER, PROXY_PASS))
+    );
+    spawn_proxy(move |mut stream, log| {
+        let expected = expected.clone();
+        async move {
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                if stream.read(&mut byte).await? == 0 {
+                    return Ok(());
+                }
+                head.push(byte[0]);
+            }
+            let text = String::from_utf8_lossy(&head).to_string();
+            let target = text.split_whitespace().nth(1).unwrap_or_default().to_string();
+            log.lock().unwrap().push(target.clone());
+
+            let authorized = text.lines().any(|line| {
+                line.split_once(':').is_some_and(|(name, value)| {
+                    name.eq_ignore_ascii_case("proxy-authorization") && value.trim() == expected
+                })
+            });
+            if !authorized {
+                stream
+                    .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await?;
+                return Ok(());
+            }
+
+            let host = reqwest::Url::parse(&target).ok().and_then(|u| u.socket_addrs(|| None).ok());
+            let Some(addr) = host.and_then(|a| a.into_iter().next()) else {
+                return Ok(());
+            };
+            // 每个连接只转发一个请求，便于按连接记录请求目标
+            let mut forwarded: String = text
+                .trim_end()
+                .lines()
+                .filter(|line| {
+                    let name = line.split(':').next().unwrap_or_default().to_ascii_lowercase();
+                    !matches!(name.as_str(), "connection" | "proxy-connection" | "proxy-authorization")
+                })
+                .map(|line| format!("{}\r\n", line))
+                .collect();
+            forwarded.push_str("Connection: close\r\n\r\n");
+            let mut backend = tokio::net::TcpStream::connect(addr).await?;
+            backend.write_all(forwarded.as_bytes()).await?;
+            tokio::io::copy_bidirectional(&mut stream, &mut backend).await?;
+            Ok(())
+        }
+    })
+    .await
+}
+
+// SOCKS5 代理：用户名密码认证（RFC 1929），支持 IPv4 与域名地址
+async fn socks5_proxy() -> TestProxy {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    spawn_proxy(|mut stream, log| async move {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await?;
+        let mut methods = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut methods).await?;
+        if !methods.contains(&0x02) {
+            stream.write_all(&[0x05, 0xff]).await?;
+            return Ok(());
+        }
+        stream.write_all(&[0x05, 0x02]).await?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut username = vec![0u8; len[1] as usize];
+        stream.read_exact(&mut username).await?;
+        stream.read_exact(&mut len[..1]).await?;
+        let mut password = vec![0u8; len[0] as usize];
+        stream.read_exact(&mut password).await?;
+        if username != PROXY_USER.as_bytes() || password != PROXY_PASS.as_bytes() {
+            stream.write_all(&[0x01, 0x01]).await?;
+            return Ok(());
+        }
+        stream.write_all(&[0x01, 0x00]).await?;
+
+        let mut request = [0u8; 4];
+        stream.read_exact(&mut request).await?;
+        let host = match request[3] {
+            0x01 => {
+                let mut ip = [0u8; 4];
+                stream.read_exact(&mut ip).await?;
+                std::net::Ipv4Addr::from(ip).to_string()
+            }
+            0x03 => {
+                stream.read_exact(&mut len[..1]).await?;
+                let mut name = vec![0u8; len[0] as usize];
+                stream.read_exact(&mut name).await?;
+                String::from_utf8_lossy(&name).to_string()
+            }
+            _ => return Ok(()),
+        };
+        let mut port = [0u8; 2];
+        stream.read_exact(&mut port).await?;
+        let target = format!("{}:{}", host, u16::from_be_bytes(port));
+        log.lock().unwrap().push(target.clone());
+
+        let mut backend = tokio::net::TcpStream::connect(&target).await?;
+        stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+        tokio::io::copy_bidirectional(&mut stream, &mut backend).await?;
+        Ok(())
+    })
+    .await
+}
+
+fn use_proxy(config: &ProxyConfig) {
+    config.validate().unwrap();
+    use_transport(|t| t.proxy = config.clone());
+}
+
+#[tokio::test]
+async fn http_proxy_carries_api_and_factory_requests() {
+    let server = setup().await;
+    let proxy = http_proxy().await;
+    use_proxy(&proxy.config(proxy::ProxyScheme::Http, PROXY_USER, PROXY_PASS));
+
+    let tokens = get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(tokens.len(), 2);
+    let (_, summary) = get_subscription("access-token-1").await.unwrap();
+    assert_eq!(summary.limit, 1000);
+
+    let seen = proxy.seen();
+    assert!(seen.iter().any(|t| t.ends_with("/api/v1/tokens")), "{:?}", seen);
+    assert!(seen.iter().any(|t| t.ends_with("/factory/subscription")), "{:?}", seen);
+}
+
+#[tokio::test]
+async fn http_proxy_applies_to_downloads() {
+    let server = setup().await;
+    let proxy = http_proxy().await;
+    use_proxy(&proxy.config(proxy::ProxyScheme::Http, PROXY_USER, PROXY_PASS));
+
+    let path = std::env::temp_dir().join(format!("atm-api-test-{}.exe", uuid::Uuid::new_v4()));
+    download_update("/client/download/proxied.exe", &path, |_, _| {}).await.unwrap();
+    assert!(path.exists());
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(server.state().hits("client/download"), 1);
+    assert!(proxy.seen().iter().any(|t| t.ends_with("/client/download/proxied.exe")));
+}
+
+#[tokio::test]
+async fn socks5_proxy_with_credentials() {
+    let server = setup().await;
+    let proxy = socks5_proxy().await;
+    use_proxy(&proxy.config(proxy::ProxyScheme::Socks5, PROXY_USER, PROXY_PASS));
+
+    assert!(!check_update("2.2.1", UpdateChannel::Stable).await.unwrap().has_update);
+    assert_eq!(proxy.seen(), vec![server.addr().to_string()]);
+}
+
+#[tokio::test]
+async fn no_proxy_list_bypasses_proxy() {
+    let server = setup().await;
+    let proxy = http_proxy().await;
+    let mut config = proxy.config(proxy::ProxyScheme::Http, PROXY_USER, PROXY_PASS);
+    config.no_proxy = Some("example.com, 127.0.0.1".to_string());
+    use_proxy(&config);
+
+    assert!(!check_update("2.2.1", UpdateChannel::Stable).await.unwrap().has_update);
+    assert_eq!(server.state().hits("client/version"), 1);
+    assert!(proxy.seen().is_empty());
+}
+
+#[tokio::test]
+async fn test_connection_through_proxy() {
+    let server = setup().await;
+    let proxy = http_proxy().await;
+
+    let (status, _) = test_connection(&proxy.config(proxy::ProxyScheme::Http, PROXY_USER, PROXY_PASS))
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(server.state().hits("client/version"), 1);
+
+    let err = test_connection(&proxy.config(proxy::ProxyScheme::Http, PROXY_USER, "wrong"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Http(407)), "{:?}", err);
+    assert_eq!(server.state().hits("client/version"), 1);
+}
+
+#[tokio::test]
+async fn test_connection_reports_socks5_auth_failure() {
+    let _server = setup().await;
+    let proxy = socks5_proxy().await;
+    let err = test_connection(&proxy.config(proxy::ProxyScheme::Socks5, PROXY_USER, "wrong"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err.kind(), ApiError::Network(_)), "{:?}", err);
+    assert!(proxy.seen().is_empty());
+}
+
+#[test]
+fn proxy_config_validation() {
+    let manual = |host: &str, port: u16| ProxyConfig {
+        mode: proxy::ProxyMode::Manual,
+        host: host.to_string(),
+        port,
+        ..Default::default()
+    };
+    assert!(ProxyConfig::default().validate().is_ok());
+    assert!(manual("proxy.corp.local", 8080).validate().is_ok());
+    assert!(manual("", 8080).validate().is_err());
+    assert!(manual("proxy.corp.local", 0).validate().is_err());
+    assert!(manual("http://proxy.corp.local", 8080).validate().is_err());
+
+    // SOCKS5 认证报文中用户名和密码的长度只占一个字节
+    let mut socks = ProxyConfig { scheme: proxy::ProxyScheme::Socks5, ..manual("proxy.corp.local", 1080) };
+    socks.username = Some("u".repeat(255));
+    socks.password = Some("p".repeat(255));
+    assert!(socks.validate().is_ok());
+    socks.password = Some("p".repeat(256));
+    assert!(socks.validate().is_err());
+    socks.scheme = proxy::ProxyScheme::Http;
+    assert!(socks.validate().is_ok());
+
+    // 摘要不包含密码
+    let mut config = manual("proxy.corp.local", 8080);
+    config.password = Some("secret".to_string());
+    let summary = config.summary();
+    assert_eq!(summary["hasPassword"], true);
+    assert!(!summary.to_string().contains("secret"));
+}
+
+// ==================== 请求统计 ====================
+
+// 统计是全局的（其他用例并行写入），这里只检查接口调用和重试被计入，计数细节见 metrics/tests.rs
+#[tokio::test]
+async fn api_calls_and_retries_are_recorded() {
+    let server = setup().await;
+    server.state().set_fault("client/version", Fault { bad_mac: true, ..Default::default() });
+    check_update("2.2.1", UpdateChannel::Stable).await.unwrap_err();
+    server.state().set_fault("tokens", Fault { times: Some(1), ..fault(503) });
+    get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap();
+
+    let stats = metrics::snapshot(false);
+    let version = &stats["endpoints"]["client/version"];
+    assert!(version["failures"]["RESPONSE_VERIFICATION_FAILED"].as_u64().unwrap() >= 1, "{}", version);
+    let tokens = &stats["endpoints"]["tokens"];
+    assert!(tokens["successes"].as_u64().unwrap() >= 1, "{}", tokens);
+    assert!(tokens["retries"].as_u64().unwrap() >= 1, "{}", tokens);
+}
+
+// ==================== 离线判断 ====================
+
+#[tokio::test]
+async fn unreachable_server_counts_as_offline() {
+    let server = setup().await;
+    let token = session(&server);
+    // 绑定后立即释放端口，连接会被拒绝
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    TEST_OVERRIDE.with(|o| o.borrow_mut().as_mut().unwrap().api_base = format!("http://127.0.0.1:{}/api/v1", port));
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(err.is_offline(), "{:?}", err);
+}
+
+#[tokio::test]
+async fn gateway_errors_count_as_offline_but_auth_errors_do_not() {
+    let server = setup().await;
+    let token = session(&server);
+    server.state().set_fault("tokens", fault(503));
+    assert!(get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err().is_offline());
+    server.state().set_fault("tokens", fault(401));
+    assert!(!get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err().is_offline());
+}
+
+// ==================== 条件请求 ====================
+
+#[tokio::test]
+async fn token_list_reuses_cache_on_not_modified() {
+    let server = setup().await;
+    let token = session(&server);
+    let first = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    let second = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(server.state().hits("tokens"), 2);
+    assert_eq!(server.state().not_modified_hits("tokens"), 1);
+    assert_eq!(
+        serde_json::to_value(&first).unwrap(),
+        serde_json::to_value(&second).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn token_list_refetches_after_change() {
+    let server = setup().await;
+    let token = session(&server);
+    get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    server.state().with_fixtures(|f| f.tokens[0].quota_used = Some(999));
+    let tokens = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(server.state().not_modified_hits("tokens"), 0);
+    assert!(tokens.iter().any(|t| t.quota_used == Some(999)));
+    // 新的校验值随后生效
+    get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(server.state().not_modified_hits("tokens"), 1);
+}
+
+#[tokio::test]
+async fn token_list_cache_is_per_session() {
+    let server = setup().await;
+    get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    get_token_list(&session(&server), DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    assert_eq!(server.state().not_modified_hits("tokens"), 0);
+}
+
+#[tokio::test]
+async fn token_list_not_modified_requires_valid_mac() {
+    let server = setup().await;
+    let token = session(&server);
+    get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap();
+    server.state().set_fault("tokens", Fault { bad_mac: true, ..Default::default() });
+    let err = get_token_list(&token, DEVICE_ID, &cancel::session_scope()).await.unwrap_err();
+    assert!(matches!(err.kind(), ApiError::ResponseVerification(_)), "{:?}", err);
+    assert_eq!(server.state().not_modified_hits("tokens"), 1);
+}
+
+#[tokio::test]
+async fn token_list_honors_if_modified_since() {
+    let server = setup().await;
+    let token = session(&server);
+    let url = format!("{}/tokens", server.api_base());
+    let client = http_client();
+
+    let send = |extra: Option<(&'static str, String)>| {
+        let nonce = crypto::generate_nonce();
+        let mut request = session_request(&client, Method::GET, &url, None, &nonce, &token, DEVICE_ID);
+        if let Some((name, value)) = extra {
+            request = request.header(name, value);
+        }
+        request.send()
+    };
+    let response = send(None).await.unwrap();
+    let last_modified = response.headers()[reqwest::header::LAST_MODIFIED].to_str().unwrap().to_string();
+    assert!(response.headers().contains_key(reqwest::header::ETAG));
+
+    let response = send(Some(("If-Modified-Since", last_modified))).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_MODIFIED);
+    let response = send(Some(("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT".to_string()))).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
@@ -0,0 +1,87 @@
+use crate::error::ApiError;
+use std::future::Future;
+use tokio::sync::watch;
+
+// ==================== 请求取消 ====================
+// 会话代：退出登录、切换模式、清除数据和退出程序时加一
+// - 在旧会话代中发起、尚未完成的请求被中止（Scope::run 返回 ApiError::Cancelled）
+// - 请求完成时会话代已变化的结果同样丢弃，不再删除会话或改写 auth.json
+// 切换账号另有一个代：新的 activate_token 使之前未完成的切换和刷新失效，避免旧账号覆盖刚选择的账号
+
+// 单调递增的代，订阅者在变化时被唤醒
+pub(crate) struct Generation(watch::Sender<u64>);
+
+impl Generation {
+    pub(crate) fn new() -> Self {
+        Generation(watch::Sender::new(0))
+    }
+
+    pub(crate) fn bump(&self) {
+        self.0.send_modify(|g| *g += 1);
+    }
+
+    pub(crate) fn scope(&self) -> Scope {
+        let rx = self.0.subscribe();
+        let generation = *rx.borrow();
+        Scope { generation, rx }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SESSION: Generation = Generation::new();
+    static ref ACTIVE_TOKEN: Generation = Generation::new();
+}
+
+// 退出登录、切换模式、清除数据或退出程序时调用：中止当前会话代的所有请求
+pub fn bump_session() {
+    SESSION.bump();
+}
+
+// 当前会话代
+pub fn session_scope() -> Scope {
+    SESSION.scope()
+}
+
+// 切换账号时调用：之前未完成的切换和刷新不再写入 auth.json
+pub fn bump_active_token() {
+    ACTIVE_TOKEN.bump();
+}
+
+// 当前账号的代（刷新当前账号时使用，不中止其他请求）
+pub fn active_token_scope() -> Scope {
+    ACTIVE_TOKEN.scope()
+}
+
+// 创建时的代，代变化后失效
+#[derive(Clone)]
+pub struct Scope {
+    generation: u64,
+    rx: watch::Receiver<u64>,
+}
+
+impl Scope {
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // 代未变化，结果仍可写入本地数据
+    pub fn is_current(&self) -> bool {
+        *self.rx.borrow() == self.generation
+    }
+
+    // 运行 fut，代变化时立即中止（未完成的请求随 fut 一起丢弃）
+    pub async fn run<T, F>(&self, fut: F) -> Result<T, ApiError>
+    where
+        F: Future<Output = Result<T, ApiError>>,
+    {
+        let mut rx = self.rx.clone();
+        let generation = self.generation;
+        tokio::select! {
+            result = fut => if self.is_current() { result } else { Err(ApiError::Cancelled) },
+            _ = rx.wait_for(|g| *g != generation) => Err(ApiError::Cancelled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
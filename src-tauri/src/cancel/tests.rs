@@ -0,0 +1,51 @@
+// 请求取消测试：代变化时中止进行中的请求、丢弃代变化后才返回的结果
+// 使用局部的 Generation，不改变全局会话代（集成测试并行运行）
+
+use super::*;
+use std::time::Duration;
+
+#[tokio::test]
+async fn run_completes_within_current_generation() {
+    let session = Generation::new();
+    let scope = session.scope();
+    assert!(scope.is_current());
+    assert_eq!(scope.run(async { Ok(7) }).await.unwrap(), 7);
+    let err = scope.run(async { Err::<(), _>(ApiError::Timeout) }).await.unwrap_err();
+    assert!(matches!(err, ApiError::Timeout), "{:?}", err);
+}
+
+#[tokio::test]
+async fn bump_aborts_pending_request() {
+    let session = Generation::new();
+    let scope = session.scope();
+    let pending = scope.run(async {
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        Ok(())
+    });
+    let bump = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        session.bump();
+    };
+    let started = std::time::Instant::now();
+    let (result, ()) = tokio::join!(pending, bump);
+    assert!(matches!(result, Err(ApiError::Cancelled)), "{:?}", result);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // 旧的代不再生效，新的代不受影响
+    assert!(!scope.is_current());
+    assert!(matches!(scope.run(async { Ok(()) }).await, Err(ApiError::Cancelled)));
+    assert!(session.scope().run(async { Ok(()) }).await.is_ok());
+}
+
+#[tokio::test]
+async fn result_completed_after_bump_is_discarded() {
+    let session = Generation::new();
+    let scope = session.scope();
+    // 请求返回时代已变化（如返回前刚退出登录）：结果不再交给调用者
+    let result = scope.run(async {
+        session.bump();
+        Ok(1)
+    })
+    .await;
+    assert!(matches!(result, Err(ApiError::Cancelled)), "{:?}", result);
+}
@@ -0,0 +1,81 @@
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// ==================== 服务器时钟偏差 ====================
+// 服务器只接受 5 分钟内的签名时间戳，本机时钟不准时所有请求都会被拒绝（401）：
+// - 每个响应的 Date 头都会更新该服务器时间与本机时间的偏差
+//   偏差按服务器（host:port）分别记录：切换到备用地址时，不同区域服务器的时钟可能不同
+// - 请求签名和响应 MAC 的时效检查都使用 now(url)（本机时间 + 该服务器的偏差）
+// - 请求被 401 拒绝且偏差明显变化时，retry::send 按新的偏差重新签名并立即重试一次
+
+// 签名时使用的偏差与最新偏差相差超过该值时，401 视为时间戳被拒绝
+pub const RESYNC_THRESHOLD: i64 = 60;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockSkew {
+    // 服务器时间 - 本机时间（秒）
+    pub offset_secs: i64,
+    // 最近一次测量时的本机时间，None 表示尚未测量
+    pub measured_at: Option<i64>,
+    // 因时间戳被拒绝而重新签名的次数
+    pub resyncs: u32,
+}
+
+// 服务器标识：HTTP 接口和推送通道（同一主机端口）共用
+fn server_key(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .map(|u| format!("{}:{}", u.host_str().unwrap_or_default(), u.port_or_known_default().unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+lazy_static::lazy_static! {
+    static ref SERVERS: Mutex<HashMap<String, ClockSkew>> = Mutex::new(HashMap::new());
+}
+
+fn with_server<R>(url: &str, f: impl FnOnce(&mut ClockSkew) -> R) -> R {
+    let mut servers = SERVERS.lock().unwrap_or_else(|e| e.into_inner());
+    f(servers.entry(server_key(url)).or_default())
+}
+
+// url 所在服务器的偏差
+pub fn offset(url: &str) -> i64 {
+    with_server(url, |s| s.offset_secs)
+}
+
+// 按 url 所在服务器的时钟的当前时间（秒级时间戳）
+pub fn now(url: &str) -> i64 {
+    chrono::Utc::now().timestamp() + offset(url)
+}
+
+// 诊断信息
+pub fn skew(url: &str) -> ClockSkew {
+    with_server(url, |s| s.clone())
+}
+
+// 从响应的 Date 头更新偏差，没有或无法解析时返回 None
+pub(crate) fn observe(url: &str, headers: &HeaderMap) -> Option<i64> {
+    let value = headers.get(reqwest::header::DATE)?.to_str().ok()?;
+    let server = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?.timestamp();
+    let local = chrono::Utc::now().timestamp();
+    let offset = server - local;
+    with_server(url, |s| {
+        s.offset_secs = offset;
+        s.measured_at = Some(local);
+    });
+    Some(offset)
+}
+
+// 请求签名时的偏差与最新偏差相差较大：请求很可能因为时间戳被拒绝，需要重新签名
+pub(crate) fn resync_needed(url: &str, signed_offset: i64) -> bool {
+    (offset(url) - signed_offset).abs() >= RESYNC_THRESHOLD
+}
+
+pub(crate) fn record_resync(url: &str) {
+    with_server(url, |s| s.resyncs += 1);
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,53 @@
+// 时钟偏差测试：Date 头解析、按服务器分别记录偏差、重新签名的判断
+// 偏差按主机端口记录，每个用例使用自己的主机名，与并行运行的集成测试互不影响
+
+use super::*;
+use reqwest::header::HeaderValue;
+
+fn date(offset_secs: i64) -> HeaderMap {
+    let at = chrono::Utc::now() + chrono::Duration::seconds(offset_secs);
+    let mut headers = HeaderMap::new();
+    headers.insert(reqwest::header::DATE, HeaderValue::from_str(&at.to_rfc2822()).unwrap());
+    headers
+}
+
+#[test]
+fn offset_is_tracked_per_server() {
+    let api = "https://per-server.clock.test/api/v1/tokens";
+    let push = "wss://per-server.clock.test/ws";
+    let other = "https://per-server-other.clock.test/api/v1/tokens";
+    assert_eq!(skew(api).measured_at, None);
+    let observed = observe(api, &date(600)).unwrap();
+    assert!((observed - 600).abs() <= 1, "{}", observed);
+
+    // 同一主机端口的推送通道共用偏差，其他服务器不受影响
+    assert_eq!(offset(push), observed);
+    assert!(skew(api).measured_at.is_some());
+    assert!((now(api) - chrono::Utc::now().timestamp() - 600).abs() <= 1);
+    assert_eq!(offset(other), 0);
+    assert_eq!(skew(other).measured_at, None);
+}
+
+#[test]
+fn resync_needed_after_large_change() {
+    let api = "https://resync.clock.test/api/v1/tokens";
+    let other = "https://resync-other.clock.test/api/v1/tokens";
+    observe(api, &date(600));
+    assert!(resync_needed(api, 0));
+    assert!(!resync_needed(api, offset(api) - RESYNC_THRESHOLD + 1));
+    assert!(!resync_needed(other, 0));
+
+    record_resync(api);
+    assert_eq!(skew(api).resyncs, 1);
+    assert_eq!(skew(other).resyncs, 0);
+}
+
+#[test]
+fn missing_or_invalid_date_is_ignored() {
+    let api = "https://invalid-date.clock.test/api/v1/tokens";
+    assert_eq!(observe(api, &HeaderMap::new()), None);
+    let mut headers = HeaderMap::new();
+    headers.insert(reqwest::header::DATE, HeaderValue::from_static("yesterday"));
+    assert_eq!(observe(api, &headers), None);
+    assert_eq!(skew(api).measured_at, None);
+}
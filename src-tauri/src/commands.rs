@@ -1,31 +1,22 @@
 use crate::api;
+use crate::cancel;
+use crate::clock;
 use crate::crypto;
+use crate::endpoints;
+use crate::error::ApiError;
+use crate::metrics;
+use crate::profile;
+use crate::proxy::{self, ProxyConfig};
+use crate::push;
 use crate::security;
+use crate::singleflight::Group;
 use crate::storage::{self, Session};
+use crate::version::{self, UpdateChannel};
+use crate::watchdog;
 use serde_json::{json, Value};
 use tauri::Emitter;
 
-const CURRENT_VERSION: &str = "2.2.1";
-
-// 语义化版本比较：判断 server_ver 是否比 current_ver 新
-fn is_newer_version(server_ver: &str, current_ver: &str) -> bool {
-    let parse_version = |v: &str| -> Vec<u32> {
-        v.split('.')
-            .filter_map(|s| s.parse::<u32>().ok())
-            .collect()
-    };
-    
-    let sv = parse_version(server_ver);
-    let cv = parse_version(current_ver);
-    
-    for i in 0..sv.len().max(cv.len()) {
-        let s = sv.get(i).copied().unwrap_or(0);
-        let c = cv.get(i).copied().unwrap_or(0);
-        if s > c { return true; }
-        if s < c { return false; }
-    }
-    false
-}
+pub const CURRENT_VERSION: &str = "2.2.1";
 
 // UTF-8 安全的字符串前缀截取（防止多字节字符切片导致 panic）
 #[inline]
@@ -40,7 +31,9 @@ pub async fn activate_license(code: String) -> Result<Value, String> {
     #[cfg(debug_assertions)]
     println!("[activate_license] 激活码: {}, 设备ID: {}", &code, &device_id);
     
-    match api::activate_license(&code, &device_id).await {
+    // 激活期间退出登录时不再保存会话
+    let scope = cancel::session_scope();
+    match scope.run(api::activate_license(&code, &device_id)).await {
         Ok(response) => {
             #[cfg(debug_assertions)]
             println!("[activate_license] 响应: success={}", response.success);
@@ -53,6 +46,7 @@ pub async fn activate_license(code: String) -> Result<Value, String> {
                     #[cfg(debug_assertions)]
                     println!("[activate_license] 保存会话: token前10字符={}", safe_token_prefix(token, 10));
                     storage::save_code_session(&code, token, &device_id, response.expires_at);
+                    push::wake();
                 }
                 
                 // 保存 auto_switch 设置（兼容旧逻辑）
@@ -94,16 +88,10 @@ pub async fn activate_license(code: String) -> Result<Value, String> {
                     "hasBothLicenses": has_both
                 }))
             } else {
-                Ok(json!({
-                    "success": false,
-                    "error": response.error.unwrap_or_else(|| "激活失败".to_string())
-                }))
+                Ok(ApiError::server(response.code, response.error, "激活失败").to_response())
             }
         }
-        Err(e) => Ok(json!({
-            "success": false,
-            "error": e
-        }))
+        Err(e) => Ok(e.to_response())
     }
 }
 
@@ -121,6 +109,7 @@ pub fn get_session_status() -> Result<Value, String> {
 
 #[tauri::command]
 pub fn logout() -> Result<Value, String> {
+    cancel::bump_session();
     storage::clear_session();
     security::set_session_valid(false);
     
@@ -150,11 +139,14 @@ pub async fn unbind_and_clear() -> Result<Value, String> {
     }
     
     // 清除本地数据
+    cancel::bump_session();
     storage::clear_session();
     storage::clear_all_sessions();
     storage::clear_saved_codes();
     storage::clear_factory_auth();
+    storage::clear_dismissed_announcements();
     security::set_session_valid(false);
+    push::wake();
     
     Ok(json!({
         "success": true,
@@ -165,10 +157,13 @@ pub async fn unbind_and_clear() -> Result<Value, String> {
 #[tauri::command]
 pub fn clear_all_data() -> Result<Value, String> {
     // 清除所有会话
+    cancel::bump_session();
     storage::clear_session();
     storage::clear_all_sessions();
     storage::clear_saved_codes();
+    storage::clear_dismissed_announcements();
     security::set_session_valid(false);
+    push::wake();
     
     Ok(json!({
         "success": true
@@ -182,20 +177,18 @@ pub async fn get_token_list() -> Result<Value, String> {
     let session_token = session.session_token.ok_or("未登录")?;
     let device_id = session.device_id.ok_or("设备ID缺失")?;
     
-    match api::get_token_list(&session_token, &device_id).await {
+    let scope = cancel::session_scope();
+    match api::get_token_list(&session_token, &device_id, &scope).await {
         Ok(tokens) => Ok(json!({
             "success": true,
             "data": tokens
         })),
         Err(e) => {
-            if e == "SESSION_EXPIRED" {
+            if e.is_session_expired() {
                 storage::clear_session();
                 security::set_session_valid(false);
             }
-            Ok(json!({
-                "success": false,
-                "error": e
-            }))
+            Ok(e.to_response())
         }
     }
 }
@@ -220,17 +213,25 @@ pub async fn activate_token(token_id: String) -> Result<Value, String> {
         }));
     }
     
+    // 之前未完成的切换不再生效；退出登录或切换模式时中止本次切换
+    cancel::bump_active_token();
+    let scope = cancel::session_scope();
+    let token_scope = cancel::active_token_scope();
+    
     // 遍历所有会话，尝试激活
-    let mut last_error = String::new();
+    let mut last_error: Option<ApiError> = None;
     for session in sessions {
         #[cfg(debug_assertions)]
         println!("[activate_token] 尝试会话: {}, token前10字符: {}", &session.code, safe_token_prefix(&session.session_token, 10));
-        match api::activate_token(&session.session_token, &token_id, &session.device_id).await {
+        let result = token_scope.run(api::activate_token(&session.session_token, &token_id, &session.device_id, &scope)).await;
+        match result {
             Ok((access_token, refresh_token)) => {
                 #[cfg(debug_assertions)]
                 println!("[activate_token] 激活成功!");
                 // 写入本地 auth.json（包含 token_id 以便后续自动刷新）
                 let path = storage::sync_to_factory_auth_with_id(&access_token, &refresh_token, Some(&token_id))?;
+                // 推送通道改为订阅新的 token
+                push::wake();
                 
                 return Ok(json!({
                     "success": true,
@@ -239,11 +240,14 @@ pub async fn activate_token(token_id: String) -> Result<Value, String> {
             }
             Err(e) => {
                 #[cfg(debug_assertions)]
-                println!("[activate_token] 会话失败: {}", &e);
-                last_error = e.clone();
-                if e == "SESSION_EXPIRED" {
+                println!("[activate_token] 会话失败: {} ({:?})", &e, e.detail());
+                if e.is_session_expired() {
                     storage::remove_code_session(&session.code);
                 }
+                if matches!(e, ApiError::Cancelled) {
+                    return Ok(e.to_response());
+                }
+                last_error = Some(e);
                 // 继续尝试下一个会话
                 continue;
             }
@@ -252,11 +256,11 @@ pub async fn activate_token(token_id: String) -> Result<Value, String> {
     
     // 所有会话都失败
     #[cfg(debug_assertions)]
-    println!("[activate_token] 所有会话都失败, 最后错误: {}", &last_error);
-    Ok(json!({
-        "success": false,
-        "error": format!("激活失败: {}", last_error)
-    }))
+    println!("[activate_token] 所有会话都失败, 最后错误: {:?}", &last_error);
+    let e = last_error.unwrap_or_else(|| ApiError::server(None, None, "未知错误"));
+    let mut result = e.to_response();
+    result["error"] = json!(format!("激活失败: {}", e));
+    Ok(result)
 }
 
 #[tauri::command]
@@ -272,23 +276,25 @@ pub async fn get_subscription(token_id: String) -> Result<Value, String> {
     }
     
     // 遍历所有会话，尝试获取
+    let scope = cancel::session_scope();
     for session in sessions {
-        match api::activate_token(&session.session_token, &token_id, &session.device_id).await {
+        match api::activate_token(&session.session_token, &token_id, &session.device_id, &scope).await {
             Ok((access_token, _)) => {
                 // 查询余额
-                match api::get_subscription(&access_token).await {
-                    Ok(data) => return Ok(json!({
+                match scope.run(api::get_subscription(&access_token)).await {
+                    Ok((data, summary)) => return Ok(json!({
                         "success": true,
-                        "data": data
+                        "data": data,
+                        "summary": summary
                     })),
-                    Err(e) => return Ok(json!({
-                        "success": false,
-                        "error": e
-                    }))
+                    Err(e) => return Ok(e.to_response())
                 }
             }
             Err(e) => {
-                if e == "SESSION_EXPIRED" {
+                if matches!(e, ApiError::Cancelled) {
+                    return Ok(e.to_response());
+                }
+                if e.is_session_expired() {
                     storage::remove_code_session(&session.code);
                 }
                 continue;
@@ -306,12 +312,13 @@ pub async fn get_subscription(token_id: String) -> Result<Value, String> {
 pub async fn heartbeat() -> Result<Value, String> {
     // 优先使用文件存储的会话（应用重启后内存会话为空）
     let sessions = storage::get_all_valid_sessions();
+    let scope = cancel::session_scope();
     
     if sessions.is_empty() {
         // 尝试内存中的会话（兼容旧逻辑）
         let session = storage::get_session();
         if let (Some(session_token), Some(device_id)) = (session.session_token, session.device_id) {
-            match api::heartbeat(&session_token, &device_id).await {
+            match scope.run(api::heartbeat(&session_token, &device_id)).await {
                 Ok(resp) => {
                     if !resp.valid {
                         storage::clear_session();
@@ -321,6 +328,8 @@ pub async fn heartbeat() -> Result<Value, String> {
                     }
                     return Ok(json!({ "valid": resp.valid }));
                 }
+                // 已退出登录或切换模式，结果不代表当前会话
+                Err(ApiError::Cancelled) => return Ok(json!({ "valid": false, "cancelled": true })),
                 Err(_) => return Ok(json!({ "valid": false }))
             }
         }
@@ -329,7 +338,7 @@ pub async fn heartbeat() -> Result<Value, String> {
     
     // 使用第一个有效会话发送心跳
     let first_session = &sessions[0];
-    match api::heartbeat(&first_session.session_token, &first_session.device_id).await {
+    match scope.run(api::heartbeat(&first_session.session_token, &first_session.device_id)).await {
         Ok(resp) => {
             if !resp.valid {
                 // 会话无效，清理
@@ -341,6 +350,7 @@ pub async fn heartbeat() -> Result<Value, String> {
             }
             Ok(json!({ "valid": resp.valid }))
         }
+        Err(ApiError::Cancelled) => Ok(json!({ "valid": false, "cancelled": true })),
         Err(_) => Ok(json!({ "valid": false }))
     }
 }
@@ -358,6 +368,9 @@ pub async fn hide_window(window: tauri::Window) -> Result<(), String> {
 
 #[tauri::command]
 pub async fn exit_app(app: tauri::AppHandle) -> Result<(), String> {
+    // 先中止进行中的请求，避免恢复 auth.json 后又被改写
+    cancel::bump_session();
+    cancel::bump_active_token();
     storage::restore_factory_auth();
     app.exit(0);
     Ok(())
@@ -366,7 +379,8 @@ pub async fn exit_app(app: tauri::AppHandle) -> Result<(), String> {
 #[tauri::command]
 pub async fn get_app_info() -> Result<Value, String> {
     Ok(json!({
-        "version": CURRENT_VERSION
+        "version": CURRENT_VERSION,
+        "serverProfile": profile::active().summary()
     }))
 }
 
@@ -385,15 +399,12 @@ pub fn remove_saved_code(code: String) -> Result<Value, String> {
     Ok(json!({ "success": true }))
 }
 
-#[tauri::command]
-pub async fn get_all_tokens() -> Result<Value, String> {
+// 当前模式对应的有效会话
+pub(crate) fn current_mode_sessions() -> Result<Vec<storage::CodeSession>, &'static str> {
     let all_sessions = storage::get_all_valid_sessions();
     
     if all_sessions.is_empty() {
-        return Ok(json!({
-            "success": false,
-            "error": "无有效会话"
-        }));
+        return Err("无有效会话");
     }
     
     // 获取当前模式和对应的激活码
@@ -415,19 +426,43 @@ pub async fn get_all_tokens() -> Result<Value, String> {
     };
     
     if sessions.is_empty() {
-        return Ok(json!({
-            "success": false,
-            "error": "当前模式无有效会话"
-        }));
+        return Err("当前模式无有效会话");
     }
-    
+    Ok(sessions)
+}
+
+struct TokenFetch {
+    tokens: Vec<api::TokenInfo>,
+    errors: Vec<String>,
+    error_codes: Vec<&'static str>,
+    // 失败请求的 X-Request-ID
+    request_ids: Vec<String>,
+    // 所有请求都因服务器不可达而失败
+    offline: bool,
+    // 会话已过期的激活码
+    expired: Vec<String>,
+}
+
+impl TokenFetch {
+    // 删除过期的会话：请求期间退出登录或切换模式（会话代已变化）时不删除
+    fn remove_expired(&self, scope: &cancel::Scope) {
+        if !scope.is_current() {
+            return;
+        }
+        for code in &self.expired {
+            storage::remove_code_session(code);
+        }
+    }
+}
+
+async fn fetch_all_tokens(sessions: &[storage::CodeSession], scope: &cancel::Scope) -> TokenFetch {
     // 并行请求所有会话的 token 列表（大幅提升加载速度）
     let futures: Vec<_> = sessions.iter().map(|session| {
         let session_token = session.session_token.clone();
         let device_id = session.device_id.clone();
         let code = session.code.clone();
         async move {
-            let result = api::get_token_list(&session_token, &device_id).await;
+            let result = api::get_token_list(&session_token, &device_id, scope).await;
             (code, result)
         }
     }).collect();
@@ -436,10 +471,15 @@ pub async fn get_all_tokens() -> Result<Value, String> {
     
     let mut all_tokens: Vec<api::TokenInfo> = Vec::new();
     let mut errors: Vec<String> = Vec::new();
+    let mut error_codes: Vec<&'static str> = Vec::new();
+    let mut request_ids: Vec<String> = Vec::new();
+    let mut offline = true;
+    let mut expired: Vec<String> = Vec::new();
     
     for (code, result) in results {
         match result {
             Ok(tokens) => {
+                offline = false;
                 for token in tokens {
                     if !all_tokens.iter().any(|t| t.id == token.id) {
                         all_tokens.push(token);
@@ -447,25 +487,121 @@ pub async fn get_all_tokens() -> Result<Value, String> {
                 }
             }
             Err(e) => {
-                if e == "SESSION_EXPIRED" {
-                    storage::remove_code_session(&code);
-                }
+                offline &= e.is_offline();
                 errors.push(format!("{}: {}", code, e));
+                error_codes.push(e.code());
+                request_ids.extend(e.request_id().map(|id| id.to_string()));
+                if e.is_session_expired() {
+                    expired.push(code);
+                }
             }
         }
     }
     
-    if all_tokens.is_empty() && !errors.is_empty() {
-        return Ok(json!({
+    TokenFetch { tokens: all_tokens, errors, error_codes, request_ids, offline, expired }
+}
+
+// 在线获取结果转为命令返回值；全部成功时更新离线缓存
+fn token_fetch_response(cache_key: &str, fetched: TokenFetch) -> Value {
+    if fetched.tokens.is_empty() && !fetched.errors.is_empty() {
+        return json!({
             "success": false,
-            "error": errors.join(", ")
-        }));
+            "error": fetched.errors.join(", "),
+            "errorCode": fetched.error_codes.first(),
+            "errorCodes": fetched.error_codes,
+            "requestId": fetched.request_ids.first()
+        });
     }
     
-    Ok(json!({
+    // 部分激活码失败时不覆盖缓存，避免缓存被不完整的列表替换
+    let fetched_at = if fetched.errors.is_empty() {
+        storage::save_cached_tokens(cache_key, &fetched.tokens)
+    } else {
+        chrono::Utc::now().timestamp()
+    };
+    
+    json!({
         "success": true,
-        "data": all_tokens
-    }))
+        "data": fetched.tokens,
+        "stale": false,
+        "fetched_at": fetched_at
+    })
+}
+
+fn session_cache_key(sessions: &[storage::CodeSession]) -> String {
+    let codes: Vec<&str> = sessions.iter().map(|s| s.code.as_str()).collect();
+    storage::token_cache_key(&codes)
+}
+
+#[tauri::command]
+pub async fn get_all_tokens(app: tauri::AppHandle) -> Result<Value, String> {
+    let sessions = match current_mode_sessions() {
+        Ok(sessions) => sessions,
+        Err(e) => return Ok(json!({ "success": false, "error": e })),
+    };
+    let cache_key = session_cache_key(&sessions);
+    let scope = cancel::session_scope();
+    let fetched = match scope.run(async { Ok(fetch_all_tokens(&sessions, &scope).await) }).await {
+        Ok(fetched) => fetched,
+        Err(e) => return Ok(e.to_response()),
+    };
+    fetched.remove_expired(&scope);
+    
+    // 断网时返回缓存的列表，并在后台等待网络恢复后刷新
+    if fetched.tokens.is_empty() && fetched.offline && !fetched.errors.is_empty() {
+        if let Some(cached) = storage::load_cached_tokens(&cache_key) {
+            start_offline_refresh(app);
+            return Ok(json!({
+                "success": true,
+                "data": cached.tokens,
+                "stale": true,
+                "fetched_at": cached.fetched_at,
+                "errorCode": fetched.error_codes.first()
+            }));
+        }
+    }
+    
+    Ok(token_fetch_response(&cache_key, fetched))
+}
+
+lazy_static::lazy_static! {
+    static ref OFFLINE_REFRESHING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    // 定时器、推送和手动刷新经常重叠：同一 token 的并发刷新共享一次执行，避免并发写入 auth.json
+    static ref ACTIVE_TOKEN_REFRESHES: Group<Result<Value, String>> = Group::new();
+}
+
+// 离线期间在后台重试，网络恢复后推送 tokens-refreshed 事件（结构与 get_all_tokens 相同）
+fn start_offline_refresh(app: tauri::AppHandle) {
+    use std::sync::atomic::Ordering;
+    if OFFLINE_REFRESHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    
+    tauri::async_runtime::spawn(async move {
+        let mut delay = std::time::Duration::from_secs(5);
+        loop {
+            tokio::time::sleep(delay).await;
+            
+            // 期间可能已退出登录或切换模式，每次都重新读取会话；请求期间切换时丢弃结果，下一轮按新会话重试
+            let Ok(sessions) = current_mode_sessions() else {
+                break;
+            };
+            let scope = cancel::session_scope();
+            let Ok(fetched) = scope.run(async { Ok(fetch_all_tokens(&sessions, &scope).await) }).await else {
+                continue;
+            };
+            fetched.remove_expired(&scope);
+            if !fetched.offline {
+                #[cfg(debug_assertions)]
+                println!("[Offline] 网络已恢复，刷新 Token 列表");
+                let response = token_fetch_response(&session_cache_key(&sessions), fetched);
+                let _ = app.emit("tokens-refreshed", response);
+                break;
+            }
+            delay = (delay * 2).min(std::time::Duration::from_secs(60));
+        }
+        OFFLINE_REFRESHING.store(false, Ordering::SeqCst);
+    });
 }
 
 // 自动刷新当前激活的 token（检查服务器版本，版本变化立即同步）
@@ -477,6 +613,18 @@ pub async fn refresh_active_token(force: bool) -> Result<Value, String> {
         None => return Ok(json!({ "success": false, "error": "无激活的token" }))
     };
     
+    // 刷新期间切换账号、退出登录或切换模式时不再写入 auth.json；切换后的刷新不合并到之前的刷新
+    let scope = cancel::session_scope();
+    let token_scope = cancel::active_token_scope();
+    let key = format!("{}:{}:{}", token_id, force, token_scope.generation());
+    let session_scope = scope.clone();
+    ACTIVE_TOKEN_REFRESHES
+        .run(key, &scope, move || async move { Ok(refresh_token(token_id, force, session_scope, token_scope).await) })
+        .await
+        .unwrap_or_else(|e| Ok(e.to_response()))
+}
+
+async fn refresh_token(token_id: String, force: bool, scope: cancel::Scope, token_scope: cancel::Scope) -> Result<Value, String> {
     let local_updated_at = storage::get_auth_updated_at().unwrap_or(0);
     
     // 获取有效会话
@@ -490,14 +638,17 @@ pub async fn refresh_active_token(force: bool) -> Result<Value, String> {
     let mut valid_session: Option<&storage::CodeSession> = None;
     
     for session in &sessions {
-        match api::check_token_version(&session.session_token, &token_id, &session.device_id).await {
+        match token_scope.run(api::check_token_version(&session.session_token, &token_id, &session.device_id, &scope)).await {
             Ok(updated_at) => {
                 server_updated_at = updated_at;
                 valid_session = Some(session);
                 break;
             }
             Err(e) => {
-                if e == "SESSION_EXPIRED" {
+                if matches!(e, ApiError::Cancelled) {
+                    return Ok(e.to_response());
+                }
+                if e.is_session_expired() {
                     storage::remove_code_session(&session.code);
                 }
                 continue;
@@ -525,7 +676,7 @@ pub async fn refresh_active_token(force: bool) -> Result<Value, String> {
     println!("[refresh_active_token] 服务器有更新，开始同步 token_id: {}", &token_id);
     
     // 从服务器获取最新 token
-    match api::activate_token(&session.session_token, &token_id, &session.device_id).await {
+    match token_scope.run(api::activate_token(&session.session_token, &token_id, &session.device_id, &scope)).await {
         Ok((access_token, refresh_token)) => {
             storage::sync_to_factory_auth_with_id(&access_token, &refresh_token, Some(&token_id))?;
             #[cfg(debug_assertions)]
@@ -534,52 +685,135 @@ pub async fn refresh_active_token(force: bool) -> Result<Value, String> {
         }
         Err(e) => {
             #[cfg(debug_assertions)]
-            println!("[refresh_active_token] 同步失败: {}", &e);
-            return Ok(json!({ "success": false, "error": e }));
+            println!("[refresh_active_token] 同步失败: {} ({:?})", &e, e.detail());
+            return Ok(e.to_response());
         }
     }
 }
 
 // 检查客户端更新
 #[tauri::command]
-pub async fn check_update() -> Result<Value, String> {
-    match api::check_update().await {
+pub async fn check_update(manual: Option<bool>) -> Result<Value, String> {
+    let channel = storage::get_update_channel();
+    match api::check_update(CURRENT_VERSION, channel).await {
         Ok(info) => {
-            if !info.has_update || info.version.is_none() {
+            let Some(server_version) = info.version.as_deref().filter(|_| info.has_update) else {
                 return Ok(json!({ "hasUpdate": false }));
-            }
-            
-            let server_version = info.version.as_ref().unwrap();
-            let sv = server_version.trim_start_matches('v');
-            let cv = CURRENT_VERSION.trim_start_matches('v');
+            };
             
-            if !is_newer_version(sv, cv) {
+            if !version::is_newer(server_version, CURRENT_VERSION) {
+                // 从测试版切回正式版：不自动降级，由前端确认后再安装
+                if downgrade_allowed(server_version, channel) {
+                    return Ok(json!({
+                        "hasUpdate": false,
+                        "downgrade": {
+                            "version": info.version,
+                            "changelog": info.changelog,
+                            "size": info.size,
+                            "downloadUrl": info.download_url
+                        }
+                    }));
+                }
                 return Ok(json!({ "hasUpdate": false }));
             }
             
+            // 安装后未能正常启动并已回滚的版本：自动检查时不再提示，手动检查时提示之前失败过
+            let previously_failed = watchdog::failed_versions().iter().any(|v| version::is_same(v, server_version));
+            if previously_failed && manual != Some(true) {
+                return Ok(json!({ "hasUpdate": false, "skippedVersion": info.version }));
+            }
+            
             Ok(json!({
                 "hasUpdate": true,
+                "previouslyFailed": previously_failed,
                 "version": info.version,
                 "changelog": info.changelog,
                 "size": info.size,
                 "forceUpdate": info.force_update.unwrap_or(false),
-                "downloadUrl": info.download_url
+                "downloadUrl": info.download_url,
+                // 有增量补丁时前端显示补丁大小
+                "patchSize": applicable_patch(&info).and_then(|p| p.size)
             }))
         }
         Err(e) => {
-            Ok(json!({ "hasUpdate": false, "error": e }))
+            Ok(json!({ "hasUpdate": false, "error": e.to_string(), "errorCode": e.code(), "requestId": e.request_id() }))
+        }
+    }
+}
+
+// 当前运行测试版且已切回正式版频道时，允许（经用户确认）安装较旧的正式版
+fn downgrade_allowed(target: &str, channel: UpdateChannel) -> bool {
+    channel == UpdateChannel::Stable
+        && version::is_prerelease(CURRENT_VERSION)
+        && !version::is_prerelease(target)
+        && version::is_newer(CURRENT_VERSION, target)
+}
+
+// 获取更新频道
+#[tauri::command]
+pub fn get_update_channel() -> Result<Value, String> {
+    Ok(json!({ "success": true, "channel": storage::get_update_channel(), "currentVersion": CURRENT_VERSION }))
+}
+
+// 设置更新频道（stable / beta）
+#[tauri::command]
+pub fn set_update_channel(channel: String) -> Result<Value, String> {
+    match UpdateChannel::parse(&channel) {
+        Some(channel) => match storage::save_update_channel(channel) {
+            Ok(()) => Ok(json!({ "success": true, "channel": channel })),
+            Err(e) => Ok(json!({ "success": false, "error": e })),
+        },
+        None => Ok(json!({ "success": false, "error": format!("未知的更新频道: {}", channel) })),
+    }
+}
+
+// 适用于当前版本的增量补丁
+fn applicable_patch(info: &api::UpdateInfo) -> Option<&api::PatchInfo> {
+    info.patch.as_ref().filter(|p| version::is_same(&p.from, CURRENT_VERSION))
+}
+
+// 公告是否应当显示：在有效期内且适用于当前版本
+fn announcement_visible(announcement: &api::Announcement, now: i64, current_ver: &str) -> bool {
+    announcement.starts_at.is_none_or(|t| now >= t)
+        && announcement.ends_at.is_none_or(|t| now < t)
+        && announcement.min_version.as_deref().is_none_or(|min| !version::is_newer(min, current_ver))
+        && announcement.max_version.as_deref().is_none_or(|max| !version::is_newer(current_ver, max))
+}
+
+// 获取需要显示的公告（过滤已过期、不适用于当前版本和已关闭的公告）
+#[tauri::command]
+pub async fn get_announcements() -> Result<Value, String> {
+    match api::get_announcements().await {
+        Ok(announcements) => {
+            let now = chrono::Utc::now().timestamp();
+            let dismissed = storage::dismissed_announcement_ids();
+            let current_ver = CURRENT_VERSION.trim_start_matches('v');
+            let visible: Vec<api::Announcement> = announcements
+                .into_iter()
+                .filter(|a| !dismissed.contains(&a.id) && announcement_visible(a, now, current_ver))
+                .collect();
+            Ok(json!({ "success": true, "data": visible }))
         }
+        Err(e) => Ok(e.to_response()),
     }
 }
 
+// 关闭公告（加密保存，之后不再显示）
+#[tauri::command]
+pub fn dismiss_announcement(id: String) -> Result<Value, String> {
+    if id.trim().is_empty() {
+        return Ok(json!({ "success": false, "error": "公告 ID 为空" }));
+    }
+    storage::dismiss_announcement(&id);
+    Ok(json!({ "success": true }))
+}
+
 // 打开浏览器下载更新
 #[tauri::command]
 pub async fn open_download_url(download_url: String) -> Result<Value, String> {
     // 构建完整 URL
     let full_url = if download_url.starts_with("http") {
         download_url
-    } else if download_url.starts_with("/client/") {
-        format!("{}{}", api::get_api_base(), download_url)
     } else {
         format!("{}{}", api::get_api_base(), download_url)
     };
@@ -595,84 +829,115 @@ pub async fn open_download_url(download_url: String) -> Result<Value, String> {
 #[tauri::command]
 pub async fn download_and_update(
     app: tauri::AppHandle,
-    download_url: String,
-) -> Result<Value, String> {
+    allow_downgrade: Option<bool>,
+) -> Result<Value, ApiError> {
     // macOS 暂不支持自动更新，请手动下载
     #[cfg(target_os = "macos")]
     {
-        return Err("macOS 版本请手动下载更新".to_string());
+        return Err(ApiError::server(None, None, "macOS 版本请手动下载更新"));
     }
     
     #[cfg(not(target_os = "macos"))]
     {
-    use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
-    
     // 获取当前 exe 路径
     let current_exe = std::env::current_exe()
-        .map_err(|e| format!("获取程序路径失败: {}", e))?;
+        .map_err(|e| ApiError::Io(format!("获取程序路径失败: {}", e)))?;
     let exe_dir = current_exe.parent()
-        .ok_or("无法获取程序目录")?;
+        .ok_or_else(|| ApiError::Io("无法获取程序目录".to_string()))?;
     let exe_name = current_exe.file_name()
-        .ok_or("无法获取程序名称")?;
+        .ok_or_else(|| ApiError::Io("无法获取程序名称".to_string()))?;
     
     // 定义路径
     let new_exe_path = exe_dir.join("update_new.exe");
+    let patch_path = exe_dir.join("update_patch.bin");
     let old_exe_path = exe_dir.join(format!("{}.old", exe_name.to_string_lossy()));
     
-    // 进度跟踪
-    let downloaded = Arc::new(AtomicU64::new(0));
-    let total = Arc::new(AtomicU64::new(0));
-    let downloaded_clone = Arc::clone(&downloaded);
-    let total_clone = Arc::clone(&total);
-    let app_clone = app.clone();
-    
-    // 进度回调
-    let progress_callback = move |dl: u64, tl: u64| {
-        downloaded_clone.store(dl, Ordering::SeqCst);
-        total_clone.store(tl, Ordering::SeqCst);
-        // 发送进度事件到前端
-        let _ = app_clone.emit("update-progress", json!({
-            "downloaded": dl,
-            "total": tl,
-            "percent": if tl > 0 { (dl as f64 / tl as f64 * 100.0) as u32 } else { 0 }
-        }));
+    // 进度回调（delta 表示正在下载增量补丁）
+    let progress = |delta: bool| {
+        let app = app.clone();
+        move |dl: u64, tl: u64| {
+            // 发送进度事件到前端
+            let _ = app.emit("update-progress", json!({
+                "downloaded": dl,
+                "total": tl,
+                "percent": if tl > 0 { (dl as f64 / tl as f64 * 100.0) as u32 } else { 0 },
+                "delta": delta
+            }));
+        }
     };
     
-    // 下载新版本
-    api::download_update(&download_url, &new_exe_path, progress_callback).await?;
+    // 重新获取版本信息并校验签名（不信任前端传入的版本信息）
+    let channel = storage::get_update_channel();
+    let info = api::check_update(CURRENT_VERSION, channel).await?;
+    let release = crate::update::verify_release(&info, profile::active().update_public_key.as_deref())?;
+    // 防止用旧版本的合法签名降级：只有切回正式版频道且用户确认时才允许安装较旧的版本
+    let downgrade = allow_downgrade == Some(true) && downgrade_allowed(&release.version, channel);
+    if !version::is_newer(&release.version, CURRENT_VERSION) && !downgrade {
+        return Err(ApiError::UpdateVerification(format!("版本 {} 不比当前版本新", release.version)));
+    }
     
-    // 验证下载的文件
-    let metadata = std::fs::metadata(&new_exe_path)
-        .map_err(|e| format!("验证下载文件失败: {}", e))?;
-    if metadata.len() < 1024 * 100 {
-        // 文件小于 100KB，可能下载失败
-        let _ = std::fs::remove_file(&new_exe_path);
-        return Err("下载的文件异常，请重试".to_string());
+    // 优先使用增量补丁：应用到当前程序的副本，结果与签名摘要一致才算成功，任何失败都改为完整下载
+    let mut patched = false;
+    if let Some(patch) = applicable_patch(&info) {
+        let result = match api::download_update(&patch.download_url, &patch_path, progress(true)).await {
+            Ok(()) => crate::update::apply_patch(&current_exe, &patch_path, &new_exe_path, &release),
+            Err(e) => Err(e),
+        };
+        let _ = std::fs::remove_file(&patch_path);
+        match result {
+            Ok(()) => patched = true,
+            Err(e) => {
+                #[cfg(debug_assertions)]
+                println!("[download_and_update] 增量更新失败，改为完整下载: {} ({:?})", &e, e.detail());
+                #[cfg(not(debug_assertions))]
+                let _ = e;
+                let _ = std::fs::remove_file(&new_exe_path);
+            }
+        }
+    }
+    
+    if !patched {
+        // 下载新版本（使用刚重新获取的版本信息中的地址，不使用前端传入的地址）
+        let download_url = info.download_url.as_deref()
+            .ok_or_else(|| ApiError::UpdateVerification("缺少下载地址".to_string()))?;
+        api::download_update(download_url, &new_exe_path, progress(false)).await?;
+        
+        // 校验下载的文件摘要，不一致时删除且不安装
+        if let Err(e) = crate::update::verify_file(&new_exe_path, &release) {
+            let _ = std::fs::remove_file(&new_exe_path);
+            return Err(e);
+        }
     }
     
     // 重命名策略：
     // 1. 当前运行的 exe 重命名为 .old（Windows 允许重命名运行中的文件）
     // 2. 新下载的 exe 重命名为原名称
-    // 3. 启动新版本
+    // 3. 从 .old 启动看门狗，由看门狗启动新版本，新版本未能正常启动时恢复 .old
     // 4. 退出当前程序
     
     // 步骤1：重命名当前 exe 为 .old
     if let Err(e) = std::fs::rename(&current_exe, &old_exe_path) {
         let _ = std::fs::remove_file(&new_exe_path);
-        return Err(format!("重命名当前程序失败: {}，请关闭其他可能占用的程序", e));
+        return Err(ApiError::Io(format!("重命名当前程序失败: {}，请关闭其他可能占用的程序", e)));
     }
     
     // 步骤2：重命名新 exe 为当前名称
     if let Err(e) = std::fs::rename(&new_exe_path, &current_exe) {
         // 回滚：恢复原来的 exe
         let _ = std::fs::rename(&old_exe_path, &current_exe);
-        return Err(format!("安装新版本失败: {}", e));
+        return Err(ApiError::Io(format!("安装新版本失败: {}", e)));
     }
     
-    // 步骤3：启动新版本
-    let _ = std::process::Command::new(&current_exe)
-        .spawn()
-        .map_err(|e| format!("启动新版本失败: {}", e))?;
+    // 步骤3：启动看门狗；看门狗无法启动时直接启动新版本（不做启动检查）
+    if let Err(e) = watchdog::begin(&release.version, CURRENT_VERSION, &current_exe, &old_exe_path) {
+        #[cfg(debug_assertions)]
+        println!("[download_and_update] 启动看门狗失败，直接启动新版本: {}", e);
+        #[cfg(not(debug_assertions))]
+        let _ = e;
+        let _ = std::process::Command::new(&current_exe)
+            .spawn()
+            .map_err(|e| ApiError::Io(format!("启动新版本失败: {}", e)))?;
+    }
     
     // 步骤4：退出当前程序
     storage::restore_factory_auth();
@@ -686,21 +951,26 @@ pub async fn download_and_update(
 pub fn cleanup_old_version() {
     if let Ok(current_exe) = std::env::current_exe() {
         if let Some(exe_dir) = current_exe.parent() {
-            // 清理 .old 文件
+            // 清理 .old 和 .failed（回滚后的新版本）文件
+            // 启动检查未完成时 .old 是回滚用的备份，保留到下次启动
+            let keep_backup = watchdog::has_pending();
             if let Ok(entries) = std::fs::read_dir(exe_dir) {
                 for entry in entries.flatten() {
                     let path = entry.path();
                     if let Some(ext) = path.extension() {
-                        if ext == "old" {
+                        if (ext == "old" && !keep_backup) || ext == "failed" {
                             let _ = std::fs::remove_file(&path);
                         }
                     }
                 }
             }
-            // 清理 update_new.exe（下载中断的残留）
-            let new_exe = exe_dir.join("update_new.exe");
-            if new_exe.exists() {
-                let _ = std::fs::remove_file(&new_exe);
+            // 清理 update_new.exe（下载完成但未安装的残留）和增量补丁（含中断的补丁下载）
+            // 中断的完整包下载保存在 update_new.exe.part，保留用于下次续传；补丁很小，重新下载即可
+            for name in ["update_new.exe", "update_patch.bin", "update_patch.bin.part", "update_patch.bin.part.json"] {
+                let path = exe_dir.join(name);
+                if path.exists() {
+                    let _ = std::fs::remove_file(&path);
+                }
             }
         }
     }
@@ -902,7 +1172,12 @@ pub fn set_current_mode(mode: String) -> Result<Value, String> {
             "error": "无效的模式"
         }));
     }
+    // 切换模式时中止旧模式下进行中的请求
+    if storage::get_current_mode() != mode {
+        cancel::bump_session();
+    }
     storage::save_current_mode(&mode);
+    push::wake();
     Ok(json!({
         "success": true,
         "mode": mode
@@ -923,11 +1198,13 @@ pub fn get_current_mode() -> Result<Value, String> {
 // 清除所有激活码（完全退出登录）
 #[tauri::command]
 pub fn clear_all_licenses() -> Result<Value, String> {
+    cancel::bump_session();
     storage::clear_all_licenses();
     storage::clear_session();
     storage::clear_all_sessions();
     storage::clear_saved_codes();
     security::set_session_valid(false);
+    push::wake();
     Ok(json!({ "success": true }))
 }
 
@@ -951,3 +1228,78 @@ pub fn get_license_code(mode: String) -> Result<Value, String> {
         }))
     }
 }
+
+// ==================== 代理设置 ====================
+
+// 获取代理设置（不返回密码）
+#[tauri::command]
+pub fn get_proxy_config() -> Result<Value, String> {
+    Ok(json!({
+        "success": true,
+        "config": proxy::load().summary()
+    }))
+}
+
+// 前端不持有已保存的密码：password 为 null 时沿用已保存的密码，为空字符串时清除
+fn merge_saved_password(mut config: ProxyConfig) -> ProxyConfig {
+    if config.password.is_none() {
+        let saved = proxy::load();
+        if saved.username == config.username {
+            config.password = saved.password;
+        }
+    }
+    config
+}
+
+// 保存代理设置，之后的所有请求（包括 Factory API 和更新下载）立即生效
+#[tauri::command]
+pub fn set_proxy_config(config: ProxyConfig) -> Result<Value, String> {
+    let config = merge_saved_password(config);
+    if let Err(e) = proxy::save(&config) {
+        return Ok(json!({ "success": false, "error": e }));
+    }
+    api::rebuild_http_client();
+    Ok(json!({
+        "success": true,
+        "config": config.summary()
+    }))
+}
+
+// 通过代理测试服务器连接；不传 config 时测试已保存的设置
+#[tauri::command]
+pub async fn test_proxy_connection(config: Option<ProxyConfig>) -> Result<Value, String> {
+    let config = match config {
+        Some(config) => merge_saved_password(config),
+        None => proxy::load(),
+    };
+    match api::test_connection(&config).await {
+        Ok((status, latency)) => Ok(json!({
+            "success": true,
+            "status": status,
+            "latencyMs": latency as u64
+        })),
+        Err(ApiError::Http(407)) => Ok(json!({
+            "success": false,
+            "error": "代理认证失败，请检查用户名和密码",
+            "errorCode": "PROXY_AUTH_REQUIRED"
+        })),
+        Err(e) => Ok(e.to_response()),
+    }
+}
+
+// ==================== 网络统计 ====================
+
+// 各接口的请求统计（成功/失败次数、重试次数、耗时分布），reset 为 true 时导出后清零
+#[tauri::command]
+pub fn get_network_stats(reset: Option<bool>) -> Result<Value, String> {
+    let mut stats = metrics::snapshot(reset.unwrap_or(false));
+    // 与服务器的时钟偏差（签名时间戳已按此校正）
+    stats["clockSkew"] = json!(clock::skew(&api::get_api_base()));
+    // 各服务器地址的健康状况和当前使用的地址（生产环境不包含地址本身）
+    let server = profile::active();
+    stats["apiEndpoints"] = endpoints::snapshot(&server.api_bases(), server.name != profile::ProfileName::Production);
+    Ok(json!({
+        "success": true,
+        "stats": stats
+    }))
+}
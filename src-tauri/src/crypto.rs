@@ -3,9 +3,8 @@ use aes_gcm::{
     Aes256Gcm, Nonce, KeyInit as AesKeyInit,
 };
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 use rand::Rng;
-use base64::Engine as _;
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -52,8 +51,114 @@ pub fn generate_signature(data: &str, timestamp: i64, device_id: &str) -> String
 }
 
 pub fn verify_signature(data: &str, timestamp: i64, device_id: &str, signature: &str) -> bool {
-    let expected = generate_signature(data, timestamp, device_id);
-    expected == signature
+    let message = format!("{}|{}|{}", data, timestamp, device_id);
+    verify_hmac(message.as_bytes(), signature)
+}
+
+// 常量时间比较 HMAC，避免通过响应时间逐字节猜测签名
+fn verify_hmac(message: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let key = get_encryption_key();
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(&key).expect("HMAC key error");
+    mac.update(message);
+    mac.verify_slice(&signature).is_ok()
+}
+
+// ==================== 签名 v2 ====================
+// 对规范化请求签名：方法、路径、查询参数、请求体 SHA-256、时间戳、随机数、设备 ID
+// 签名随请求头发送，X-Signature-Version 标记版本；不带版本头的请求按 v1 校验
+
+pub const SIGNATURE_VERSION: &str = "2";
+
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub body: &'a [u8],
+    pub timestamp: i64,
+    pub nonce: &'a str,
+    pub device_id: &'a str,
+}
+
+impl SignedRequest<'_> {
+    fn canonical(&self) -> String {
+        format!(
+            "v2\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.method.to_ascii_uppercase(),
+            self.path,
+            self.query,
+            hex::encode(Sha256::digest(self.body)),
+            self.timestamp,
+            self.nonce,
+            self.device_id
+        )
+    }
+}
+
+pub fn generate_signature_v2(request: &SignedRequest) -> String {
+    let key = get_encryption_key();
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(&key).expect("HMAC key error");
+    mac.update(request.canonical().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_signature_v2(request: &SignedRequest, signature: &str) -> bool {
+    verify_hmac(request.canonical().as_bytes(), signature)
+}
+
+// ==================== 响应 MAC ====================
+// 服务器对响应状态码、时间戳、请求随机数和响应体签名，防止响应被篡改或重放
+
+pub fn generate_response_mac(status: u16, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    let message = response_message(status, timestamp, nonce, body);
+    let key = get_encryption_key();
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(&key).expect("HMAC key error");
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_response_mac(status: u16, timestamp: i64, nonce: &str, body: &[u8], signature: &str) -> bool {
+    verify_hmac(response_message(status, timestamp, nonce, body).as_bytes(), signature)
+}
+
+fn response_message(status: u16, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    format!("resp-v1\n{}\n{}\n{}\n{}", status, timestamp, nonce, hex::encode(Sha256::digest(body)))
+}
+
+// ==================== 更新包签名 ====================
+// 发布服务器用 Ed25519 私钥对 (版本号, 文件名, SHA-256) 签名，客户端用内置公钥校验
+// 签名覆盖文件名和版本号，防止用其他版本的合法安装包替换
+
+pub fn update_signing_message(version: &str, filename: &str, sha256_hex: &str) -> String {
+    format!("atm-update-v1\n{}\n{}\n{}", version, filename, sha256_hex.to_ascii_lowercase())
+}
+
+// public_key 和 signature 均为 base64
+pub fn verify_update_signature(public_key: &str, message: &str, signature: &str) -> Result<(), String> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    let key: [u8; 32] = STANDARD
+        .decode(public_key.trim())
+        .ok()
+        .and_then(|k| k.try_into().ok())
+        .ok_or("更新签名公钥格式错误")?;
+    let key = VerifyingKey::from_bytes(&key).map_err(|_| "更新签名公钥无效")?;
+    let signature: [u8; 64] = STANDARD
+        .decode(signature.trim())
+        .ok()
+        .and_then(|s| s.try_into().ok())
+        .ok_or("更新签名格式错误")?;
+    key.verify_strict(message.as_bytes(), &Signature::from_bytes(&signature))
+        .map_err(|_| "更新签名校验失败".to_string())
+}
+
+// 每个请求唯一的随机数（128 位）
+pub fn generate_nonce() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    hex::encode(bytes)
 }
 
 const MAX_ENCRYPTED_DATA_LEN: usize = 10 * 1024 * 1024; // 10MB 最大限制，防止 DoS
@@ -0,0 +1,146 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// ==================== 服务器地址故障切换 ====================
+// 服务器环境可配置多个地址（主地址在前），按请求结果被动记录每个地址的健康状况：
+// - 连接失败、超时或网关错误（502/503/504）连续 FAILURE_THRESHOLD 次后，该地址暂停使用 COOLDOWN
+// - 每次请求使用第一个可用的地址，全部暂停时使用最先恢复的地址
+// - 暂停结束后主地址重新优先，再次失败立即重新暂停，成功后清零
+// retry::send 按每次尝试的请求地址记录结果，地址刚被暂停时立即改用下一个地址重试（签名随请求重新生成）
+
+// 连续失败多少次后暂停使用
+const FAILURE_THRESHOLD: u32 = 2;
+// 暂停时长
+pub(crate) const COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default)]
+struct Health {
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+}
+
+impl Health {
+    fn is_down(&self, now: Instant) -> bool {
+        self.down_until.is_some_and(|until| until > now)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // 用过的地址列表（按优先级），生产环境只有当前服务器环境这一组
+    lists: Vec<Vec<String>>,
+    health: HashMap<String, Health>,
+}
+
+impl State {
+    fn health(&self, base: &str) -> Health {
+        self.health.get(base).cloned().unwrap_or_default()
+    }
+
+    // 第一个可用的地址；全部暂停时取最先恢复的
+    fn selected(&self, bases: &[String], now: Instant) -> Option<usize> {
+        (0..bases.len())
+            .find(|i| !self.health(&bases[*i]).is_down(now))
+            .or_else(|| (0..bases.len()).min_by_key(|i| self.health(&bases[*i]).down_until))
+    }
+
+    // 本次请求使用的地址，并登记地址列表（bases 不能为空）
+    fn select(&mut self, bases: &[String], now: Instant) -> String {
+        if !self.lists.iter().any(|list| list == bases) {
+            self.lists.push(bases.to_vec());
+        }
+        self.selected(bases, now).map_or_else(|| bases[0].clone(), |i| bases[i].clone())
+    }
+
+    // 请求地址对应的服务器地址（不属于任何地址列表时为 None，如 Factory 接口）
+    fn matching(&self, url: &str) -> Option<String> {
+        self.lists
+            .iter()
+            .flatten()
+            .filter(|base| url.strip_prefix(base.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?'])))
+            .max_by_key(|base| base.len())
+            .cloned()
+    }
+
+    // 记录一次尝试的结果，返回该地址是否刚被暂停且同组有其他可用地址
+    fn record(&mut self, url: &str, failed: bool, now: Instant, cooldown: Duration) -> bool {
+        let Some(base) = self.matching(url) else {
+            return false;
+        };
+        if !failed {
+            self.health.remove(&base);
+            return false;
+        }
+        let health = self.health.entry(base.clone()).or_default();
+        let was_down = health.is_down(now);
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= FAILURE_THRESHOLD {
+            health.down_until = Some(now + cooldown);
+        }
+        if was_down || !health.is_down(now) {
+            return false;
+        }
+        #[cfg(debug_assertions)]
+        println!("[Endpoints] {} 连续失败，暂停使用 {}ms", base, cooldown.as_millis());
+        self.lists
+            .iter()
+            .filter(|list| list.contains(&base))
+            .flatten()
+            .any(|other| *other != base && !self.health(other).is_down(now))
+    }
+
+    fn snapshot(&self, bases: &[String], now: Instant, expose_urls: bool) -> Value {
+        let endpoints: Vec<Value> = bases
+            .iter()
+            .enumerate()
+            .map(|(index, base)| {
+                let health = self.health(base);
+                json!({
+                    "index": index,
+                    "url": if expose_urls { Some(base) } else { None },
+                    "healthy": !health.is_down(now),
+                    "consecutiveFailures": health.consecutive_failures,
+                    "downForMs": health.down_until.map(|until| until.saturating_duration_since(now).as_millis() as u64)
+                })
+            })
+            .collect();
+        json!({
+            "selected": self.selected(bases, now),
+            "endpoints": endpoints
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// 本次请求使用的服务器地址（bases 为当前服务器环境的地址列表，不能为空）
+pub fn select(bases: &[String]) -> String {
+    state().select(bases, Instant::now())
+}
+
+// 请求地址是否属于我们的服务器（Factory 等第三方接口不属于）
+pub(crate) fn is_ours(url: &str) -> bool {
+    state().matching(url).is_some()
+}
+
+// 记录一次尝试的结果：failed 为连接失败、超时或网关错误
+// 返回是否应立即改用其他地址重试（地址刚被暂停且有其他可用地址）
+pub(crate) fn record(url: &str, failed: bool) -> bool {
+    state().record(url, failed, Instant::now(), crate::retry::scaled(COOLDOWN))
+}
+
+// 诊断信息：各地址的健康状况和当前选择的地址序号，expose_urls 为 false 时不包含地址
+pub fn snapshot(bases: &[String], expose_urls: bool) -> Value {
+    state().snapshot(bases, Instant::now(), expose_urls)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,94 @@
+// 故障切换测试：连续失败暂停地址、暂停结束后主地址恢复优先、全部暂停时的选择，以及哪些请求地址计入
+// 直接操作 State，时间由参数传入，不需要等待暂停时长
+
+use super::*;
+
+const PRIMARY: &str = "https://primary.example.com/api/v1";
+const SECONDARY: &str = "https://secondary.example.com/api/v1";
+
+fn bases() -> Vec<String> {
+    vec![PRIMARY.to_string(), SECONDARY.to_string()]
+}
+
+fn url(base: &str) -> String {
+    format!("{}/tokens", base)
+}
+
+// 按地址列表选择一次（登记地址列表）
+fn state(now: Instant) -> State {
+    let mut state = State::default();
+    assert_eq!(state.select(&bases(), now), PRIMARY);
+    state
+}
+
+#[test]
+fn consecutive_failures_mark_endpoint_down() {
+    let now = Instant::now();
+    let mut s = state(now);
+
+    // 未达到阈值时继续使用主地址，成功后清零
+    assert!(!s.record(&url(PRIMARY), true, now, COOLDOWN));
+    assert!(!s.record(&url(PRIMARY), false, now, COOLDOWN));
+    assert!(!s.record(&url(PRIMARY), true, now, COOLDOWN));
+    assert_eq!(s.select(&bases(), now), PRIMARY);
+
+    // 连续失败达到阈值：暂停主地址，立即切换到备用地址
+    assert!(s.record(&url(PRIMARY), true, now, COOLDOWN));
+    assert_eq!(s.select(&bases(), now), SECONDARY);
+    let snapshot = s.snapshot(&bases(), now, false);
+    assert_eq!(snapshot["selected"], 1);
+    assert_eq!(snapshot["endpoints"][0]["healthy"], false);
+    assert_eq!(snapshot["endpoints"][0]["url"], Value::Null);
+    assert_eq!(snapshot["endpoints"][1]["healthy"], true);
+}
+
+#[test]
+fn recovered_primary_is_preferred_again() {
+    let now = Instant::now();
+    let mut s = state(now);
+    s.record(&url(PRIMARY), true, now, COOLDOWN);
+    s.record(&url(PRIMARY), true, now, COOLDOWN);
+    assert_eq!(s.select(&bases(), now + COOLDOWN / 2), SECONDARY);
+
+    // 暂停结束后主地址重新优先；再次失败立即重新暂停
+    let later = now + COOLDOWN + Duration::from_secs(1);
+    assert_eq!(s.select(&bases(), later), PRIMARY);
+    assert!(s.record(&url(PRIMARY), true, later, COOLDOWN));
+    assert_eq!(s.select(&bases(), later), SECONDARY);
+
+    // 成功后清零
+    let recovered = later + COOLDOWN + Duration::from_secs(1);
+    s.record(&url(PRIMARY), false, recovered, COOLDOWN);
+    assert!(!s.record(&url(PRIMARY), true, recovered, COOLDOWN));
+    assert_eq!(s.select(&bases(), recovered), PRIMARY);
+}
+
+#[test]
+fn all_down_uses_earliest_recovery() {
+    let now = Instant::now();
+    let mut s = state(now);
+    s.record(&url(SECONDARY), true, now, COOLDOWN);
+    s.record(&url(SECONDARY), true, now, COOLDOWN);
+    // 没有其他可用地址时不提前重试
+    s.record(&url(PRIMARY), true, now, COOLDOWN);
+    assert!(!s.record(&url(PRIMARY), true, now + Duration::from_secs(1), COOLDOWN));
+    assert_eq!(s.select(&bases(), now + Duration::from_secs(2)), SECONDARY);
+}
+
+#[test]
+fn only_configured_addresses_are_ours() {
+    let now = Instant::now();
+    let mut s = state(now);
+    assert!(s.matching(&url(PRIMARY)).is_some());
+    assert!(s.matching(&format!("{}?x=1", SECONDARY)).is_some());
+    assert!(s.matching("https://primary.example.com/api/v10/tokens").is_none());
+    assert!(s.matching("https://app.factory.ai/api/subscription").is_none());
+    // 不属于任何地址列表的请求不计入
+    assert!(!s.record("https://app.factory.ai/api/subscription", true, now, COOLDOWN));
+
+    // 其他地址列表（如另一个服务器环境）互不影响
+    let other = vec!["https://other.example.com/api/v1".to_string()];
+    assert_eq!(s.select(&other, now), other[0]);
+    assert!(s.matching("https://other.example.com/api/v1/tokens").is_some());
+    assert_eq!(s.select(&bases(), now), PRIMARY);
+}
@@ -0,0 +1,200 @@
+use crate::pinning;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_json::{json, Value};
+use std::fmt;
+
+// API 错误类型
+// code() 返回稳定的机器可读错误码，前端和客服据此区分错误种类
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    // 网络连接失败（DNS 解析、连接被拒绝、TLS 握手等），附带底层原因
+    Network(String),
+    // 请求超时
+    Timeout,
+    // 服务器证书公钥与固定值不匹配（可能存在中间人）
+    CertificatePin,
+    // 服务器返回非成功状态码
+    Http(u16),
+    // 会话已过期（服务器返回 401）
+    SessionExpired,
+    // 签名校验或解密失败
+    Crypto(String),
+    // 响应数据解析失败
+    Parse(String),
+    // 响应 MAC 缺失、不匹配或已过期
+    ResponseVerification(String),
+    // 服务器返回的业务错误
+    Server { code: Option<String>, message: String },
+    // 本地文件读写失败
+    Io(String),
+    // 更新包摘要或签名校验失败（不会安装）
+    UpdateVerification(String),
+    // 退出登录、切换模式或切换账号后，旧的请求被中止或结果被丢弃
+    Cancelled,
+    // 附带请求 ID 的接口调用错误（由 api::tracked 添加），其余行为与内部错误一致
+    Request { request_id: String, source: Box<ApiError> },
+}
+
+impl ApiError {
+    // 去掉请求 ID 后的错误，按错误种类匹配时使用
+    pub fn kind(&self) -> &ApiError {
+        match self {
+            ApiError::Request { source, .. } => source.kind(),
+            other => other,
+        }
+    }
+
+    // 出错请求的 X-Request-ID，用户反馈问题时据此查找服务器日志
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            ApiError::Request { request_id, .. } => Some(request_id),
+            _ => None,
+        }
+    }
+
+    // 附加请求 ID（已有请求 ID 时保留原来的）
+    pub fn with_request_id(self, request_id: &str) -> Self {
+        match self {
+            ApiError::Request { .. } => self,
+            other => ApiError::Request { request_id: request_id.to_string(), source: Box::new(other) },
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self.kind() {
+            ApiError::Network(_) => "NETWORK_ERROR",
+            ApiError::Timeout => "TIMEOUT",
+            ApiError::CertificatePin => "CERT_PIN_MISMATCH",
+            ApiError::Http(_) => "HTTP_ERROR",
+            ApiError::SessionExpired => "SESSION_EXPIRED",
+            ApiError::Crypto(_) => "CRYPTO_ERROR",
+            ApiError::Parse(_) => "PARSE_ERROR",
+            ApiError::ResponseVerification(_) => "RESPONSE_VERIFICATION_FAILED",
+            ApiError::Server { .. } => "SERVER_ERROR",
+            ApiError::Io(_) => "IO_ERROR",
+            ApiError::UpdateVerification(_) => "UPDATE_VERIFICATION_FAILED",
+            ApiError::Cancelled => "CANCELLED",
+            ApiError::Request { source, .. } => source.code(),
+        }
+    }
+
+    // 底层错误详情（仅用于排查问题，不直接展示给用户）
+    pub fn detail(&self) -> Option<String> {
+        match self.kind() {
+            ApiError::Network(d)
+            | ApiError::Crypto(d)
+            | ApiError::Parse(d)
+            | ApiError::ResponseVerification(d)
+            | ApiError::Io(d)
+            | ApiError::UpdateVerification(d) => Some(d.clone()),
+            ApiError::Http(status) => Some(status.to_string()),
+            ApiError::Server { code, .. } => code.clone(),
+            ApiError::Timeout | ApiError::CertificatePin | ApiError::SessionExpired | ApiError::Cancelled => None,
+            ApiError::Request { source, .. } => source.detail(),
+        }
+    }
+
+    pub fn is_session_expired(&self) -> bool {
+        matches!(self.kind(), ApiError::SessionExpired)
+    }
+
+    // 服务器不可达（断网、超时或网关故障），可以使用离线缓存
+    pub fn is_offline(&self) -> bool {
+        matches!(self.kind(), ApiError::Network(_) | ApiError::Timeout | ApiError::Http(502..=504))
+    }
+
+    // 构造服务器业务错误，message 为空时使用默认提示
+    pub fn server(code: Option<String>, message: Option<String>, fallback: &str) -> Self {
+        ApiError::Server {
+            code,
+            message: message.unwrap_or_else(|| fallback.to_string()),
+        }
+    }
+
+    // 命令返回给前端的统一失败结构
+    pub fn to_response(&self) -> Value {
+        json!({
+            "success": false,
+            "error": self.to_string(),
+            "errorCode": self.code(),
+            "errorDetail": self.detail(),
+            "requestId": self.request_id()
+        })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Network(_) => write!(f, "网络连接失败，请检查网络"),
+            ApiError::Timeout => write!(f, "请求超时，请稍后重试"),
+            ApiError::CertificatePin => write!(f, "服务器证书校验失败，请检查网络环境"),
+            ApiError::Http(status) => write!(f, "服务器错误 ({})", status),
+            ApiError::SessionExpired => write!(f, "会话已过期，请重新激活"),
+            ApiError::Crypto(_) => write!(f, "数据校验失败"),
+            ApiError::Parse(_) => write!(f, "数据解析失败"),
+            ApiError::ResponseVerification(_) => write!(f, "服务器响应校验失败"),
+            ApiError::Server { message, .. } => write!(f, "{}", message),
+            ApiError::Io(detail) => write!(f, "文件操作失败: {}", detail),
+            ApiError::UpdateVerification(detail) => write!(f, "更新包校验失败，已取消安装: {}", detail),
+            ApiError::Cancelled => write!(f, "请求已取消"),
+            ApiError::Request { source, .. } => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// 作为 Tauri 命令的错误返回时，前端收到 { code, message, detail, requestId }
+impl Serialize for ApiError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("ApiError", 4)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("detail", &self.detail())?;
+        s.serialize_field("requestId", &self.request_id())?;
+        s.end()
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        if pinning::is_pin_mismatch(&e) {
+            return ApiError::CertificatePin;
+        }
+        if e.is_timeout() {
+            return ApiError::Timeout;
+        }
+        if e.is_decode() {
+            return ApiError::Parse(error_chain(&e));
+        }
+        ApiError::Network(error_chain(&e))
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::Io(e.to_string())
+    }
+}
+
+// 拼接底层错误链（不含 URL，避免泄露服务器地址）
+fn error_chain(e: &reqwest::Error) -> String {
+    let mut parts = Vec::new();
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        parts.push(err.to_string());
+        source = err.source();
+    }
+    if parts.is_empty() {
+        let kind = if e.is_connect() {
+            "connect error"
+        } else if e.is_body() {
+            "body error"
+        } else {
+            "request error"
+        };
+        parts.push(kind.to_string());
+    }
+    parts.join(": ")
+}
@@ -1,10 +1,25 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod cancel;
+mod clock;
 mod commands;
 mod crypto;
+mod endpoints;
+mod error;
+mod metrics;
+mod pinning;
+mod profile;
+mod proxy;
+mod push;
 mod security;
+mod singleflight;
 mod api;
+mod retry;
 mod storage;
+mod subscription;
+mod update;
+mod version;
+mod watchdog;
 
 use tauri::{
     menu::{Menu, MenuItem},
@@ -13,6 +28,12 @@ use tauri::{
 };
 
 fn main() {
+    // 更新后的启动检查进程，不启动界面
+    if std::env::args().any(|arg| arg == watchdog::WATCHDOG_ARG) {
+        watchdog::run();
+        return;
+    }
+    
     // 反调试检测
     #[cfg(not(debug_assertions))]
     {
@@ -51,6 +72,10 @@ fn main() {
             commands::refresh_active_token,
             commands::unbind_and_clear,
             commands::check_update,
+            commands::get_update_channel,
+            commands::set_update_channel,
+            commands::get_announcements,
+            commands::dismiss_announcement,
             commands::open_download_url,
             commands::download_and_update,
             commands::set_autostart,
@@ -62,6 +87,10 @@ fn main() {
             commands::get_current_mode,
             commands::clear_all_licenses,
             commands::get_license_code,
+            commands::get_proxy_config,
+            commands::set_proxy_config,
+            commands::test_proxy_connection,
+            commands::get_network_stats,
         ])
         .setup(|app| {
             // 清理旧版本文件（更新后的残留）
@@ -121,6 +150,18 @@ fn main() {
                 security::start_heartbeat_loop(app_handle);
             });
             
+            // 启动实时推送连接
+            push::start(app.handle().clone());
+            
+            // 更新后的首次启动：通知看门狗已正常启动，本地数据无法解密时退出由看门狗回滚
+            if let Err(e) = watchdog::mark_ready(commands::CURRENT_VERSION) {
+                #[cfg(debug_assertions)]
+                println!("[Update] 启动检查失败: {}", e);
+                #[cfg(not(debug_assertions))]
+                let _ = e;
+                std::process::exit(1);
+            }
+
             Ok(())
         })
         .on_window_event(|window, event| {
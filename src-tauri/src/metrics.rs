@@ -0,0 +1,179 @@
+use crate::error::ApiError;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// ==================== 请求统计 ====================
+// 按接口记录成功/失败次数、重试次数和耗时分布，供 get_network_stats 导出给客服排查
+// 耗时为一次接口调用的总时间（含重试等待），统计只保存在内存中，重启后清零
+
+// 耗时分布的桶上限（毫秒），超过最后一个桶的计入溢出桶
+const LATENCY_BUCKETS_MS: &[u64] = &[50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // 长度为 LATENCY_BUCKETS_MS.len() + 1，最后一个为溢出桶
+    counts: Vec<u64>,
+    count: u64,
+    sum_ms: u64,
+    max_ms: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, ms: u64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+        let index = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|le| ms <= *le)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    // 按桶上限估算分位数（落在溢出桶时取最大值）
+    fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((self.count as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return LATENCY_BUCKETS_MS.get(i).map_or(self.max_ms, |le| (*le).min(self.max_ms));
+            }
+        }
+        self.max_ms
+    }
+
+    fn to_json(&self) -> Value {
+        let buckets: Vec<Value> = LATENCY_BUCKETS_MS
+            .iter()
+            .map(|le| Value::from(*le))
+            .chain(std::iter::once(Value::from("+Inf")))
+            .zip(self.counts.iter().chain(std::iter::repeat(&0)))
+            .map(|(le, count)| json!({ "le": le, "count": count }))
+            .collect();
+        json!({
+            "count": self.count,
+            "avg": self.sum_ms.checked_div(self.count).unwrap_or(0),
+            "max": self.max_ms,
+            "p50": self.percentile(0.5),
+            "p95": self.percentile(0.95),
+            "buckets": buckets
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct EndpointStats {
+    successes: u64,
+    // 错误码 -> 次数（HTTP 错误附带状态码，如 HTTP_ERROR:503）
+    failures: BTreeMap<String, u64>,
+    retries: u64,
+    latency: Histogram,
+    // 最近一次失败：错误种类、请求 ID 和时间，客服据此查找服务器日志
+    last_failure: Option<(String, Option<String>, i64)>,
+}
+
+impl EndpointStats {
+    fn to_json(&self) -> Value {
+        let failed: u64 = self.failures.values().sum();
+        json!({
+            "requests": self.successes + failed,
+            "successes": self.successes,
+            "failures": self.failures,
+            "retries": self.retries,
+            "latencyMs": self.latency.to_json(),
+            "lastFailure": self.last_failure.as_ref().map(|(kind, request_id, at)| {
+                json!({ "kind": kind, "requestId": request_id, "at": at })
+            })
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Stats {
+    endpoints: BTreeMap<String, EndpointStats>,
+    // 开始统计的时间（启动或上次重置）
+    since: i64,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats { endpoints: BTreeMap::new(), since: chrono::Utc::now().timestamp() }
+    }
+
+    fn endpoint(&mut self, name: &str) -> &mut EndpointStats {
+        self.endpoints.entry(name.to_string()).or_default()
+    }
+
+    fn record(&mut self, endpoint: &str, elapsed: Duration, error: Option<&ApiError>) {
+        let stats = self.endpoint(endpoint);
+        match error {
+            Some(e) => {
+                let kind = failure_kind(e);
+                *stats.failures.entry(kind.clone()).or_default() += 1;
+                stats.last_failure = Some((kind, e.request_id().map(|id| id.to_string()), chrono::Utc::now().timestamp()));
+            }
+            None => stats.successes += 1,
+        }
+        stats.latency.record(elapsed.as_millis() as u64);
+    }
+
+    fn to_json(&self) -> Value {
+        let endpoints: serde_json::Map<String, Value> = self
+            .endpoints
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.to_json()))
+            .collect();
+        json!({
+            "since": self.since,
+            "now": chrono::Utc::now().timestamp(),
+            "endpoints": endpoints
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATS: Mutex<Stats> = Mutex::new(Stats::new());
+}
+
+fn stats() -> std::sync::MutexGuard<'static, Stats> {
+    STATS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn failure_kind(error: &ApiError) -> String {
+    match error.kind() {
+        ApiError::Http(status) => format!("{}:{}", error.code(), status),
+        _ => error.code().to_string(),
+    }
+}
+
+// 记录一次接口调用的结果
+pub fn record(endpoint: &str, elapsed: Duration, error: Option<&ApiError>) {
+    stats().record(endpoint, elapsed, error);
+}
+
+// 记录一次重试（由 retry::send 在等待重试前调用）
+pub fn record_retry(endpoint: &str) {
+    stats().endpoint(endpoint).retries += 1;
+}
+
+// 导出统计，reset 为 true 时导出后清零
+pub fn snapshot(reset: bool) -> Value {
+    let mut stats = stats();
+    let snapshot = stats.to_json();
+    if reset {
+        *stats = Stats::new();
+    }
+    snapshot
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,48 @@
+// 请求统计测试：成功/失败/重试计数、按错误种类分组、耗时分位数
+// 直接操作 Stats，不读写全局统计（集成测试并行写入全局统计）
+
+use super::*;
+
+fn ms(n: u64) -> Duration {
+    Duration::from_millis(n)
+}
+
+#[test]
+fn records_successes_failures_and_retries() {
+    let mut stats = Stats::new();
+    stats.record("tokens", ms(40), None);
+    stats.endpoint("tokens").retries += 1;
+    stats.record("tokens", ms(300), None);
+    stats.record("auth/activate", ms(60), Some(&ApiError::Http(500)));
+    stats.record("auth/activate", ms(70), Some(&ApiError::Http(500)));
+
+    let json = stats.to_json();
+    let tokens = &json["endpoints"]["tokens"];
+    assert_eq!(tokens["requests"], 2);
+    assert_eq!(tokens["successes"], 2);
+    assert_eq!(tokens["retries"], 1);
+    assert_eq!(tokens["latencyMs"]["count"], 2);
+    assert_eq!(tokens["latencyMs"]["max"], 300);
+    assert_eq!(tokens["latencyMs"]["p50"], 50);
+    assert_eq!(tokens["latencyMs"]["p95"], 300);
+
+    let activate = &json["endpoints"]["auth/activate"];
+    assert_eq!(activate["successes"], 0);
+    assert_eq!(activate["failures"]["HTTP_ERROR:500"], 2);
+}
+
+#[test]
+fn failures_are_grouped_by_kind_with_last_request_id() {
+    let mut stats = Stats::new();
+    stats.record("client/version", ms(10), Some(&ApiError::ResponseVerification("bad mac".to_string())));
+    stats.record("tokens", ms(10), Some(&ApiError::SessionExpired.with_request_id("req-1")));
+    stats.record("tokens", ms(10), Some(&ApiError::Http(503).with_request_id("req-2")));
+
+    let json = stats.to_json();
+    assert_eq!(json["endpoints"]["client/version"]["failures"]["RESPONSE_VERIFICATION_FAILED"], 1);
+    let tokens = &json["endpoints"]["tokens"];
+    assert_eq!(tokens["failures"]["SESSION_EXPIRED"], 1);
+    assert_eq!(tokens["failures"]["HTTP_ERROR:503"], 1);
+    assert_eq!(tokens["lastFailure"]["kind"], "HTTP_ERROR:503");
+    assert_eq!(tokens["lastFailure"]["requestId"], "req-2");
+}
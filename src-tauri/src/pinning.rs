@@ -0,0 +1,201 @@
+use base64::Engine as _;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+
+// ==================== 证书公钥固定（SPKI Pinning） ====================
+// 在标准证书校验通过后，要求证书链（服务器证书、中间证书或信任根）中
+// 至少有一个 SubjectPublicKeyInfo 的 SHA-256 与固定值匹配
+
+// 生产环境固定值（base64 SHA-256）：ISRG Root X1 为主，ISRG Root X2 为备份
+const PRODUCTION_PINS: &[&str] = &[
+    "C5+lpZ7tcVwmwQIMcRtPbsQtWLABXhQzejna0wHFr8M=",
+    "diGVwiVYbubAI3RW4hB9xU8e/CH2GnkuvVFZE8zmgzI=",
+];
+
+pub type Pin = [u8; 32];
+
+pub fn production_pins() -> Vec<String> {
+    PRODUCTION_PINS.iter().map(|p| p.to_string()).collect()
+}
+
+// 解析固定值，兼容 "sha256/" 前缀
+pub fn parse_pin(pin: &str) -> Result<Pin, String> {
+    let value = pin.trim();
+    let value = value.strip_prefix("sha256/").unwrap_or(value);
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| format!("无效的证书指纹 {}: {}", pin, e))?;
+    bytes.try_into().map_err(|_| format!("证书指纹长度错误: {}", pin))
+}
+
+pub fn parse_pins(pins: &[String]) -> Result<Vec<Pin>, String> {
+    pins.iter().map(|p| parse_pin(p)).collect()
+}
+
+// 证书链中没有与固定值匹配的公钥
+#[derive(Debug)]
+pub struct PinMismatch;
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "certificate public key does not match any pin")
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+// 判断底层错误链中是否包含固定值不匹配
+// io::Error 的 source() 会跳过内部错误本身，需要用 get_ref() 展开
+pub fn is_pin_mismatch(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) =
+            err.downcast_ref::<rustls::Error>()
+        {
+            if other.0.downcast_ref::<PinMismatch>().is_some() {
+                return true;
+            }
+        }
+        if let Some(inner) = err.downcast_ref::<std::io::Error>().and_then(|e| e.get_ref()) {
+            if is_pin_mismatch(inner) {
+                return true;
+            }
+        }
+        current = err.source();
+    }
+    false
+}
+
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    pins: Vec<Pin>,
+}
+
+impl PinnedVerifier {
+    fn matches(&self, der: &[u8]) -> bool {
+        let hash: Pin = Sha256::digest(der).into();
+        self.pins.contains(&hash)
+    }
+
+    fn chain_matches(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>]) -> bool {
+        let mut last_issuer = None;
+        for der in std::iter::once(end_entity).chain(intermediates) {
+            let Ok(cert) = webpki::EndEntityCert::try_from(der) else {
+                continue;
+            };
+            if self.matches(cert.subject_public_key_info().as_ref()) {
+                return true;
+            }
+            last_issuer = Some(cert.issuer().to_vec());
+        }
+
+        // 服务器通常不发送根证书，按颁发者在信任根中查找
+        let Some(issuer) = last_issuer else {
+            return false;
+        };
+        self.roots
+            .roots
+            .iter()
+            .filter(|anchor| anchor.subject.as_ref() == issuer.as_slice())
+            .any(|anchor| self.matches(&der_sequence(anchor.subject_public_key_info.as_ref())))
+    }
+}
+
+// 信任根中保存的 SPKI 不含外层 SEQUENCE，计算指纹前补回
+fn der_sequence(content: &[u8]) -> Vec<u8> {
+    let len = content.len();
+    let mut der = vec![0x30];
+    if len < 0x80 {
+        der.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        der.push(0x80 | (bytes.len() - skip) as u8);
+        der.extend_from_slice(&bytes[skip..]);
+    }
+    der.extend_from_slice(content);
+    der
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        if self.chain_matches(end_entity, intermediates) {
+            Ok(verified)
+        } else {
+            #[cfg(debug_assertions)]
+            println!("[TLS] 证书公钥固定校验失败: {:?}", server_name);
+            Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(PinMismatch)))))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+// 内置信任根（Mozilla 根证书列表）
+pub fn builtin_roots() -> RootCertStore {
+    RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() }
+}
+
+// 使用内置信任根构建 TLS 配置，pins 为空时只做标准证书校验
+pub fn tls_config(pins: Vec<Pin>) -> Result<ClientConfig, String> {
+    tls_config_with_roots(builtin_roots(), pins)
+}
+
+pub fn tls_config_with_roots(roots: RootCertStore, pins: Vec<Pin>) -> Result<ClientConfig, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let roots = Arc::new(roots);
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+
+    if pins.is_empty() {
+        return Ok(builder.with_root_certificates(roots).with_no_client_auth());
+    }
+
+    let inner = WebPkiServerVerifier::builder_with_provider(roots.clone(), provider)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let verifier = PinnedVerifier { inner, roots, pins };
+    Ok(builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
@@ -0,0 +1,288 @@
+use crate::crypto;
+use crate::pinning;
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+// ==================== 服务器环境配置 ====================
+// 选择优先级：环境变量 ATM_SERVER_PROFILE > 数据目录下的 server.json > production
+// 非生产环境的地址由 ATM_API_BASE 或 server.json 指定
+// 备用地址（其他区域，主地址不可用时切换）：生产环境在编译时由 ATM_API_FALLBACKS 内置，非生产环境同名环境变量或 server.json 指定
+// 证书固定值：生产环境内置，非生产环境由 ATM_SPKI_PINS（逗号分隔）或 server.json 指定
+// 更新签名公钥：生产环境在编译时由 ATM_UPDATE_PUBLIC_KEY 内置，非生产环境同名环境变量或 server.json 指定（调试构建的 local 环境默认使用 mock 服务器的公钥）
+
+const ENV_PROFILE: &str = "ATM_SERVER_PROFILE";
+const ENV_API_BASE: &str = "ATM_API_BASE";
+const ENV_SPKI_PINS: &str = "ATM_SPKI_PINS";
+const ENV_API_FALLBACKS: &str = "ATM_API_FALLBACKS";
+const ENV_UPDATE_PUBLIC_KEY: &str = "ATM_UPDATE_PUBLIC_KEY";
+
+// 本地测试服务器默认地址（与 mock-server 默认监听地址一致）
+const DEFAULT_LOCAL_API_BASE: &str = "http://127.0.0.1:3000/api/v1";
+
+// 生产环境更新签名公钥（base64 Ed25519），发布构建时通过环境变量内置
+// 未内置时生产环境拒绝安装任何更新
+const PRODUCTION_UPDATE_PUBLIC_KEY: Option<&str> = option_env!("ATM_UPDATE_PUBLIC_KEY");
+
+// 生产环境备用地址（逗号分隔），发布构建时通过环境变量内置
+const PRODUCTION_API_FALLBACKS: Option<&str> = option_env!("ATM_API_FALLBACKS");
+
+// 本地测试服务器的更新签名公钥（与 mock-server 固定的签名私钥对应，私钥是公开的）
+// 只内置在调试构建中：发布构建的 local 环境同样需要显式配置公钥
+#[cfg(debug_assertions)]
+const DEFAULT_LOCAL_UPDATE_PUBLIC_KEY: Option<&str> = Some("IVL40Zt5HSRFMkLhXy6rbLfP+ntqXtMAl5YOBpiB2xI=");
+#[cfg(not(debug_assertions))]
+const DEFAULT_LOCAL_UPDATE_PUBLIC_KEY: Option<&str> = None;
+
+// 生产环境推送通道地址；其他环境由服务器地址推导（同一主机的 /ws）
+const PRODUCTION_WS_URL: &str = "wss://dd.776523718.xyz/ws";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileName {
+    Production,
+    Staging,
+    Local,
+}
+
+impl ProfileName {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "production" | "prod" => Some(ProfileName::Production),
+            "staging" => Some(ProfileName::Staging),
+            "local" => Some(ProfileName::Local),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProfileName::Production => "production",
+            ProfileName::Staging => "staging",
+            ProfileName::Local => "local",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerProfile {
+    pub name: ProfileName,
+    // 主地址
+    pub api_base: String,
+    // 备用地址（按优先级），主地址不可用时切换
+    pub fallback_bases: Vec<String>,
+    // 推送通道（WebSocket）地址
+    pub ws_url: String,
+    // 证书 SPKI 固定值（base64 SHA-256），为空时只做标准证书校验
+    pub spki_pins: Vec<String>,
+    // 更新签名公钥（base64 Ed25519），为空时拒绝安装更新
+    pub update_public_key: Option<String>,
+    // 配置来源: "default" / "env" / "config"
+    pub source: &'static str,
+    // 配置无效时回退到生产环境，并记录原因
+    pub error: Option<String>,
+}
+
+impl ServerProfile {
+    fn production() -> Self {
+        ServerProfile {
+            name: ProfileName::Production,
+            api_base: crypto::get_api_url(),
+            // 内置值在发布构建时确定，无效的地址直接忽略
+            fallback_bases: split_list(PRODUCTION_API_FALLBACKS.unwrap_or_default())
+                .iter()
+                .filter_map(|url| validate_api_base(url).ok())
+                .collect(),
+            ws_url: PRODUCTION_WS_URL.to_string(),
+            spki_pins: pinning::production_pins(),
+            update_public_key: PRODUCTION_UPDATE_PUBLIC_KEY.map(|k| k.to_string()),
+            source: "default",
+            error: None,
+        }
+    }
+
+    // 所有服务器地址（主地址在前，去重）
+    pub fn api_bases(&self) -> Vec<String> {
+        let mut bases = vec![self.api_base.clone()];
+        for base in &self.fallback_bases {
+            if !bases.contains(base) {
+                bases.push(base.clone());
+            }
+        }
+        bases
+    }
+
+    // 提供给前端的摘要（生产环境不暴露服务器地址）
+    pub fn summary(&self) -> serde_json::Value {
+        let expose = self.name != ProfileName::Production;
+        serde_json::json!({
+            "name": self.name.as_str(),
+            "source": self.source,
+            "apiBase": if expose { Some(&self.api_base) } else { None },
+            "fallbacks": if expose { serde_json::json!(self.fallback_bases) } else { serde_json::json!(self.fallback_bases.len()) },
+            "pinned": !self.spki_pins.is_empty(),
+            "updateSigned": self.update_public_key.is_some(),
+            "error": self.error
+        })
+    }
+}
+
+// server.json 结构
+#[derive(Debug, Default, Deserialize)]
+struct ProfileConfig {
+    profile: Option<String>,
+    staging_url: Option<String>,
+    local_url: Option<String>,
+    #[serde(default)]
+    fallback_urls: Vec<String>,
+    #[serde(default)]
+    spki_pins: Vec<String>,
+    update_public_key: Option<String>,
+}
+
+lazy_static::lazy_static! {
+    static ref ACTIVE_PROFILE: RwLock<Option<ServerProfile>> = RwLock::new(None);
+}
+
+fn get_config_file() -> PathBuf {
+    let mut path = storage::get_data_dir();
+    path.push("server.json");
+    path
+}
+
+fn load_config() -> Result<Option<ProfileConfig>, String> {
+    let file = get_config_file();
+    if !file.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&file).map_err(|e| format!("读取 server.json 失败: {}", e))?;
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("server.json 格式错误: {}", e))
+}
+
+// 校验服务器地址：必须是 https，本机地址除外
+pub fn validate_api_base(url: &str) -> Result<String, String> {
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|e| format!("无效的服务器地址 {}: {}", url, e))?;
+    let host = parsed.host_str().unwrap_or_default();
+    let is_loopback = host == "localhost" || host == "127.0.0.1" || host == "[::1]" || host == "::1";
+    match parsed.scheme() {
+        "https" => {}
+        "http" if is_loopback => {}
+        _ => return Err(format!("服务器地址必须使用 https（本机地址除外）: {}", url)),
+    }
+    Ok(parsed.as_str().trim_end_matches('/').to_string())
+}
+
+// 校验更新签名公钥：base64 编码的 32 字节 Ed25519 公钥
+fn validate_update_key(key: &str) -> Result<String, String> {
+    use base64::Engine as _;
+    let key = key.trim();
+    match base64::engine::general_purpose::STANDARD.decode(key) {
+        Ok(bytes) if bytes.len() == 32 => Ok(key.to_string()),
+        _ => Err(format!("无效的更新签名公钥: {}", key)),
+    }
+}
+
+// 逗号分隔的列表
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect()
+}
+
+// 推送通道地址：http -> ws，https -> wss，路径为 /ws
+fn ws_url_for(api_base: &str) -> Result<String, String> {
+    let mut url = reqwest::Url::parse(api_base).map_err(|e| format!("无效的服务器地址 {}: {}", api_base, e))?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme).map_err(|_| format!("无法推导推送地址: {}", api_base))?;
+    url.set_path("/ws");
+    url.set_query(None);
+    Ok(url.to_string())
+}
+
+fn resolve() -> Result<ServerProfile, String> {
+    let config = load_config()?;
+    let env_profile = std::env::var(ENV_PROFILE).ok().filter(|v| !v.trim().is_empty());
+
+    let (name_str, source) = match (&env_profile, config.as_ref().and_then(|c| c.profile.clone())) {
+        (Some(name), _) => (name.clone(), "env"),
+        (None, Some(name)) => (name, "config"),
+        (None, None) => return Ok(ServerProfile::production()),
+    };
+    let name = ProfileName::parse(&name_str).ok_or_else(|| format!("未知的服务器环境: {}", name_str))?;
+
+    if name == ProfileName::Production {
+        return Ok(ServerProfile { source, ..ServerProfile::production() });
+    }
+
+    // 地址优先取环境变量，其次取配置文件
+    let env_base = std::env::var(ENV_API_BASE).ok().filter(|v| !v.trim().is_empty());
+    let config_base = config.as_ref().and_then(|c| match name {
+        ProfileName::Staging => c.staging_url.clone(),
+        ProfileName::Local => c.local_url.clone(),
+        ProfileName::Production => None,
+    });
+    let api_base = match (env_base, config_base, name) {
+        (Some(url), _, _) | (None, Some(url), _) => url,
+        (None, None, ProfileName::Local) => DEFAULT_LOCAL_API_BASE.to_string(),
+        (None, None, _) => return Err(format!("{} 环境未配置服务器地址", name.as_str())),
+    };
+
+    let update_public_key = match std::env::var(ENV_UPDATE_PUBLIC_KEY).ok().filter(|v| !v.trim().is_empty()) {
+        Some(key) => Some(key),
+        None => config.as_ref().and_then(|c| c.update_public_key.clone()),
+    };
+    let update_public_key = match (update_public_key, name) {
+        (Some(key), _) => Some(validate_update_key(&key)?),
+        (None, ProfileName::Local) => DEFAULT_LOCAL_UPDATE_PUBLIC_KEY.map(|k| k.to_string()),
+        (None, _) => None,
+    };
+
+    let fallback_bases = match std::env::var(ENV_API_FALLBACKS).ok().filter(|v| !v.trim().is_empty()) {
+        Some(urls) => split_list(&urls),
+        None => config.as_ref().map(|c| c.fallback_urls.clone()).unwrap_or_default(),
+    };
+    let fallback_bases = fallback_bases.iter().map(|url| validate_api_base(url)).collect::<Result<Vec<_>, _>>()?;
+
+    let spki_pins = match std::env::var(ENV_SPKI_PINS).ok().filter(|v| !v.trim().is_empty()) {
+        Some(pins) => split_list(&pins),
+        None => config.map(|c| c.spki_pins).unwrap_or_default(),
+    };
+    pinning::parse_pins(&spki_pins)?;
+
+    let api_base = validate_api_base(&api_base)?;
+    Ok(ServerProfile {
+        name,
+        ws_url: ws_url_for(&api_base)?,
+        api_base,
+        fallback_bases,
+        spki_pins,
+        update_public_key,
+        source,
+        error: None,
+    })
+}
+
+// 当前生效的服务器环境（首次调用时解析并缓存）
+pub fn active() -> ServerProfile {
+    if let Ok(guard) = ACTIVE_PROFILE.read() {
+        if let Some(profile) = guard.as_ref() {
+            return profile.clone();
+        }
+    }
+
+    let profile = resolve().unwrap_or_else(|e| {
+        #[cfg(debug_assertions)]
+        println!("[Profile] 服务器环境配置无效，使用生产环境: {}", e);
+        ServerProfile { error: Some(e), ..ServerProfile::production() }
+    });
+
+    #[cfg(debug_assertions)]
+    println!("[Profile] 当前服务器环境: {} ({})", profile.name.as_str(), profile.source);
+
+    if let Ok(mut guard) = ACTIVE_PROFILE.write() {
+        *guard = Some(profile.clone());
+    }
+    profile
+}
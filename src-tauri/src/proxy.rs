@@ -0,0 +1,371 @@
+use crate::crypto;
+use crate::storage;
+use base64::Engine as _;
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// ==================== 代理设置 ====================
+// system: 系统代理（HTTPS_PROXY / HTTP_PROXY / ALL_PROXY / NO_PROXY 环境变量，Windows 下还会读取系统代理设置）
+//         推送通道的隧道只读取环境变量，只在系统设置中配置的代理不会生效，需要时改用手动配置
+// none:   直连，忽略环境变量和系统代理
+// manual: 手动配置的 HTTP / HTTPS / SOCKS5 代理
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    #[default]
+    System,
+    None,
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyScheme {
+    #[default]
+    Http,
+    Https,
+    Socks5,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyConfig {
+    #[serde(default)]
+    pub mode: ProxyMode,
+    #[serde(default)]
+    pub scheme: ProxyScheme,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    // 不走代理的地址（逗号分隔），为空时使用 NO_PROXY 环境变量
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mode != ProxyMode::Manual {
+            return Ok(());
+        }
+        let host = self.host.trim();
+        if host.is_empty() {
+            return Err("代理地址不能为空".to_string());
+        }
+        if host.contains("://") || host.contains('/') || host.contains(char::is_whitespace) {
+            return Err(format!("代理地址格式错误: {}", host));
+        }
+        if self.port == 0 {
+            return Err("代理端口无效".to_string());
+        }
+        // SOCKS5 用户名密码认证中长度只占一个字节
+        if self.scheme == ProxyScheme::Socks5 {
+            if let Some((username, password)) = credentials(self) {
+                if username.len() > 255 || password.len() > 255 {
+                    return Err("SOCKS5 代理的用户名和密码不能超过 255 字节".to_string());
+                }
+            }
+        }
+        self.proxy_url().map(|_| ())
+    }
+
+    // 代理地址，认证信息放在 URL 中（HTTP 代理转为 Proxy-Authorization，SOCKS5 用于用户名密码认证）
+    fn proxy_url(&self) -> Result<reqwest::Url, String> {
+        // SOCKS5 使用 socks5h，由代理服务器解析域名（内网环境常见要求）
+        let scheme = match self.scheme {
+            ProxyScheme::Http => "http",
+            ProxyScheme::Https => "https",
+            ProxyScheme::Socks5 => "socks5h",
+        };
+        let mut url = reqwest::Url::parse(&format!("{}://{}:{}", scheme, self.host.trim(), self.port))
+            .map_err(|e| format!("代理地址格式错误: {}", e))?;
+        if let Some(username) = self.username.as_deref().filter(|u| !u.is_empty()) {
+            url.set_username(username).map_err(|_| "代理用户名无效".to_string())?;
+            url.set_password(self.password.as_deref().filter(|p| !p.is_empty()))
+                .map_err(|_| "代理密码无效".to_string())?;
+        }
+        Ok(url)
+    }
+
+    // 应用到 HTTP Client
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, String> {
+        match self.mode {
+            ProxyMode::System => Ok(builder),
+            ProxyMode::None => Ok(builder.no_proxy()),
+            ProxyMode::Manual => {
+                let no_proxy = match self.no_proxy.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+                    Some(list) => NoProxy::from_string(list),
+                    None => NoProxy::from_env(),
+                };
+                let proxy = Proxy::all(self.proxy_url()?)
+                    .map_err(|e| format!("代理配置无效: {}", e))?
+                    .no_proxy(no_proxy);
+                Ok(builder.proxy(proxy))
+            }
+        }
+    }
+
+    // 提供给前端的摘要（不返回密码）
+    pub fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "mode": self.mode,
+            "scheme": self.scheme,
+            "host": self.host,
+            "port": self.port,
+            "username": self.username,
+            "hasPassword": self.password.as_deref().is_some_and(|p| !p.is_empty()),
+            "noProxy": self.no_proxy,
+            "envProxy": env_proxy()
+        })
+    }
+}
+
+// 环境变量中的代理（仅用于界面展示，去掉认证信息）
+fn env_proxy() -> Option<String> {
+    ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy", "HTTP_PROXY", "http_proxy"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|v| !v.trim().is_empty()))
+        .map(|value| match reqwest::Url::parse(&value) {
+            Ok(mut url) => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.to_string()
+            }
+            Err(_) => value,
+        })
+}
+
+// ==================== TCP 隧道 ====================
+// reqwest 之外的长连接（推送通道）通过这里建立，代理规则与 HTTP 请求一致：
+// HTTP 代理使用 CONNECT，SOCKS5 代理由代理服务器解析域名
+
+// NO_PROXY 规则：* 匹配所有，其余按主机名或域名后缀匹配
+fn bypassed(list: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    list.split(',').map(str::trim).filter(|e| !e.is_empty()).any(|entry| {
+        let entry = entry.to_ascii_lowercase();
+        let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+        entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+fn env_value(names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| std::env::var(name).ok().filter(|v| !v.trim().is_empty()))
+}
+
+// 系统代理模式下从环境变量解析代理（不读取 Windows 系统代理设置）
+fn env_tunnel_proxy(tls: bool) -> Option<ProxyConfig> {
+    let names: &[&str] = if tls {
+        &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+    } else {
+        &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+    };
+    let value = env_value(names)?;
+    let url = reqwest::Url::parse(&value).or_else(|_| reqwest::Url::parse(&format!("http://{}", value))).ok()?;
+    let scheme = match url.scheme() {
+        "http" => ProxyScheme::Http,
+        "https" => ProxyScheme::Https,
+        "socks5" | "socks5h" => ProxyScheme::Socks5,
+        _ => return None,
+    };
+    Some(ProxyConfig {
+        mode: ProxyMode::Manual,
+        scheme,
+        host: url.host_str()?.to_string(),
+        port: url.port_or_known_default()?,
+        username: Some(url.username().to_string()).filter(|u| !u.is_empty()),
+        password: url.password().map(|p| p.to_string()),
+        no_proxy: None,
+    })
+}
+
+impl ProxyConfig {
+    // 连接 host:port 时实际使用的代理，None 表示直连
+    fn tunnel_proxy(&self, host: &str, tls: bool) -> Option<ProxyConfig> {
+        let proxy = match self.mode {
+            ProxyMode::None => return None,
+            ProxyMode::Manual => self.clone(),
+            ProxyMode::System => env_tunnel_proxy(tls)?,
+        };
+        let no_proxy = proxy
+            .no_proxy
+            .clone()
+            .filter(|v| !v.trim().is_empty())
+            .or_else(|| env_value(&["NO_PROXY", "no_proxy"]))
+            .unwrap_or_default();
+        if bypassed(&no_proxy, host) {
+            return None;
+        }
+        Some(proxy)
+    }
+}
+
+// 建立到 host:port 的 TCP 连接（按代理设置直连或经代理隧道），tls 表示上层协议是否加密
+pub async fn connect_tcp(config: &ProxyConfig, host: &str, port: u16, tls: bool) -> std::io::Result<TcpStream> {
+    let Some(proxy) = config.tunnel_proxy(host, tls) else {
+        return TcpStream::connect((host, port)).await;
+    };
+    let mut stream = TcpStream::connect((proxy.host.trim(), proxy.port)).await?;
+    match proxy.scheme {
+        ProxyScheme::Http => http_connect(&mut stream, &proxy, host, port).await?,
+        ProxyScheme::Socks5 => socks5_connect(&mut stream, &proxy, host, port).await?,
+        ProxyScheme::Https => {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "推送通道暂不支持 HTTPS 代理"));
+        }
+    }
+    Ok(stream)
+}
+
+fn credentials(proxy: &ProxyConfig) -> Option<(&str, &str)> {
+    let username = proxy.username.as_deref().filter(|u| !u.is_empty())?;
+    Some((username, proxy.password.as_deref().unwrap_or_default()))
+}
+
+async fn http_connect(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    let target = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some((username, password)) = credentials(proxy) {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // 逐字节读取响应头，避免读走隧道中的后续数据
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 || head.len() > 8192 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "代理响应无效"));
+        }
+        head.push(byte[0]);
+    }
+    let status = String::from_utf8_lossy(&head).split_whitespace().nth(1).unwrap_or_default().to_string();
+    match status.as_str() {
+        "200" => Ok(()),
+        "407" => Err(io::Error::new(io::ErrorKind::PermissionDenied, "代理认证失败")),
+        _ => Err(io::Error::other(format!("代理拒绝连接: {}", status))),
+    }
+}
+
+// SOCKS5 报文中的变长字段（长度占一个字节），超长时报错而不是截断成错误的报文
+fn socks5_len(name: &str, value: &str) -> io::Result<u8> {
+    u8::try_from(value.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("代理配置错误: SOCKS5 {}超过 255 字节", name)))
+}
+
+async fn socks5_connect(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    let credentials = credentials(proxy);
+    // 协商认证方式：0x00 无认证，0x02 用户名密码
+    let method = if credentials.is_some() { 0x02 } else { 0x00 };
+    stream.write_all(&[0x05, 0x01, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != method {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "SOCKS5 代理不支持该认证方式"));
+    }
+    if let Some((username, password)) = credentials {
+        let mut auth = vec![0x01, socks5_len("用户名", username)?];
+        auth.extend_from_slice(username.as_bytes());
+        auth.push(socks5_len("密码", password)?);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "代理认证失败"));
+        }
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(std::net::IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            request.push(0x03);
+            request.push(socks5_len("目标地址", host)?);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 0x00 {
+        return Err(io::Error::other(format!("SOCKS5 代理拒绝连接: {}", head[1])));
+    }
+    // 跳过绑定地址
+    let skip = match head[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "SOCKS5 响应无效")),
+    };
+    let mut rest = vec![0u8; skip + 2];
+    stream.read_exact(&mut rest).await?;
+    Ok(())
+}
+
+lazy_static::lazy_static! {
+    static ref PROXY_CONFIG: RwLock<Option<ProxyConfig>> = RwLock::new(None);
+}
+
+fn get_proxy_file() -> PathBuf {
+    let mut path = storage::get_data_dir();
+    path.push("proxy.enc"); // 含代理密码，加密存储
+    path
+}
+
+// 当前代理设置（首次调用时从磁盘读取）
+pub fn load() -> ProxyConfig {
+    if let Ok(guard) = PROXY_CONFIG.read() {
+        if let Some(config) = guard.as_ref() {
+            return config.clone();
+        }
+    }
+
+    let config = fs::read_to_string(get_proxy_file())
+        .ok()
+        .and_then(|encrypted| crypto::decrypt_local_data(&encrypted).ok())
+        .and_then(|json| serde_json::from_str::<ProxyConfig>(&json).ok())
+        .filter(|config| config.validate().is_ok())
+        .unwrap_or_default();
+
+    if let Ok(mut guard) = PROXY_CONFIG.write() {
+        *guard = Some(config.clone());
+    }
+    config
+}
+
+pub fn save(config: &ProxyConfig) -> Result<(), String> {
+    config.validate()?;
+    storage::ensure_data_dir();
+    let json = serde_json::to_string(config).map_err(|e| e.to_string())?;
+    let encrypted = crypto::encrypt_local_data(&json)?;
+    fs::write(get_proxy_file(), encrypted).map_err(|e| format!("保存代理设置失败: {}", e))?;
+    if let Ok(mut guard) = PROXY_CONFIG.write() {
+        *guard = Some(config.clone());
+    }
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use crate::api;
+use crate::cancel;
+use crate::clock;
+use crate::commands;
+use crate::crypto;
+use crate::error::ApiError;
+use crate::pinning;
+use crate::profile;
+use crate::proxy;
+use crate::retry::RetryPolicy;
+use crate::storage;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+// ==================== 实时推送（WebSocket） ====================
+// 后台常驻连接，不受窗口节流或刷新影响：
+// 1. 握手请求使用当前模式的会话和 v2 签名认证（与 HTTP 接口一致）
+// 2. 订阅当前激活的 token，收到 token_updated 后直接同步本地 token
+// 3. 断线后按指数退避重连，登录或切换账号时立即重连/重新订阅
+
+// 前端事件
+pub const EVENT_PUSH_STATUS: &str = "push-status";
+pub const EVENT_TOKEN_SYNCED: &str = "token-synced";
+
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// 超过该时间没有收到任何消息视为连接已失效
+const IDLE_TIMEOUT: Duration = Duration::from_secs(75);
+// 未登录时的检查间隔
+const LOGGED_OUT_CHECK: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushStatus {
+    pub connected: bool,
+    // 断开时距下次重连的时间
+    pub reconnect_in_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenSynced {
+    pub token_id: String,
+    pub refreshed: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    TokenUpdated { token_id: String },
+    Pong,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage<'a> {
+    Subscribe { token_id: &'a str },
+    Ping,
+}
+
+lazy_static::lazy_static! {
+    static ref WAKE: Notify = Notify::new();
+}
+
+// 登录、退出或切换账号后调用：立即重新检查会话和订阅
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+// 连接期间依赖的外部状态（测试中替换）
+pub(crate) trait PushContext: Send + Sync {
+    // 当前激活的 token
+    fn active_token(&self) -> Option<String>;
+    // 建立连接时使用的会话是否仍然有效
+    fn session_valid(&self) -> bool;
+    // 订阅的 token 在服务器上已更新
+    fn token_updated(&self, token_id: String);
+}
+
+struct AppContext {
+    app: tauri::AppHandle,
+    session_token: String,
+}
+
+impl PushContext for AppContext {
+    fn active_token(&self) -> Option<String> {
+        storage::get_active_token_id()
+    }
+
+    fn session_valid(&self) -> bool {
+        commands::current_mode_sessions()
+            .is_ok_and(|sessions| sessions.iter().any(|s| s.session_token == self.session_token))
+    }
+
+    fn token_updated(&self, token_id: String) {
+        let app = self.app.clone();
+        tauri::async_runtime::spawn(async move {
+            let event = match commands::refresh_active_token(true).await {
+                Ok(result) => TokenSynced {
+                    token_id,
+                    refreshed: result.get("refreshed").and_then(|v| v.as_bool()) == Some(true),
+                    error: result.get("error").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                },
+                Err(e) => TokenSynced { token_id, refreshed: false, error: Some(e) },
+            };
+            #[cfg(debug_assertions)]
+            println!("[Push] token 同步结果: {:?}", event);
+            let _ = app.emit(EVENT_TOKEN_SYNCED, event);
+        });
+    }
+}
+
+// 在 setup 中调用，推送连接在后台运行直到程序退出
+pub fn start(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut failures: u32 = 0;
+        loop {
+            let Some(session) = commands::current_mode_sessions().ok().and_then(|s| s.into_iter().next()) else {
+                // 未登录，等待登录后唤醒
+                let _ = tokio::time::timeout(LOGGED_OUT_CHECK, WAKE.notified()).await;
+                continue;
+            };
+
+            let scope = cancel::session_scope();
+            let started = Instant::now();
+            let result = match connect(&profile::active().ws_url, &session.session_token, &session.device_id).await {
+                Ok(mut socket) => {
+                    #[cfg(debug_assertions)]
+                    println!("[Push] 已连接");
+                    let _ = app.emit(EVENT_PUSH_STATUS, PushStatus { connected: true, reconnect_in_ms: None, error: None });
+                    let context = AppContext { app: app.clone(), session_token: session.session_token.clone() };
+                    serve(&mut socket, &context, &WAKE).await
+                }
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = &result {
+                // 连接期间已退出登录或切换模式时，失败不代表当前会话
+                if e.is_session_expired() && scope.is_current() {
+                    storage::remove_code_session(&session.code);
+                }
+            }
+            // 连接保持过一段时间才重置退避，避免服务器反复断开时频繁重连
+            if started.elapsed() >= PING_INTERVAL {
+                failures = 0;
+            }
+            failures += 1;
+            let delay = RetryPolicy::PUSH.backoff(failures);
+
+            #[cfg(debug_assertions)]
+            println!("[Push] 连接断开: {:?}，{}ms 后重连", result, delay.as_millis());
+            let _ = app.emit(EVENT_PUSH_STATUS, PushStatus {
+                connected: false,
+                reconnect_in_ms: Some(delay.as_millis() as u64),
+                error: result.err().map(|e| e.to_string()),
+            });
+            // 等待期间登录或切换账号会立即重连
+            let _ = tokio::time::timeout(delay, WAKE.notified()).await;
+        }
+    });
+}
+
+fn ws_error(e: tungstenite::Error) -> ApiError {
+    if pinning::is_pin_mismatch(&e) {
+        return ApiError::CertificatePin;
+    }
+    match e {
+        tungstenite::Error::Http(response) if response.status() == 401 => ApiError::SessionExpired,
+        tungstenite::Error::Http(response) => ApiError::Http(response.status().as_u16()),
+        other => ApiError::Network(other.to_string()),
+    }
+}
+
+// 握手被 401 拒绝时先按 Date 校正时钟：偏差明显变化说明是时间戳被拒绝，按网络错误重连而不是清除会话
+fn handshake_error(e: tungstenite::Error, url: &str, signed_offset: i64) -> ApiError {
+    if let tungstenite::Error::Http(response) = &e {
+        clock::observe(url, response.headers());
+        if response.status() == 401 && clock::resync_needed(url, signed_offset) {
+            clock::record_resync(url);
+            return ApiError::Network(format!("时钟偏差 {}s，重新签名后重连", clock::offset(url)));
+        }
+    }
+    ws_error(e)
+}
+
+pub(crate) type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// 建立推送连接：代理和证书固定与 HTTP 请求一致，握手请求携带会话和 v2 签名
+pub(crate) async fn connect(url: &str, session_token: &str, device_id: &str) -> Result<Socket, ApiError> {
+    let mut request = url.into_client_request().map_err(ws_error)?;
+    let nonce = crypto::generate_nonce();
+    let signed_offset = clock::offset(url);
+    let headers = request.headers_mut();
+    for (name, value) in api::signature_headers("GET", url, &[], &nonce, device_id) {
+        let value = HeaderValue::from_str(&value).map_err(|e| ApiError::Network(e.to_string()))?;
+        headers.insert(name, value);
+    }
+    let authorization = HeaderValue::from_str(&format!("Bearer {}", session_token))
+        .map_err(|e| ApiError::Network(e.to_string()))?;
+    headers.insert("Authorization", authorization);
+
+    let uri = request.uri().clone();
+    let host = uri.host().unwrap_or_default().to_string();
+    let tls = uri.scheme_str() == Some("wss");
+    let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+    let connector = if tls {
+        let config = pinning::parse_pins(&profile::active().spki_pins)
+            .and_then(pinning::tls_config)
+            .map_err(ApiError::Network)?;
+        Some(Connector::Rustls(Arc::new(config)))
+    } else {
+        None
+    };
+
+    let handshake = async {
+        let stream = proxy::connect_tcp(&proxy::load(), &host, port, tls)
+            .await
+            .map_err(|e| ApiError::Network(e.to_string()))?;
+        tokio_tungstenite::client_async_tls_with_config(request, stream, None, connector)
+            .await
+            .map_err(|e| handshake_error(e, url, signed_offset))
+    };
+    let (socket, _) = tokio::time::timeout(RetryPolicy::PUSH.attempt_timeout, handshake)
+        .await
+        .map_err(|_| ApiError::Timeout)??;
+    Ok(socket)
+}
+
+async fn send<S>(socket: &mut WebSocketStream<S>, message: &ClientMessage<'_>) -> Result<(), ApiError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let text = serde_json::to_string(message).map_err(|e| ApiError::Parse(e.to_string()))?;
+    socket.send(Message::Text(text.into())).await.map_err(ws_error)
+}
+
+// 处理一次连接直到断开：订阅当前 token、定时心跳、分发推送
+// 服务器关闭或会话失效时返回 Ok，连接异常时返回 Err
+pub(crate) async fn serve<S>(socket: &mut WebSocketStream<S>, context: &dyn PushContext, wake: &Notify) -> Result<(), ApiError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut subscribed: Option<String> = None;
+    let mut last_seen = Instant::now();
+    let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+
+    loop {
+        // 切换账号后重新订阅
+        let active = context.active_token();
+        if let Some(token_id) = active.as_deref().filter(|id| subscribed.as_deref() != Some(*id)) {
+            send(socket, &ClientMessage::Subscribe { token_id }).await?;
+            #[cfg(debug_assertions)]
+            println!("[Push] 订阅 token: {}", token_id);
+            subscribed = active.clone();
+        }
+
+        tokio::select! {
+            message = socket.next() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                last_seen = Instant::now();
+                match message.map_err(ws_error)? {
+                    Message::Text(text) => match serde_json::from_str::<ServerMessage>(text.as_str()) {
+                        Ok(ServerMessage::TokenUpdated { token_id }) if subscribed.as_deref() == Some(token_id.as_str()) => {
+                            context.token_updated(token_id);
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            #[cfg(debug_assertions)]
+                            println!("[Push] 无法解析的消息: {} ({})", text.as_str(), e);
+                            #[cfg(not(debug_assertions))]
+                            let _ = e;
+                        }
+                    },
+                    Message::Close(_) => return Ok(()),
+                    _ => {}
+                }
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    return Err(ApiError::Timeout);
+                }
+                send(socket, &ClientMessage::Ping).await?;
+            }
+            _ = wake.notified() => {
+                if !context.session_valid() {
+                    let _ = socket.close(None).await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,164 @@
+// 推送通道测试：连接进程内 mock 服务器的 /ws
+
+use super::*;
+use atm_mock_server::{Fault, Fixtures, MockServer};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const DEVICE_ID: &str = "test-device";
+const LICENSE: &str = "TEST-AAAA-BBBB-CCCC";
+
+#[derive(Default)]
+struct TestContext {
+    active: Mutex<Option<String>>,
+    invalid: AtomicBool,
+    updates: Mutex<Vec<String>>,
+}
+
+impl TestContext {
+    fn with_active(token_id: &str) -> Self {
+        TestContext { active: Mutex::new(Some(token_id.to_string())), ..Default::default() }
+    }
+
+    fn updates(&self) -> Vec<String> {
+        self.updates.lock().unwrap().clone()
+    }
+}
+
+impl PushContext for TestContext {
+    fn active_token(&self) -> Option<String> {
+        self.active.lock().unwrap().clone()
+    }
+
+    fn session_valid(&self) -> bool {
+        !self.invalid.load(Ordering::SeqCst)
+    }
+
+    fn token_updated(&self, token_id: String) {
+        self.updates.lock().unwrap().push(token_id);
+    }
+}
+
+async fn start() -> (MockServer, Socket) {
+    let server = MockServer::start(Fixtures::sample()).await.expect("启动 mock 服务器失败");
+    let session = server.state().issue_session(LICENSE, DEVICE_ID);
+    let socket = connect(&server.ws_url(), &session, DEVICE_ID).await.unwrap();
+    (server, socket)
+}
+
+// 轮询等待条件成立
+async fn until(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("等待超时");
+}
+
+#[tokio::test]
+async fn subscribes_and_receives_token_updates() {
+    let (server, mut socket) = start().await;
+    let context = TestContext::with_active("token-1");
+    let wake = Notify::new();
+
+    tokio::select! {
+        result = serve(&mut socket, &context, &wake) => panic!("连接意外结束: {:?}", result),
+        _ = async {
+            until(|| server.state().push_subscriptions() == ["token-1"]).await;
+            assert_eq!(server.state().push_token_updated("token-1"), 1);
+            until(|| !context.updates().is_empty()).await;
+        } => {}
+    }
+    assert_eq!(context.updates(), ["token-1"]);
+}
+
+#[tokio::test]
+async fn resubscribes_after_switching_token() {
+    let (server, mut socket) = start().await;
+    let context = TestContext::with_active("token-1");
+    let wake = Notify::new();
+
+    tokio::select! {
+        result = serve(&mut socket, &context, &wake) => panic!("连接意外结束: {:?}", result),
+        _ = async {
+            until(|| server.state().push_subscriptions().len() == 1).await;
+            *context.active.lock().unwrap() = Some("token-2".to_string());
+            wake.notify_one();
+            until(|| server.state().push_subscriptions() == ["token-1", "token-2"]).await;
+            // 旧 token 的推送不再处理
+            server.state().push_token_updated("token-1");
+            server.state().push_token_updated("token-2");
+            until(|| !context.updates().is_empty()).await;
+        } => {}
+    }
+    assert_eq!(context.updates(), ["token-2"]);
+}
+
+#[tokio::test]
+async fn closes_when_session_becomes_invalid() {
+    let (server, mut socket) = start().await;
+    let context = TestContext::with_active("token-1");
+    let wake = Notify::new();
+
+    let result = tokio::time::timeout(Duration::from_secs(5), async {
+        tokio::join!(serve(&mut socket, &context, &wake), async {
+            until(|| !server.state().push_subscriptions().is_empty()).await;
+            context.invalid.store(true, Ordering::SeqCst);
+            wake.notify_one();
+        })
+        .0
+    })
+    .await
+    .expect("连接没有关闭");
+    assert!(result.is_ok(), "{:?}", result);
+}
+
+#[tokio::test]
+async fn handshake_requires_valid_session() {
+    let server = MockServer::start(Fixtures::sample()).await.unwrap();
+    let err = connect(&server.ws_url(), "not-a-session", DEVICE_ID).await.unwrap_err();
+    assert!(err.is_session_expired(), "{:?}", err);
+    assert_eq!(server.state().hits("ws"), 1);
+}
+
+#[tokio::test]
+async fn handshake_rejected_for_clock_skew_keeps_session() {
+    let server = MockServer::start(Fixtures::sample()).await.unwrap();
+    server.state().set_clock_offset(-900);
+    let session = server.state().issue_session(LICENSE, DEVICE_ID);
+
+    // 时间戳被拒绝不是会话过期，校正偏差后重连成功
+    let err = connect(&server.ws_url(), &session, DEVICE_ID).await.unwrap_err();
+    assert!(!err.is_session_expired(), "{:?}", err);
+    assert!(connect(&server.ws_url(), &session, DEVICE_ID).await.is_ok());
+    assert_eq!(clock::skew(&server.ws_url()).resyncs, 1);
+}
+
+#[tokio::test]
+async fn handshake_failure_maps_status() {
+    let server = MockServer::start(Fixtures::sample()).await.unwrap();
+    server.state().set_fault("ws", Fault { status: Some(503), ..Default::default() });
+    let session = server.state().issue_session(LICENSE, DEVICE_ID);
+    let err = connect(&server.ws_url(), &session, DEVICE_ID).await.unwrap_err();
+    assert!(matches!(err, ApiError::Http(503)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn unreachable_server_is_network_error() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let err = connect(&format!("ws://127.0.0.1:{}/ws", port), "session", DEVICE_ID).await.unwrap_err();
+    assert!(err.is_offline(), "{:?}", err);
+}
+
+#[test]
+fn reconnect_backoff_grows_and_is_capped() {
+    let policy = RetryPolicy::PUSH;
+    assert!(policy.backoff(1) <= policy.base_delay);
+    assert!(policy.backoff(4) >= policy.base_delay * 4);
+    for failures in [10, 20, 100] {
+        let delay = policy.backoff(failures);
+        assert!(delay >= policy.max_delay / 2 && delay <= policy.max_delay, "{:?}", delay);
+    }
+}
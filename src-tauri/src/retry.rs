@@ -0,0 +1,272 @@
+use crate::clock;
+use crate::endpoints;
+use crate::error::ApiError;
+use crate::metrics;
+use crate::pinning;
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::{Duration, Instant};
+
+// 关联客户端请求与服务器日志的请求头，同一次接口调用的所有尝试使用相同的 ID
+pub const REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+tokio::task_local! {
+    // 当前接口调用的请求 ID（由 api::tracked 设置）
+    static REQUEST_ID: String;
+}
+
+// 在 fut 内发出的请求都携带 request_id
+pub(crate) async fn with_request_id<F: std::future::Future>(request_id: String, fut: F) -> F::Output {
+    REQUEST_ID.scope(request_id, fut).await
+}
+
+// 当前接口调用的请求 ID，不在 with_request_id 内时为 None
+pub(crate) fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// 附加当前接口调用的请求 ID
+pub(crate) fn tag(request: RequestBuilder) -> RequestBuilder {
+    match request_id() {
+        Some(id) => request.header(REQUEST_ID_HEADER, id),
+        None => request,
+    }
+}
+
+// 请求重试策略
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    // 最大尝试次数（含首次请求）
+    pub max_attempts: u32,
+    // 指数退避的基础延迟
+    pub base_delay: Duration,
+    // 单次退避的最大延迟
+    pub max_delay: Duration,
+    // 所有尝试（含等待）的总时限
+    pub deadline: Duration,
+    // 单次请求超时
+    pub attempt_timeout: Duration,
+    // 是否幂等：非幂等请求只在请求确定未送达服务器时重试
+    pub idempotent: bool,
+}
+
+impl RetryPolicy {
+    // 读取类接口及可安全重放的写接口
+    pub const IDEMPOTENT: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(8),
+        deadline: Duration::from_secs(30),
+        attempt_timeout: Duration::from_secs(15),
+        idempotent: true,
+    };
+
+    // 后台周期性请求（心跳、版本检查），失败后等下个周期即可
+    pub const BACKGROUND: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(5),
+        deadline: Duration::from_secs(20),
+        attempt_timeout: Duration::from_secs(10),
+        idempotent: true,
+    };
+
+    // 有副作用且不可重放的接口（如解绑设备）
+    pub const NON_IDEMPOTENT: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(4),
+        deadline: Duration::from_secs(20),
+        attempt_timeout: Duration::from_secs(15),
+        idempotent: false,
+    };
+
+    // 更新下载：每次重试从断点续传，attempt_timeout 为连接建立和数据停滞的超时
+    pub const DOWNLOAD: RetryPolicy = RetryPolicy {
+        max_attempts: 6,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(15),
+        deadline: Duration::from_secs(30 * 60),
+        attempt_timeout: Duration::from_secs(30),
+        idempotent: true,
+    };
+
+    // 推送通道断线重连：一直重连（不限次数和总时长），attempt_timeout 为握手超时
+    pub const PUSH: RetryPolicy = RetryPolicy {
+        max_attempts: u32::MAX,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(60),
+        deadline: Duration::MAX,
+        attempt_timeout: Duration::from_secs(15),
+        idempotent: true,
+    };
+
+    // 第 attempt 次失败后的退避时间（带随机抖动，避免多个客户端同时重试）
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        let half = exp / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter)
+    }
+
+    fn should_retry_status(&self, status: StatusCode) -> bool {
+        match status {
+            // 服务器明确表示请求未被处理
+            StatusCode::TOO_MANY_REQUESTS => true,
+            StatusCode::REQUEST_TIMEOUT
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => self.idempotent,
+            _ => false,
+        }
+    }
+
+    fn should_retry_error(&self, e: &reqwest::Error) -> bool {
+        if pinning::is_pin_mismatch(e) {
+            // 证书固定失败不是临时故障
+            return false;
+        }
+        if e.is_connect() {
+            // 连接未建立，请求一定没有发出
+            return true;
+        }
+        self.idempotent && (e.is_timeout() || e.is_request())
+    }
+}
+
+// 测试时按比例缩短所有等待和超时时间
+#[cfg(test)]
+const TIME_SCALE: u32 = 50;
+#[cfg(not(test))]
+const TIME_SCALE: u32 = 1;
+
+pub(crate) fn scaled(d: Duration) -> Duration {
+    d / TIME_SCALE
+}
+
+// 网关错误：服务器所在区域不可用
+fn is_gateway_error(status: StatusCode) -> bool {
+    matches!(status, StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+}
+
+// 解析 Retry-After（秒数或 HTTP 日期）
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.timestamp() - chrono::Utc::now().timestamp();
+    Some(Duration::from_secs(wait.max(0) as u64))
+}
+
+// 按策略发送请求并在可重试的失败上重试
+// build 在每次尝试时都会被调用，时间戳和签名随之重新生成
+// 我们服务器响应的 Date 头用于校正时钟偏差；时间戳被拒绝时按新的偏差立即重试一次（第三方接口不参与）
+// 每次尝试的结果计入对应服务器地址的健康状况，地址刚被暂停时不等待，立即由 build 改用其他地址
+// 返回最后一次收到的响应（状态码由调用方处理）
+pub async fn send<F>(name: &str, policy: &RetryPolicy, mut build: F) -> Result<Response, ApiError>
+where
+    F: FnMut() -> RequestBuilder,
+{
+    let started = Instant::now();
+    let deadline = scaled(policy.deadline);
+    let attempt_timeout = scaled(policy.attempt_timeout);
+    let mut attempt: u32 = 0;
+    let mut resynced = false;
+
+    loop {
+        attempt += 1;
+        let remaining = deadline.saturating_sub(started.elapsed());
+        let (client, request) = tag(build()).timeout(remaining.min(attempt_timeout)).build_split();
+        let url = request.as_ref().map(|r| r.url().to_string()).unwrap_or_default();
+        // build 刚按这个偏差签名
+        let signed_offset = clock::offset(&url);
+        let result = match request {
+            Ok(request) => client.execute(request).await,
+            Err(e) => Err(e),
+        };
+        let ours = endpoints::is_ours(&url);
+        let failover = ours && match &result {
+            Ok(response) => endpoints::record(&url, is_gateway_error(response.status())),
+            // 证书固定失败不代表服务器不可用，不计入
+            Err(e) if pinning::is_pin_mismatch(e) => false,
+            Err(e) => endpoints::record(&url, e.is_connect() || e.is_timeout() || e.is_request()),
+        };
+
+        if let Some(response) = result.as_ref().ok().filter(|_| ours) {
+            clock::observe(&url, response.headers());
+            // 服务器拒绝的请求使用了偏差很大的时间戳：已按 Date 校正，重新签名后重试（只重试一次）
+            if response.status() == StatusCode::UNAUTHORIZED && !resynced && clock::resync_needed(&url, signed_offset) {
+                resynced = true;
+                clock::record_resync(&url);
+                #[cfg(debug_assertions)]
+                println!("[API] {} 返回 401，时钟偏差 {}s，重新签名后重试", name, clock::offset(&url));
+                continue;
+            }
+        }
+
+        let delay = scaled(match &result {
+            Ok(response) => {
+                let status = response.status();
+                if !policy.should_retry_status(status) {
+                    return result.map_err(ApiError::from);
+                }
+                if failover {
+                    Duration::ZERO
+                } else if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+                    retry_after(response).unwrap_or_else(|| policy.backoff(attempt))
+                } else {
+                    policy.backoff(attempt)
+                }
+            }
+            Err(e) => {
+                if !policy.should_retry_error(e) {
+                    return result.map_err(ApiError::from);
+                }
+                if failover {
+                    Duration::ZERO
+                } else {
+                    policy.backoff(attempt)
+                }
+            }
+        });
+
+        // 次数用尽或等待后会超出总时限，返回最后一次结果
+        if attempt >= policy.max_attempts || started.elapsed() + delay >= deadline {
+            return result.map_err(ApiError::from);
+        }
+
+        #[cfg(debug_assertions)]
+        match &result {
+            Ok(r) => println!("[API] {} 返回 {}，{}ms 后重试 ({}/{})", name, r.status(), delay.as_millis(), attempt, policy.max_attempts),
+            Err(e) => println!("[API] {} 请求失败: {:?}，{}ms 后重试 ({}/{})", name, e, delay.as_millis(), attempt, policy.max_attempts),
+        }
+        metrics::record_retry(name);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// 不经过 send 的重试（如分段下载）：判断错误是否可重试，是则等待退避时间
+// 次数或总时限用尽、错误不可重试时返回 false
+pub async fn pause(name: &str, policy: &RetryPolicy, attempt: u32, started: Instant, error: &ApiError) -> bool {
+    let retryable = match error {
+        ApiError::Network(_) | ApiError::Timeout => true,
+        ApiError::Http(status) => StatusCode::from_u16(*status).is_ok_and(|s| policy.should_retry_status(s)),
+        _ => false,
+    };
+    let delay = scaled(policy.backoff(attempt));
+    if !retryable || attempt >= policy.max_attempts || started.elapsed() + delay >= scaled(policy.deadline) {
+        return false;
+    }
+
+    #[cfg(debug_assertions)]
+    println!("[API] {} 失败: {:?}，{}ms 后重试 ({}/{})", name, error, delay.as_millis(), attempt, policy.max_attempts);
+    metrics::record_retry(name);
+    tokio::time::sleep(delay).await;
+    true
+}
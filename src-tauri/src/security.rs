@@ -172,8 +172,9 @@ pub fn start_heartbeat_loop(_app_handle: AppHandle) {
     }
 }
 
-pub fn verify_timestamp(timestamp: i64) -> bool {
-    let now = chrono::Utc::now().timestamp();
+// 按 server_url 所在服务器的时钟（已校正偏差）检查时间戳
+pub fn verify_timestamp(server_url: &str, timestamp: i64) -> bool {
+    let now = crate::clock::now(server_url);
     let diff = (now - timestamp).abs();
     // 允许 5 分钟的时间差
     diff < 300
@@ -0,0 +1,117 @@
+use crate::cancel::Scope;
+use crate::error::ApiError;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+
+// ==================== 合并并发调用 ====================
+// 相同 key 的并发调用共享同一个进行中的 future 和结果：
+// - 第一个调用者发起执行，之后的调用者等待同一个结果，不再重复请求
+// - 执行完成后移除，之后的调用重新执行（不缓存结果）
+// - 只合并同一会话代的调用：退出登录、切换模式后的调用不会等待旧会话代的执行
+// - 执行在独立的任务中运行：会话代变化或最后一个调用者被取消时中止任务（请求随之中止）并移除
+// key 由操作名和 token id / 会话组成
+
+type Call<T> = Shared<BoxFuture<'static, Result<T, ApiError>>>;
+
+struct Entry<T> {
+    call: Call<T>,
+    abort: AbortHandle,
+}
+
+type Calls<T> = Arc<Mutex<HashMap<String, Entry<T>>>>;
+
+pub struct Group<T> {
+    calls: Calls<T>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Group<T> {
+    pub fn new() -> Self {
+        Group { calls: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    // 有相同 key 的执行在进行中时等待它的结果，否则用 make 创建并在 scope 的会话代中执行
+    pub async fn run<F>(&self, key: String, scope: &Scope, make: impl FnOnce() -> F) -> Result<T, ApiError>
+    where
+        F: Future<Output = Result<T, ApiError>> + Send + 'static,
+    {
+        let key = format!("{}@{}", key, scope.generation());
+        let call = {
+            let mut calls = lock(&self.calls);
+            calls.entry(key.clone()).or_insert_with(|| spawn(&self.calls, key.clone(), scope.clone(), make())).call.clone()
+        };
+        let mut waiter = Waiter { calls: &self.calls, key, call };
+        (&mut waiter.call).await
+    }
+
+    // 进行中的执行数
+    #[cfg(test)]
+    pub(crate) fn in_flight(&self) -> usize {
+        lock(&self.calls).len()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for Group<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lock<T>(calls: &Calls<T>) -> std::sync::MutexGuard<'_, HashMap<String, Entry<T>>> {
+    calls.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// 在独立任务中执行，完成或会话代变化后移除自己
+fn spawn<T, F>(calls: &Calls<T>, key: String, scope: Scope, fut: F) -> Entry<T>
+where
+    T: Clone + Send + Sync + 'static,
+    F: Future<Output = Result<T, ApiError>> + Send + 'static,
+{
+    let calls = calls.clone();
+    let task = tokio::spawn(async move {
+        let result = scope.run(fut).await;
+        // 被中止的执行已由调用者移除，相同 key 可能已是新的执行
+        let mut calls = lock(&calls);
+        if calls.get(&key).is_some_and(|entry| entry.abort.id() == tokio::task::id()) {
+            calls.remove(&key);
+        }
+        result
+    });
+    let abort = task.abort_handle();
+    let call = task
+        .map(|joined| match joined {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(ApiError::Cancelled),
+        })
+        .boxed()
+        .shared();
+    Entry { call, abort }
+}
+
+// 等待中的调用者；最后一个调用者在执行完成前被取消时中止执行
+struct Waiter<'a, T> {
+    calls: &'a Calls<T>,
+    key: String,
+    call: Call<T>,
+}
+
+impl<T> Drop for Waiter<'_, T> {
+    fn drop(&mut self) {
+        let mut calls = lock(self.calls);
+        // 映射表和自己各持有一份：没有其他调用者在等待
+        let abandoned = calls
+            .get(&self.key)
+            .is_some_and(|entry| entry.call.ptr_eq(&self.call) && entry.call.strong_count() == Some(2));
+        if abandoned {
+            if let Some(entry) = calls.remove(&self.key) {
+                entry.abort.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,112 @@
+// 合并并发调用测试：用计数器代替网络请求，用局部的 Generation 代替全局会话代
+
+use super::*;
+use crate::cancel::Generation;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// 执行次数和执行完成次数
+#[derive(Default)]
+struct Counter {
+    runs: AtomicU32,
+    finished: AtomicU32,
+}
+
+fn counted(counter: &Arc<Counter>, value: u32) -> impl Future<Output = Result<u32, ApiError>> + Send + 'static {
+    let counter = counter.clone();
+    async move {
+        counter.runs.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        counter.finished.fetch_add(1, Ordering::SeqCst);
+        Ok(value)
+    }
+}
+
+#[tokio::test]
+async fn concurrent_calls_with_same_key_share_one_execution() {
+    let (group, session, counter) = (Group::new(), Generation::new(), Arc::new(Counter::default()));
+    let scope = session.scope();
+    let (a, b, c) = tokio::join!(
+        group.run("tokens/check:a".to_string(), &scope, || counted(&counter, 1)),
+        group.run("tokens/check:a".to_string(), &scope, || counted(&counter, 2)),
+        group.run("tokens/check:a".to_string(), &scope, || counted(&counter, 3)),
+    );
+    assert_eq!((a.unwrap(), b.unwrap(), c.unwrap()), (1, 1, 1));
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 1);
+    assert_eq!(group.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn different_keys_and_later_calls_execute_again() {
+    let (group, session, counter) = (Group::new(), Generation::new(), Arc::new(Counter::default()));
+    let scope = session.scope();
+    let (a, b) = tokio::join!(
+        group.run("tokens/check:a".to_string(), &scope, || counted(&counter, 1)),
+        group.run("tokens/check:b".to_string(), &scope, || counted(&counter, 2)),
+    );
+    assert_eq!((a.unwrap(), b.unwrap()), (1, 2));
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 2);
+
+    // 完成后不缓存结果
+    assert_eq!(group.run("tokens/check:a".to_string(), &scope, || counted(&counter, 4)).await.unwrap(), 4);
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn cancelled_caller_leaves_execution_for_others() {
+    let (group, session, counter) = (Group::new(), Generation::new(), Arc::new(Counter::default()));
+    let scope = session.scope();
+    let (first, second) = tokio::join!(
+        tokio::time::timeout(
+            Duration::from_millis(10),
+            group.run("tokens/activate:a".to_string(), &scope, || counted(&counter, 1)),
+        ),
+        group.run("tokens/activate:a".to_string(), &scope, || counted(&counter, 2)),
+    );
+    assert!(first.is_err());
+
+    // 还有调用者在等待时继续执行
+    assert_eq!(second.unwrap(), 1);
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 1);
+    assert_eq!(group.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn abandoned_execution_is_aborted_and_removed() {
+    let (group, session, counter) = (Group::new(), Generation::new(), Arc::new(Counter::default()));
+    let scope = session.scope();
+    // 所有调用者都被取消：中止执行并移除 key
+    let first = tokio::time::timeout(
+        Duration::from_millis(10),
+        group.run("tokens:session".to_string(), &scope, || counted(&counter, 1)),
+    )
+    .await;
+    assert!(first.is_err());
+    assert_eq!(group.in_flight(), 0);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 1);
+    assert_eq!(counter.finished.load(Ordering::SeqCst), 0);
+
+    assert_eq!(group.run("tokens:session".to_string(), &scope, || counted(&counter, 2)).await.unwrap(), 2);
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn generation_change_aborts_execution_and_is_not_joined() {
+    let (group, session, counter) = (Group::new(), Generation::new(), Arc::new(Counter::default()));
+    let old = session.scope();
+    let pending = group.run("tokens:session".to_string(), &old, || counted(&counter, 1));
+    let next = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // 退出登录后再次登录：新的调用不等待旧会话代的执行
+        session.bump();
+        group.run("tokens:session".to_string(), &session.scope(), || counted(&counter, 2)).await
+    };
+    let (old_result, new_result) = tokio::join!(pending, next);
+    assert!(matches!(old_result, Err(ApiError::Cancelled)), "{:?}", old_result);
+    assert_eq!(new_result.unwrap(), 2);
+    assert_eq!(counter.runs.load(Ordering::SeqCst), 2);
+    assert_eq!(counter.finished.load(Ordering::SeqCst), 1);
+    assert_eq!(group.in_flight(), 0);
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
+use std::collections::HashMap;
 use std::sync::RwLock;
+use crate::api::TokenInfo;
 use crate::crypto;
+use crate::version::UpdateChannel;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Session {
@@ -101,6 +104,21 @@ pub fn ensure_data_dir() {
     }
 }
 
+// 检查数据目录中的加密文件都能用本机密钥解密（更新后启动检查使用）
+pub fn verify_readable() -> Result<(), String> {
+    let Ok(entries) = fs::read_dir(get_data_dir()) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "enc") {
+            let encrypted = fs::read_to_string(&path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+            crypto::decrypt_local_data(&encrypted).map_err(|e| format!("解密 {} 失败: {}", path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
 pub fn set_session(session: Session) {
     if let Ok(mut s) = CURRENT_SESSION.write() {
         *s = session;
@@ -404,6 +422,7 @@ pub fn remove_code_session(code: &str) {
     multi.sessions.retain(|s| s.code != code);
     
     save_sessions_encrypted(&multi);
+    remove_cached_tokens(code);
 }
 
 pub fn clear_all_sessions() {
@@ -411,6 +430,7 @@ pub fn clear_all_sessions() {
     if file.exists() {
         fs::remove_file(&file).ok();
     }
+    clear_token_cache();
     // 也删除旧的明文文件
     let mut old_file = get_data_dir();
     old_file.push("sessions.json");
@@ -450,6 +470,128 @@ pub struct LicenseInfo {
     pub is_auto_switch: bool,
 }
 
+// ==================== Token 列表离线缓存（加密） ====================
+// 按激活码缓存最近一次成功获取的 Token 列表，断网时展示
+// 只缓存 TokenInfo 元数据，不包含任何凭据
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedTokens {
+    pub tokens: Vec<TokenInfo>,
+    pub fetched_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct TokenCache {
+    // 缓存键（激活码，多个时按字母序逗号连接） -> 缓存
+    entries: HashMap<String, CachedTokens>,
+}
+
+fn get_token_cache_file() -> PathBuf {
+    let mut path = get_data_dir();
+    path.push("token_cache.enc");
+    path
+}
+
+fn load_token_cache() -> TokenCache {
+    fs::read_to_string(get_token_cache_file())
+        .ok()
+        .and_then(|encrypted| crypto::decrypt_local_data(&encrypted).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn save_token_cache(cache: &TokenCache) {
+    ensure_data_dir();
+    if let Ok(json) = serde_json::to_string(cache) {
+        if let Ok(encrypted) = crypto::encrypt_local_data(&json) {
+            fs::write(get_token_cache_file(), encrypted).ok();
+        }
+    }
+}
+
+// 缓存键：同一组激活码合并后的列表共用一个缓存
+pub fn token_cache_key(codes: &[&str]) -> String {
+    let mut codes: Vec<&str> = codes.to_vec();
+    codes.sort_unstable();
+    codes.dedup();
+    codes.join(",")
+}
+
+// 保存 Token 列表，返回获取时间
+pub fn save_cached_tokens(key: &str, tokens: &[TokenInfo]) -> i64 {
+    let fetched_at = chrono::Utc::now().timestamp();
+    let mut cache = load_token_cache();
+    cache.entries.insert(key.to_string(), CachedTokens { tokens: tokens.to_vec(), fetched_at });
+    save_token_cache(&cache);
+    fetched_at
+}
+
+pub fn load_cached_tokens(key: &str) -> Option<CachedTokens> {
+    load_token_cache().entries.remove(key)
+}
+
+// 删除包含该激活码的缓存（会话失效或激活码被移除时）
+fn remove_cached_tokens(code: &str) {
+    let mut cache = load_token_cache();
+    let before = cache.entries.len();
+    cache.entries.retain(|key, _| !key.split(',').any(|c| c == code));
+    if cache.entries.len() != before {
+        save_token_cache(&cache);
+    }
+}
+
+pub fn clear_token_cache() {
+    let file = get_token_cache_file();
+    if file.exists() {
+        fs::remove_file(&file).ok();
+    }
+}
+
+// ==================== 已关闭的公告（加密） ====================
+// 保存在数据目录而不是 webview 的 localStorage，清理浏览器数据后不会重新弹出
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct DismissedAnnouncements {
+    // 公告 ID -> 关闭时间
+    ids: HashMap<String, i64>,
+}
+
+fn get_dismissed_announcements_file() -> PathBuf {
+    let mut path = get_data_dir();
+    path.push("announcements.enc");
+    path
+}
+
+fn load_dismissed_announcements() -> DismissedAnnouncements {
+    fs::read_to_string(get_dismissed_announcements_file())
+        .ok()
+        .and_then(|encrypted| crypto::decrypt_local_data(&encrypted).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn dismiss_announcement(id: &str) {
+    let mut dismissed = load_dismissed_announcements();
+    dismissed.ids.insert(id.to_string(), chrono::Utc::now().timestamp());
+    ensure_data_dir();
+    if let Ok(json) = serde_json::to_string(&dismissed) {
+        if let Ok(encrypted) = crypto::encrypt_local_data(&json) {
+            fs::write(get_dismissed_announcements_file(), encrypted).ok();
+        }
+    }
+}
+
+pub fn dismissed_announcement_ids() -> std::collections::HashSet<String> {
+    load_dismissed_announcements().ids.into_keys().collect()
+}
+
+pub fn clear_dismissed_announcements() {
+    let file = get_dismissed_announcements_file();
+    if file.exists() {
+        fs::remove_file(&file).ok();
+    }
+}
+
 fn get_license_normal_file() -> std::path::PathBuf {
     let mut path = get_data_dir();
     path.push("license_normal.enc");  // 加密存储
@@ -577,6 +719,27 @@ pub fn get_current_mode() -> String {
     "normal".to_string()
 }
 
+// ==================== 更新频道 ====================
+
+fn get_update_channel_file() -> std::path::PathBuf {
+    let mut path = get_data_dir();
+    path.push("update_channel.dat");
+    path
+}
+
+pub fn save_update_channel(channel: UpdateChannel) -> Result<(), String> {
+    ensure_data_dir();
+    fs::write(get_update_channel_file(), channel.as_str()).map_err(|e| format!("保存更新频道失败: {}", e))
+}
+
+// 未设置或内容无效时使用正式版频道
+pub fn get_update_channel() -> UpdateChannel {
+    fs::read_to_string(get_update_channel_file())
+        .ok()
+        .and_then(|content| UpdateChannel::parse(&content))
+        .unwrap_or_default()
+}
+
 // 清除指定类型的激活码
 pub fn clear_license(is_auto_switch: bool) {
     let file = if is_auto_switch {
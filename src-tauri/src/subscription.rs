@@ -0,0 +1,113 @@
+use crate::error::ApiError;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+// ==================== 订阅/余额模型 ====================
+// Factory 订阅接口的响应格式不受我们控制：
+// - 反序列化尽量宽松（数字可能是整数、小数或字符串），未知字段原样保留在 extra 中
+// - 前端只使用归一化后的 UsageSummary，上游格式变化时在 summary() 处报错，而不是界面显示错误的余额
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    // 套餐名称
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64", skip_serializing_if = "Option::is_none")]
+    pub premium_usage: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_i64", skip_serializing_if = "Option::is_none")]
+    pub premium_limit: Option<i64>,
+    // 额度重置时间：秒/毫秒时间戳或 RFC 3339 字符串
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset_date: Option<Value>,
+    // 是否允许超额使用
+    #[serde(default, deserialize_with = "lenient_bool", skip_serializing_if = "Option::is_none")]
+    pub overage_enabled: Option<bool>,
+    // 已产生的超额用量
+    #[serde(default, deserialize_with = "lenient_i64", skip_serializing_if = "Option::is_none")]
+    pub overage_usage: Option<i64>,
+    // 未识别的字段，序列化时原样输出
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// 归一化的余额信息（前端只依赖这个结构）
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSummary {
+    pub plan: Option<String>,
+    pub used: i64,
+    pub limit: i64,
+    pub remaining: i64,
+    // 已用比例，limit 为 0 时为 0
+    pub used_ratio: f64,
+    // 重置时间（秒级时间戳）
+    pub resets_at: Option<i64>,
+    // 用量已超过额度
+    pub over_limit: bool,
+    pub overage_enabled: bool,
+    pub overage_used: i64,
+}
+
+impl Subscription {
+    pub fn from_value(value: Value) -> Result<Self, ApiError> {
+        serde_json::from_value(value).map_err(|e| ApiError::Parse(format!("subscription: {}", e)))
+    }
+
+    pub fn summary(&self) -> Result<UsageSummary, ApiError> {
+        let used = self.premium_usage.ok_or_else(|| ApiError::Parse("subscription missing premiumUsage".to_string()))?;
+        let limit = self.premium_limit.ok_or_else(|| ApiError::Parse("subscription missing premiumLimit".to_string()))?;
+        if used < 0 || limit < 0 {
+            return Err(ApiError::Parse(format!("subscription usage out of range: {}/{}", used, limit)));
+        }
+        let resets_at = match &self.reset_date {
+            None | Some(Value::Null) => None,
+            Some(value) => Some(parse_timestamp(value).ok_or_else(|| {
+                ApiError::Parse(format!("subscription resetDate not a timestamp: {}", value))
+            })?),
+        };
+        let overage_used = self.overage_usage.unwrap_or(0).max(0);
+        Ok(UsageSummary {
+            plan: self.plan.clone(),
+            used,
+            limit,
+            remaining: (limit - used).max(0),
+            used_ratio: if limit > 0 { used as f64 / limit as f64 } else { 0.0 },
+            resets_at,
+            over_limit: used > limit || overage_used > 0,
+            overage_enabled: self.overage_enabled.unwrap_or(false),
+            overage_used,
+        })
+    }
+}
+
+// 整数、小数（向下取整）或数字字符串
+fn lenient_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f.floor() as i64)),
+        Value::String(s) => s.trim().parse::<f64>().ok().map(|f| f.floor() as i64),
+        _ => None,
+    })
+}
+
+// 布尔值、0/1 或 "true"/"false"
+fn lenient_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(b) => Some(b),
+        Value::Number(n) => n.as_i64().map(|n| n != 0),
+        Value::String(s) => s.trim().parse::<bool>().ok(),
+        _ => None,
+    })
+}
+
+// 秒/毫秒时间戳（大于 1e12 视为毫秒）或 RFC 3339 字符串，统一为秒
+fn parse_timestamp(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64().map(|t| if t > 1_000_000_000_000 { t / 1000 } else { t }),
+        Value::String(s) => chrono::DateTime::parse_from_rfc3339(s.trim()).ok().map(|t| t.timestamp()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,41 @@
+// 订阅数据解析测试：上游响应格式的各种变体（数字写成字符串或小数、毫秒时间戳、未知字段）
+
+use super::*;
+
+#[test]
+fn subscription_tolerates_shape_variations() {
+    let subscription = Subscription::from_value(serde_json::json!({
+        "plan": "pro",
+        "premiumUsage": "1200.5",
+        "premiumLimit": 1000.0,
+        "resetDate": "2026-11-01T00:00:00Z",
+        "overageEnabled": 1,
+        "orgId": "org-1"
+    }))
+    .unwrap();
+    // 未知字段原样保留
+    let raw = serde_json::to_value(&subscription).unwrap();
+    assert_eq!(raw["orgId"], "org-1");
+    assert_eq!(raw["premiumUsage"], 1200);
+
+    let summary = subscription.summary().unwrap();
+    assert_eq!(summary.plan.as_deref(), Some("pro"));
+    assert_eq!((summary.used, summary.limit, summary.remaining), (1200, 1000, 0));
+    assert!(summary.over_limit && summary.overage_enabled);
+    assert_eq!(summary.resets_at, Some(1_793_491_200));
+
+    // 毫秒时间戳
+    let subscription = Subscription::from_value(serde_json::json!({
+        "premiumUsage": 0, "premiumLimit": 0, "resetDate": 1_793_491_200_000i64
+    }))
+    .unwrap();
+    let summary = subscription.summary().unwrap();
+    assert_eq!(summary.resets_at, Some(1_793_491_200));
+    assert_eq!(summary.used_ratio, 0.0);
+
+    let bad_date = Subscription::from_value(serde_json::json!({
+        "premiumUsage": 1, "premiumLimit": 2, "resetDate": "next month"
+    }))
+    .unwrap();
+    assert!(matches!(bad_date.summary(), Err(ApiError::Parse(_))));
+}
@@ -0,0 +1,88 @@
+use crate::api::UpdateInfo;
+use crate::crypto;
+use crate::error::ApiError;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+// ==================== 更新包校验 ====================
+// 安装前必须通过两步校验，任一失败都不会替换当前程序：
+// 1. 版本信息中的签名：用内置公钥校验 (version, filename, sha256)
+// 2. 下载的文件：计算 SHA-256 与已签名的摘要比对
+// 下载地址不参与签名，被篡改的地址最多导致下载失败，不会执行未签名的文件
+
+// 签名校验通过的发布信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedRelease {
+    pub version: String,
+    pub filename: String,
+    pub sha256: String,
+}
+
+fn rejected(reason: impl Into<String>) -> ApiError {
+    ApiError::UpdateVerification(reason.into())
+}
+
+// 校验版本信息的签名，public_key 为空（未内置公钥）时拒绝
+pub fn verify_release(info: &UpdateInfo, public_key: Option<&str>) -> Result<VerifiedRelease, ApiError> {
+    let public_key = public_key.ok_or_else(|| rejected("客户端未配置更新签名公钥"))?;
+    let version = info.version.as_deref().ok_or_else(|| rejected("缺少版本号"))?;
+    let filename = info.filename.as_deref().ok_or_else(|| rejected("缺少文件名"))?;
+    let sha256 = info.sha256.as_deref().ok_or_else(|| rejected("缺少文件摘要"))?;
+    let signature = info.signature.as_deref().ok_or_else(|| rejected("缺少签名"))?;
+
+    let sha256 = sha256.trim().to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(rejected("文件摘要格式错误"));
+    }
+    let message = crypto::update_signing_message(version, filename, &sha256);
+    crypto::verify_update_signature(public_key, &message, signature).map_err(rejected)?;
+
+    Ok(VerifiedRelease { version: version.to_string(), filename: filename.to_string(), sha256 })
+}
+
+// 计算文件 SHA-256（hex）
+pub fn file_sha256(path: &Path) -> Result<String, ApiError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// 校验下载的文件与签名的摘要一致
+pub fn verify_file(path: &Path, release: &VerifiedRelease) -> Result<(), ApiError> {
+    let actual = file_sha256(path)?;
+    if actual != release.sha256 {
+        return Err(rejected(format!("文件摘要不匹配（期望 {}，实际 {}）", release.sha256, actual)));
+    }
+    Ok(())
+}
+
+// 补丁解压后的大小上限（bsdiff 的输出不超过补丁数据量），防止损坏的补丁耗尽内存
+const MAX_PATCHED_SIZE: u64 = 256 * 1024 * 1024;
+
+// 把增量补丁（gzip 压缩的 bsdiff 补丁）应用到当前程序，结果与签名的摘要一致时才写入 output
+// 补丁本身不签名：任何篡改或基础文件不一致都会在摘要比对时被拒绝
+pub fn apply_patch(current: &Path, patch: &Path, output: &Path, release: &VerifiedRelease) -> Result<(), ApiError> {
+    let old = std::fs::read(current)?;
+    let mut decoder = flate2::read::GzDecoder::new(std::fs::File::open(patch)?).take(MAX_PATCHED_SIZE);
+    let mut patched = Vec::new();
+    bsdiff::patch(&old, &mut decoder, &mut patched).map_err(|e| rejected(format!("补丁无法应用: {}", e)))?;
+
+    let actual = hex::encode(Sha256::digest(&patched));
+    if actual != release.sha256 {
+        return Err(rejected(format!("补丁结果摘要不匹配（期望 {}，实际 {}）", release.sha256, actual)));
+    }
+    std::fs::write(output, &patched)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,128 @@
+// 更新包校验测试：签名由 mock 服务器的固定私钥生成
+
+use super::*;
+
+const VERSION: &str = "9.9.9";
+const FILENAME: &str = "ATM-Tray-9.9.9.exe";
+
+fn signed_info(content: &[u8]) -> UpdateInfo {
+    let sha256 = hex::encode(Sha256::digest(content));
+    UpdateInfo {
+        has_update: true,
+        version: Some(VERSION.to_string()),
+        filename: Some(FILENAME.to_string()),
+        size: Some(content.len() as i64),
+        changelog: None,
+        force_update: None,
+        download_url: Some(format!("/client/download/{}", FILENAME)),
+        signature: Some(atm_mock_server::sign_update(VERSION, FILENAME, &sha256)),
+        sha256: Some(sha256),
+        patch: None,
+    }
+}
+
+fn key() -> String {
+    atm_mock_server::update_public_key()
+}
+
+fn temp_file(content: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("atm-update-test-{}.exe", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn accepts_signed_release_and_matching_file() {
+    let content = b"new version".repeat(1000);
+    let release = verify_release(&signed_info(&content), Some(&key())).unwrap();
+    assert_eq!(release.version, VERSION);
+    assert_eq!(release.filename, FILENAME);
+
+    let path = temp_file(&content);
+    verify_file(&path, &release).unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn rejects_file_with_different_digest() {
+    let release = verify_release(&signed_info(b"new version"), Some(&key())).unwrap();
+    let path = temp_file(b"malicious payload");
+    let err = verify_file(&path, &release).unwrap_err();
+    assert!(matches!(err, ApiError::UpdateVerification(_)), "{:?}", err);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn rejects_tampered_metadata() {
+    // 换成其他文件的摘要
+    let mut info = signed_info(b"new version");
+    info.sha256 = Some(hex::encode(Sha256::digest(b"malicious payload")));
+    assert!(matches!(verify_release(&info, Some(&key())), Err(ApiError::UpdateVerification(_))));
+
+    // 签名覆盖版本号和文件名
+    let mut info = signed_info(b"new version");
+    info.version = Some("10.0.0".to_string());
+    assert!(matches!(verify_release(&info, Some(&key())), Err(ApiError::UpdateVerification(_))));
+    let mut info = signed_info(b"new version");
+    info.filename = Some("other.exe".to_string());
+    assert!(matches!(verify_release(&info, Some(&key())), Err(ApiError::UpdateVerification(_))));
+}
+
+#[test]
+fn rejects_missing_signature_or_key() {
+    let mut info = signed_info(b"new version");
+    info.signature = None;
+    assert!(matches!(verify_release(&info, Some(&key())), Err(ApiError::UpdateVerification(_))));
+
+    let info = signed_info(b"new version");
+    assert!(matches!(verify_release(&info, None), Err(ApiError::UpdateVerification(_))));
+}
+
+#[test]
+fn rejects_signature_from_other_key() {
+    use base64::Engine as _;
+    let other = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+    let err = verify_release(&signed_info(b"new version"), Some(&other)).unwrap_err();
+    assert!(matches!(err, ApiError::UpdateVerification(_)), "{:?}", err);
+}
+
+#[test]
+fn applies_patch_matching_signed_digest() {
+    let old = b"old version".repeat(1000);
+    let new = [b"new version".repeat(900), b"old version".repeat(100)].concat();
+    let release = verify_release(&signed_info(&new), Some(&key())).unwrap();
+    let current = temp_file(&old);
+    let patch = temp_file(&atm_mock_server::patch_bytes(&old, &new));
+    let output = std::env::temp_dir().join(format!("atm-update-test-{}.exe", uuid::Uuid::new_v4()));
+
+    apply_patch(&current, &patch, &output, &release).unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), new);
+    for path in [current, patch, output] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn rejects_patch_for_other_base_or_corrupt_patch() {
+    let old = b"old version".repeat(1000);
+    let new = b"new version".repeat(1000);
+    let release = verify_release(&signed_info(&new), Some(&key())).unwrap();
+    let output = std::env::temp_dir().join(format!("atm-update-test-{}.exe", uuid::Uuid::new_v4()));
+    let patch_data = atm_mock_server::patch_bytes(&old, &new);
+
+    // 基础文件不是补丁对应的版本：结果摘要不匹配
+    let other = temp_file(b"other version");
+    let patch = temp_file(&patch_data);
+    let err = apply_patch(&other, &patch, &output, &release).unwrap_err();
+    assert_eq!(err.code(), "UPDATE_VERIFICATION_FAILED");
+    assert!(!output.exists());
+
+    // 截断的补丁
+    let current = temp_file(&old);
+    let truncated = temp_file(&patch_data[..patch_data.len() / 2]);
+    assert!(apply_patch(&current, &truncated, &output, &release).is_err());
+    assert!(!output.exists());
+    for path in [other, patch, current, truncated] {
+        let _ = std::fs::remove_file(path);
+    }
+}
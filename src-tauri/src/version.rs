@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+// ==================== 版本号与更新频道 ====================
+// 按语义化版本（semver 2.0）比较：
+// - 主/次/修订号缺省视为 0（兼容 "2.2" 这类旧版本号），允许 "v" 前缀
+// - 预发布版本低于对应正式版：2.3.0-beta.1 < 2.3.0-beta.2 < 2.3.0-rc.1 < 2.3.0
+// - 构建元数据（+ 之后的部分）不参与比较
+
+// 预发布标识：数字标识低于字母标识（枚举顺序即比较顺序）
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Numeric(u64),
+    Alpha(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    core: [u64; 3],
+    pre: Vec<Identifier>,
+}
+
+impl Version {
+    pub fn parse(text: &str) -> Option<Version> {
+        let text = text.trim().trim_start_matches('v');
+        let text = text.split_once('+').map_or(text, |(v, _)| v);
+        let (core_text, pre_text) = match text.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (text, None),
+        };
+
+        let mut core = [0u64; 3];
+        let parts: Vec<&str> = core_text.split('.').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        for (slot, part) in core.iter_mut().zip(&parts) {
+            *slot = part.parse().ok()?;
+        }
+
+        let mut pre = Vec::new();
+        if let Some(pre_text) = pre_text {
+            for part in pre_text.split('.') {
+                if part.is_empty() || !part.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+                    return None;
+                }
+                pre.push(match part.parse() {
+                    Ok(n) => Identifier::Numeric(n),
+                    Err(_) => Identifier::Alpha(part.to_string()),
+                });
+            }
+        }
+        Some(Version { core, pre })
+    }
+
+    // 预发布版本（beta、rc 等）
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.core.cmp(&other.core).then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            // 正式版高于同号的预发布版本
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            // 逐个比较标识，前缀相同时标识少的较低
+            (false, false) => self.pre.cmp(&other.pre),
+        })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// candidate 是否比 current 新，任一版本号无法解析时返回 false
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    match (Version::parse(candidate), Version::parse(current)) {
+        (Some(candidate), Some(current)) => candidate > current,
+        _ => false,
+    }
+}
+
+// 两个版本号是否相同（忽略 "v" 前缀和构建元数据），任一无法解析时返回 false
+pub fn is_same(a: &str, b: &str) -> bool {
+    match (Version::parse(a), Version::parse(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+// 版本号是否为预发布版本
+pub fn is_prerelease(version: &str) -> bool {
+    Version::parse(version).is_some_and(|v| v.is_prerelease())
+}
+
+// 更新频道：stable 只接收正式版，beta 同时接收测试版和正式版
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl UpdateChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Beta => "beta",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<UpdateChannel> {
+        match text.trim() {
+            "stable" => Some(UpdateChannel::Stable),
+            "beta" => Some(UpdateChannel::Beta),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,51 @@
+// 版本号比较测试（semver 2.0 规范中的示例顺序）
+
+use super::*;
+
+#[test]
+fn orders_prerelease_identifiers() {
+    let ordered = [
+        "1.0.0-alpha",
+        "1.0.0-alpha.1",
+        "1.0.0-alpha.beta",
+        "1.0.0-beta",
+        "1.0.0-beta.2",
+        "1.0.0-beta.11",
+        "1.0.0-rc.1",
+        "1.0.0",
+        "1.0.1-beta.1",
+        "1.0.1",
+        "1.1.0",
+        "2.0.0",
+    ];
+    for pair in ordered.windows(2) {
+        assert!(is_newer(pair[1], pair[0]), "{} > {}", pair[1], pair[0]);
+        assert!(!is_newer(pair[0], pair[1]), "{} < {}", pair[0], pair[1]);
+    }
+}
+
+#[test]
+fn prerelease_is_older_than_release() {
+    assert!(is_newer("2.3.0", "2.3.0-beta.1"));
+    assert!(!is_newer("2.3.0-beta.1", "2.3.0"));
+    assert!(is_newer("2.3.0-beta.1", "2.2.1"));
+    assert!(is_prerelease("2.3.0-beta.1"));
+    assert!(!is_prerelease("2.3.0"));
+}
+
+#[test]
+fn lenient_core_and_ignored_metadata() {
+    // 缺省部分视为 0，"v" 前缀和构建元数据不影响比较
+    assert!(!is_newer("v2.2", "2.2.0"));
+    assert!(!is_newer("2.2.0", "2.2"));
+    assert!(!is_newer("2.2.1+build.5", "2.2.1"));
+    assert!(is_newer("2.10.0", "2.9.9"));
+    assert!(is_same("v2.2", "2.2.0+build.5"));
+    assert!(!is_same("2.3.0-beta.1", "2.3.0"));
+    assert!(!is_same("latest", "latest"));
+
+    // 无法解析的版本号不视为更新
+    for invalid in ["", "latest", "2.x.0", "1.2.3.4", "1.0.0-", "1.0.0-beta..1"] {
+        assert!(!is_newer(invalid, "0.0.1"), "{:?}", invalid);
+    }
+}
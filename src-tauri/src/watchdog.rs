@@ -0,0 +1,215 @@
+use crate::storage;
+use crate::version;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// ==================== 更新后启动检查 ====================
+// 安装新版本后，旧程序（已重命名为 .old）以 --update-watchdog 参数启动看门狗进程：
+// 1. 看门狗等待旧程序退出（stdin 管道关闭）后再启动新版本，避免新版本被单实例检查当作重复实例
+// 2. 新版本完成 setup 且本地加密数据可以解密后写入 ready 标记
+// 3. 超时或新版本提前退出时恢复旧程序并重新启动，记录失败的版本，之后不再自动提示该版本
+
+// 看门狗进程的启动参数
+pub const WATCHDOG_ARG: &str = "--update-watchdog";
+
+// 新版本完成启动的时限
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+// 等待旧程序退出的时限
+const PARENT_EXIT_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// 超过该时间仍未完成的检查视为已中断（看门狗被结束等）
+const PENDING_STALE_SECS: i64 = 10 * 60;
+
+// 正在检查的更新
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    // 新版本号
+    pub version: String,
+    pub previous_version: String,
+    // 程序路径（已替换为新版本）
+    pub exe: PathBuf,
+    // 旧程序（.old）
+    pub backup: PathBuf,
+    pub started_at: i64,
+}
+
+// 启动失败并已回滚的版本
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedUpdate {
+    pub version: String,
+    pub reason: String,
+    pub failed_at: i64,
+}
+
+fn pending_file(dir: &Path) -> PathBuf {
+    dir.join("update_pending.json")
+}
+
+fn ready_file(dir: &Path) -> PathBuf {
+    dir.join("update_ready.dat")
+}
+
+fn failed_file(dir: &Path) -> PathBuf {
+    dir.join("update_failed.json")
+}
+
+fn load_pending(dir: &Path) -> Option<PendingUpdate> {
+    let content = fs::read_to_string(pending_file(dir)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn clear_pending(dir: &Path) {
+    fs::remove_file(pending_file(dir)).ok();
+    fs::remove_file(ready_file(dir)).ok();
+}
+
+fn load_failed(dir: &Path) -> Vec<FailedUpdate> {
+    fs::read_to_string(failed_file(dir))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_failed(dir: &Path, failed: &[FailedUpdate]) {
+    if let Ok(json) = serde_json::to_string(failed) {
+        fs::write(failed_file(dir), json).ok();
+    }
+}
+
+// 是否有未完成的启动检查（此时旧程序是回滚用的备份，不能清理）
+pub fn has_pending() -> bool {
+    load_pending(&storage::get_data_dir())
+        .is_some_and(|p| chrono::Utc::now().timestamp() - p.started_at < PENDING_STALE_SECS)
+}
+
+// 启动失败过的版本，自动检查更新时跳过
+pub fn failed_versions() -> Vec<String> {
+    load_failed(&storage::get_data_dir()).into_iter().map(|f| f.version).collect()
+}
+
+// 安装新版本后调用（替代直接启动新版本）：记录待检查的更新并从旧程序启动看门狗
+pub fn begin(version: &str, previous_version: &str, exe: &Path, backup: &Path) -> std::io::Result<()> {
+    let dir = storage::get_data_dir();
+    let pending = PendingUpdate {
+        version: version.to_string(),
+        previous_version: previous_version.to_string(),
+        exe: exe.to_path_buf(),
+        backup: backup.to_path_buf(),
+        started_at: chrono::Utc::now().timestamp(),
+    };
+    let json = serde_json::to_string(&pending).map_err(std::io::Error::other)?;
+    fs::remove_file(ready_file(&dir)).ok();
+    fs::write(pending_file(&dir), json)?;
+
+    match Command::new(backup).arg(WATCHDOG_ARG).stdin(Stdio::piped()).spawn() {
+        Ok(mut child) => {
+            // 管道写端保持到当前进程退出，看门狗读到 EOF 即表示旧程序已退出
+            std::mem::forget(child.stdin.take());
+            Ok(())
+        }
+        Err(e) => {
+            clear_pending(&dir);
+            Err(e)
+        }
+    }
+}
+
+// 看门狗进程入口（main 中检测到 WATCHDOG_ARG 时调用，不启动 Tauri）
+pub fn run() {
+    let dir = storage::get_data_dir();
+    let Some(pending) = load_pending(&dir) else {
+        return;
+    };
+
+    // 等待旧程序退出
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = std::io::stdin().read_to_end(&mut Vec::new());
+        let _ = tx.send(());
+    });
+    let _ = rx.recv_timeout(PARENT_EXIT_TIMEOUT);
+
+    let result = match Command::new(&pending.exe).spawn() {
+        Ok(mut child) => watch(&mut child, || is_ready(&dir, &pending.version), READY_TIMEOUT),
+        Err(e) => Err(format!("启动新版本失败: {}", e)),
+    };
+    match result {
+        Ok(()) => {
+            // 之前失败过的版本这次启动成功，恢复自动提示
+            let failed: Vec<FailedUpdate> = load_failed(&dir).into_iter().filter(|f| !version::is_same(&f.version, &pending.version)).collect();
+            save_failed(&dir, &failed);
+            clear_pending(&dir);
+        }
+        Err(reason) => {
+            if rollback(&dir, &pending, &reason).is_ok() {
+                let _ = Command::new(&pending.exe).spawn();
+            }
+        }
+    }
+}
+
+// 新版本是否已写入 ready 标记
+fn is_ready(dir: &Path, version: &str) -> bool {
+    fs::read_to_string(ready_file(dir)).is_ok_and(|content| version::is_same(content.trim(), version))
+}
+
+// 等待新版本就绪，返回失败原因；超时时结束新版本进程
+pub(crate) fn watch(child: &mut Child, ready: impl Fn() -> bool, timeout: Duration) -> Result<(), String> {
+    let started = Instant::now();
+    loop {
+        if ready() {
+            return Ok(());
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => return Err(format!("新版本启动后退出: {}", status)),
+            Ok(None) => {}
+            Err(e) => return Err(format!("无法获取新版本状态: {}", e)),
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("新版本 {} 秒内未完成启动", timeout.as_secs()));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// 恢复旧程序：新版本重命名为 .failed（下次启动时清理），.old 恢复为原名称，并记录失败的版本
+pub(crate) fn rollback(dir: &Path, pending: &PendingUpdate, reason: &str) -> std::io::Result<()> {
+    let name = pending.exe.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let failed_exe = pending.exe.with_file_name(format!("{}.failed", name));
+    fs::rename(&pending.exe, &failed_exe)?;
+    if let Err(e) = fs::rename(&pending.backup, &pending.exe) {
+        let _ = fs::rename(&failed_exe, &pending.exe);
+        return Err(e);
+    }
+
+    let mut failed: Vec<FailedUpdate> = load_failed(dir).into_iter().filter(|f| !version::is_same(&f.version, &pending.version)).collect();
+    failed.push(FailedUpdate {
+        version: pending.version.clone(),
+        reason: reason.to_string(),
+        failed_at: chrono::Utc::now().timestamp(),
+    });
+    save_failed(dir, &failed);
+    clear_pending(dir);
+    Ok(())
+}
+
+// 新版本完成启动后在 setup 中调用：确认本地加密数据可以解密后写入 ready 标记
+// 数据无法解密时返回 Err，调用方退出进程由看门狗回滚
+pub fn mark_ready(current_version: &str) -> Result<(), String> {
+    let dir = storage::get_data_dir();
+    if load_pending(&dir).is_none_or(|p| !version::is_same(&p.version, current_version)) {
+        return Ok(());
+    }
+    storage::verify_readable()?;
+    fs::write(ready_file(&dir), current_version).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests;
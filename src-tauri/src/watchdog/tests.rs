@@ -0,0 +1,95 @@
+// 更新后启动检查测试：文件操作在临时目录中进行
+
+use super::*;
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("atm-watchdog-test-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn seed_pending(dir: &Path) -> PendingUpdate {
+    let exe = dir.join("ATM-Tray.exe");
+    let backup = dir.join("ATM-Tray.exe.old");
+    fs::write(&exe, "new version").unwrap();
+    fs::write(&backup, "old version").unwrap();
+    let pending = PendingUpdate {
+        version: "9.9.9".to_string(),
+        previous_version: "2.2.1".to_string(),
+        exe,
+        backup,
+        started_at: chrono::Utc::now().timestamp(),
+    };
+    fs::write(pending_file(dir), serde_json::to_string(&pending).unwrap()).unwrap();
+    pending
+}
+
+// 立即退出的子进程（测试程序的 --list 只列出用例）
+fn short_lived() -> Child {
+    Command::new(std::env::current_exe().unwrap())
+        .arg("--list")
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+#[test]
+fn rollback_restores_backup_and_records_failure() {
+    let dir = temp_dir();
+    let pending = seed_pending(&dir);
+    assert_eq!(load_pending(&dir), Some(pending.clone()));
+
+    rollback(&dir, &pending, "新版本启动后退出").unwrap();
+    assert_eq!(fs::read_to_string(&pending.exe).unwrap(), "old version");
+    assert!(!pending.backup.exists());
+    assert_eq!(fs::read_to_string(dir.join("ATM-Tray.exe.failed")).unwrap(), "new version");
+    assert!(load_pending(&dir).is_none());
+
+    let failed = load_failed(&dir);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].version, "9.9.9");
+    assert_eq!(failed[0].reason, "新版本启动后退出");
+
+    // 同一版本再次失败时只保留最新记录
+    let pending = seed_pending(&dir);
+    rollback(&dir, &pending, "超时").unwrap();
+    let failed = load_failed(&dir);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].reason, "超时");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rollback_without_backup_keeps_new_version() {
+    let dir = temp_dir();
+    let pending = seed_pending(&dir);
+    fs::remove_file(&pending.backup).unwrap();
+
+    assert!(rollback(&dir, &pending, "超时").is_err());
+    assert_eq!(fs::read_to_string(&pending.exe).unwrap(), "new version");
+    assert!(load_failed(&dir).is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn ready_marker_must_match_version() {
+    let dir = temp_dir();
+    assert!(!is_ready(&dir, "9.9.9"));
+    fs::write(ready_file(&dir), "2.2.1").unwrap();
+    assert!(!is_ready(&dir, "9.9.9"));
+    fs::write(ready_file(&dir), "9.9.9").unwrap();
+    assert!(is_ready(&dir, "9.9.9"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn watch_reports_ready_or_early_exit() {
+    let mut child = short_lived();
+    assert!(watch(&mut child, || true, Duration::from_secs(5)).is_ok());
+    let _ = child.wait();
+
+    // 未写入 ready 标记就退出
+    let mut child = short_lived();
+    let err = watch(&mut child, || false, Duration::from_secs(30)).unwrap_err();
+    assert!(err.contains("退出"), "{}", err);
+}
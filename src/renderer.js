@@ -732,9 +732,9 @@ function startQuotaAutoRefresh() {
     for (const token of state.tokens) {
       try {
         const result = await invoke('get_subscription', { tokenId: token.id });
-        if (result.success && result.data) {
-          token.quota_used = result.data.premiumUsage || 0;
-          token.quota_total = result.data.premiumLimit || 500;
+        if (result.success && result.summary) {
+          token.quota_used = result.summary.used;
+          token.quota_total = result.summary.limit;
         }
       } catch (e) {
         console.error(`刷新余额失败 ${token.email}:`, e);
@@ -18,6 +18,14 @@ window.onunhandledrejection = function(event) {
   ensurePageVisible();
 };
 
+// 将后端错误转换为可显示文本（命令可能返回 { code, message } 结构的错误）
+function errorText(e) {
+  if (e && typeof e === 'object' && e.message) {
+    return e.code ? `${e.message} [${e.code}]` : e.message;
+  }
+  return String(e);
+}
+
 // 确保页面可见（防止黑屏）
 function ensurePageVisible() {
   const pageLogin = document.getElementById('page-login');
@@ -155,6 +163,9 @@ let state = {
   autoSwitch: false, // 是否启用自动切换
   currentMode: 'normal', // 当前模式: 'normal' 或 'autoswitch'
   hasBothLicenses: false, // 是否同时拥有两种激活码
+  tokensStale: false, // 当前列表是否来自离线缓存
+  tokensFetchedAt: null, // 列表获取时间（秒）
+  announcements: [], // 当前显示的服务器公告
 };
 
 // DOM 元素（延迟初始化）
@@ -200,6 +211,12 @@ async function init() {
     const appInfo = await invoke('get_app_info');
     if (appInfo.version && elements.versionBadge) {
       elements.versionBadge.textContent = 'v' + appInfo.version;
+      // 非生产环境时在版本号后标注环境名称
+      const profile = appInfo.serverProfile;
+      if (profile && profile.name !== 'production') {
+        elements.versionBadge.textContent += ' · ' + profile.name;
+        elements.versionBadge.title = profile.apiBase || '';
+      }
     }
   } catch (e) {
     console.error('获取版本号失败:', e);
@@ -216,6 +233,16 @@ async function init() {
   setupCodeListDelegation(); // 初始化激活码列表事件委托
   setupTokenListDelegation(); // 初始化 Token 列表事件委托
   
+  // 离线期间后台刷新成功后更新列表
+  listen('tokens-refreshed', async (event) => {
+    if (!state.isLoggedIn) return;
+    const wasStale = state.tokensStale;
+    await applyTokenResult(event.payload);
+    if (wasStale && event.payload.success) {
+      showToast('success', '网络已恢复，账号列表已更新', 2000);
+    }
+  });
+  
   try {
     // 先检查激活码状态
     const licenseStatus = await invoke('check_license_status');
@@ -243,9 +270,8 @@ async function init() {
   startHeartbeat();
   startAutoRefresh();
   
-  // 启动 WebSocket 实时同步
-  connectWebSocket();
-  startWsHeartbeat();
+  // 监听后端的实时推送事件
+  setupPushListeners();
   
   // 获取自动切换状态
   initAutoSwitchState();
@@ -253,8 +279,8 @@ async function init() {
   // 首次启动默认开启自启动
   initAutostart();
   
-  // 首次启动自动显示公告
-  checkAutoShowNotice();
+  // 有未关闭的服务器公告时自动显示
+  loadAnnouncements();
 }
 
 // 获取自动切换状态
@@ -328,7 +354,7 @@ async function selectMode(mode) {
     const activateResult = await invoke('activate_license', { code: licenseResult.code });
     if (!activateResult.success) {
       hideLoadingOverlay();
-      showToast('error', '激活失败: ' + (activateResult.error || '未知错误'), 2000);
+      showToast('error', '激活失败: ' + errorMessage(activateResult, '未知错误'), 2000);
       return;
     }
     
@@ -428,6 +454,8 @@ async function tryAutoLogin() {
       // 有有效会话
       state.isLoggedIn = true;
       state.tokens = tokensResult.data;
+      state.tokensStale = !!tokensResult.stale;
+      state.tokensFetchedAt = tokensResult.fetched_at || null;
       hideLoadingOverlay();
       
       // 检查是否有两种激活码
@@ -442,7 +470,7 @@ async function tryAutoLogin() {
         renderTokens();
         updateCurrentAccount();
       }
-      setStatus(`${state.tokens.length} 个账号`);
+      setStatus(tokenStatusText());
       return;
     }
     
@@ -460,6 +488,8 @@ async function tryAutoLogin() {
       if (retry.success && retry.data && retry.data.length > 0) {
         state.isLoggedIn = true;
         state.tokens = retry.data;
+        state.tokensStale = !!retry.stale;
+        state.tokensFetchedAt = retry.fetched_at || null;
         hideLoadingOverlay();
         
         // 检查是否有两种激活码
@@ -474,7 +504,7 @@ async function tryAutoLogin() {
           renderTokens();
           updateCurrentAccount();
         }
-        setStatus(`${state.tokens.length} 个账号`);
+        setStatus(tokenStatusText());
         return;
       }
     }
@@ -575,7 +605,15 @@ function bindEvents() {
     } catch (e) {
       console.error('获取自启动状态失败:', e);
     }
+    loadProxyConfig();
+    loadUpdateChannel();
   });
+
+  // 代理设置
+  document.getElementById('proxy-mode').addEventListener('change', updateProxyForm);
+  document.getElementById('btn-proxy-test').addEventListener('click', testProxy);
+  document.getElementById('btn-proxy-save').addEventListener('click', saveProxy);
+  document.getElementById('btn-copy-network-stats').addEventListener('click', copyNetworkStats);
   
   // 关闭设置弹窗
   document.getElementById('btn-close-settings').addEventListener('click', closeSettings);
@@ -587,6 +625,9 @@ function bindEvents() {
     localStorage.setItem('closeBehavior', settings.closeBehavior);
   });
   
+  // 更新频道
+  document.getElementById('update-channel').addEventListener('change', changeUpdateChannel);
+  
   // 自启动开关
   document.getElementById('autostart-toggle').addEventListener('change', async (e) => {
     try {
@@ -643,26 +684,145 @@ function closeSettings() {
   document.getElementById('modal-settings').style.display = 'none';
 }
 
-function closeNotice() {
-  // 保存"不再显示"选项（记录当前版本号）
+// ==================== 网络诊断 ====================
+// 导出各接口的请求统计，用户可直接粘贴给客服
+async function copyNetworkStats() {
+  try {
+    const [stats, info] = await Promise.all([invoke('get_network_stats'), invoke('get_app_info')]);
+    const report = JSON.stringify({ version: info.version, server: info.serverProfile, ...stats.stats }, null, 2);
+    await navigator.clipboard.writeText(report);
+    showToast('success', '网络诊断信息已复制', 2000);
+  } catch (e) {
+    showToast('error', errorText(e), 3000);
+  }
+}
+
+// ==================== 代理设置 ====================
+let proxyEnv = null;
+
+async function loadProxyConfig() {
+  try {
+    const result = await invoke('get_proxy_config');
+    const config = result.config || {};
+    document.getElementById('proxy-mode').value = config.mode || 'system';
+    document.getElementById('proxy-scheme').value = config.scheme || 'http';
+    document.getElementById('proxy-host').value = config.host || '';
+    document.getElementById('proxy-port').value = config.port || '';
+    document.getElementById('proxy-username').value = config.username || '';
+    // 密码不回传，留空表示沿用已保存的密码
+    const password = document.getElementById('proxy-password');
+    password.value = '';
+    password.placeholder = config.hasPassword ? '已保存（留空不修改）' : '密码（可选）';
+    document.getElementById('proxy-no-proxy').value = config.noProxy || '';
+    proxyEnv = config.envProxy || null;
+    updateProxyForm();
+  } catch (e) {
+    console.error('获取代理设置失败:', e);
+  }
+}
+
+function updateProxyForm() {
+  const mode = document.getElementById('proxy-mode').value;
+  document.getElementById('proxy-manual').style.display = mode === 'manual' ? 'block' : 'none';
+  const hint = document.getElementById('proxy-hint');
+  if (mode === 'system') {
+    hint.textContent = proxyEnv ? `环境变量代理: ${proxyEnv}` : '使用系统代理设置及 HTTPS_PROXY / NO_PROXY 环境变量';
+  } else {
+    hint.textContent = '';
+  }
+}
+
+function readProxyForm() {
+  const password = document.getElementById('proxy-password').value;
+  return {
+    mode: document.getElementById('proxy-mode').value,
+    scheme: document.getElementById('proxy-scheme').value,
+    host: document.getElementById('proxy-host').value.trim(),
+    port: parseInt(document.getElementById('proxy-port').value, 10) || 0,
+    username: document.getElementById('proxy-username').value.trim() || null,
+    password: password ? password : null,
+    noProxy: document.getElementById('proxy-no-proxy').value.trim() || null
+  };
+}
+
+async function testProxy() {
+  const btn = document.getElementById('btn-proxy-test');
+  btn.disabled = true;
+  showToast('loading', '正在测试连接...');
+  try {
+    const result = await invoke('test_proxy_connection', { config: readProxyForm() });
+    if (result.success) {
+      showToast('success', `连接成功 (${result.latencyMs}ms)`, 2000);
+    } else {
+      showToast('error', result.error || '连接失败', 3000);
+    }
+  } catch (e) {
+    showToast('error', errorText(e), 3000);
+  } finally {
+    btn.disabled = false;
+  }
+}
+
+async function saveProxy() {
+  try {
+    const result = await invoke('set_proxy_config', { config: readProxyForm() });
+    if (result.success) {
+      showToast('success', '代理设置已保存', 2000);
+      loadProxyConfig();
+    } else {
+      showToast('error', result.error || '保存失败', 3000);
+    }
+  } catch (e) {
+    showToast('error', errorText(e), 3000);
+  }
+}
+
+async function closeNotice() {
+  // 勾选"不再显示"时关闭当前显示的公告（后端加密保存）
   const dontShow = document.getElementById('notice-dont-show');
   if (dontShow && dontShow.checked) {
-    localStorage.setItem('noticeDismissedVersion', APP_VERSION);
+    for (const item of state.announcements) {
+      try {
+        await invoke('dismiss_announcement', { id: item.id });
+      } catch (e) {
+        console.error('[Notice] 关闭公告失败:', e);
+      }
+    }
+    state.announcements = [];
+    renderAnnouncements();
+    dontShow.checked = false;
   }
   document.getElementById('modal-notice').style.display = 'none';
 }
 
-// 检查是否需要自动显示公告（每个新版本显示一次）
-function checkAutoShowNotice() {
-  const dismissedVersion = localStorage.getItem('noticeDismissedVersion');
-  // 如果没有记录，或者记录的版本 < 当前版本，则显示公告
-  if (!dismissedVersion || dismissedVersion < APP_VERSION) {
-    document.getElementById('modal-notice').style.display = 'flex';
-    // 新版本自动标记为已显示（不勾选"不再提醒"也只弹一次）
-    localStorage.setItem('noticeDismissedVersion', APP_VERSION);
+// 获取服务器公告（后端已过滤过期、不适用于当前版本和已关闭的公告）
+async function loadAnnouncements() {
+  try {
+    const result = await invoke('get_announcements');
+    if (!result.success) {
+      console.log('[Notice] 获取公告失败:', result.error);
+      return;
+    }
+    state.announcements = result.data || [];
+    renderAnnouncements();
+    if (state.announcements.length > 0) {
+      document.getElementById('modal-notice').style.display = 'flex';
+    }
+  } catch (e) {
+    console.error('[Notice] 获取公告失败:', e);
   }
 }
 
+function renderAnnouncements() {
+  const container = document.getElementById('notice-announcements');
+  container.innerHTML = state.announcements.map(item => `
+    <div class="notice-section notice-announcement notice-${escapeHtml(item.severity)}">
+      <h4>${escapeHtml(item.title)}</h4>
+      <p>${escapeHtml(item.body).replace(/\n/g, '<br>')}</p>
+    </div>
+  `).join('');
+}
+
 function closeAddCodeModal() {
   document.getElementById('modal-add-code').style.display = 'none';
 }
@@ -693,7 +853,7 @@ async function handleAddNewCode() {
       await loadTokens();
       setStatus('激活码添加成功');
     } else {
-      elements.addCodeError.textContent = result.error || '激活失败';
+      elements.addCodeError.textContent = errorMessage(result, '激活失败');
       elements.addCodeError.style.display = 'block';
     }
   } catch (e) {
@@ -787,7 +947,7 @@ async function handleSwitchCode(code) {
       await loadTokens();
       setStatus('切换成功');
     } else {
-      setStatus(result.error || '切换失败');
+      setStatus(errorMessage(result, '切换失败'));
     }
   } catch (e) {
     setStatus('切换失败');
@@ -888,7 +1048,7 @@ async function handleActivate() {
       showMainPage();
       loadTokens();
     } else {
-      showError(result.error || '激活失败');
+      showError(errorMessage(result, '激活失败'));
     }
   } catch (e) {
     showError(`激活失败: ${e}`);
@@ -903,35 +1063,54 @@ async function loadTokens() {
   
   try {
     const result = await invoke('get_all_tokens');
-    if (result.success) {
-      state.tokens = result.data || [];
-      
-      // 如果当前选中的 Token 不在列表中了，重置选中状态
-      if (state.activeTokenId && !state.tokens.find(t => t.id === state.activeTokenId)) {
-        state.activeTokenId = null;
-      }
-
-      renderTokens();
-      updateCurrentAccount();
-      
-      // 自动切换检查（仅在启用自动切换时）
-      if (state.autoSwitch) {
-        await checkAndAutoSwitch();
-      }
-      
-      if (state.tokens.length > 0) {
-        setStatus(`${state.tokens.length} 个账号`);
-      } else {
-        setStatus('暂无可用账号');
-      }
-    } else {
-      setStatus(`加载失败: ${result.error}`);
-    }
+    await applyTokenResult(result);
   } catch (e) {
     setStatus(`加载失败: ${e}`);
   }
 }
 
+// 应用 get_all_tokens / tokens-refreshed 的结果
+async function applyTokenResult(result) {
+  // 退出登录或切换模式后被取消的旧请求，新的请求会更新列表
+  if (result.errorCode === 'CANCELLED') return;
+  if (!result.success) {
+    setStatus(`加载失败: ${errorMessage(result, '未知错误')}`);
+    return;
+  }
+  state.tokens = result.data || [];
+  state.tokensStale = !!result.stale;
+  state.tokensFetchedAt = result.fetched_at || null;
+  
+  // 如果当前选中的 Token 不在列表中了，重置选中状态
+  if (state.activeTokenId && !state.tokens.find(t => t.id === state.activeTokenId)) {
+    state.activeTokenId = null;
+  }
+
+  renderTokens();
+  updateCurrentAccount();
+  
+  // 自动切换检查（仅在启用自动切换时；离线缓存的余额可能已过期，不据此切换）
+  if (state.autoSwitch && !state.tokensStale) {
+    await checkAndAutoSwitch();
+  }
+  
+  setStatus(tokenStatusText());
+}
+
+// 账号数量状态文本，离线时附带缓存时间
+function tokenStatusText() {
+  if (state.tokens.length === 0) {
+    return '暂无可用账号';
+  }
+  if (state.tokensStale) {
+    const time = state.tokensFetchedAt
+      ? new Date(state.tokensFetchedAt * 1000).toLocaleString('zh-CN', { month: '2-digit', day: '2-digit', hour: '2-digit', minute: '2-digit' })
+      : '未知';
+    return `离线模式 · ${state.tokens.length} 个账号（更新于 ${time}）`;
+  }
+  return `${state.tokens.length} 个账号`;
+}
+
 // 检查并自动切换 Token（当余额用完时）
 // 策略：优先使用余额最少的 Token（用完一个再用下一个）
 async function checkAndAutoSwitch() {
@@ -1131,11 +1310,11 @@ async function activateToken(tokenId) {
       updateCurrentAccount();
       setStatus(`已激活: ${token.email}`);
       showToast('success', '切换成功', 1500);
-      // 订阅该 token 的实时更新
-      subscribeTokenUpdate(tokenId);
+    } else if (result.errorCode === 'CANCELLED') {
+      // 已选择其他账号，以后发起的切换为准
     } else {
       setStatus(`激活失败`);
-      showToast('error', `切换失败: ${result.error}`, 3000);
+      showToast('error', `切换失败: ${errorMessage(result, '未知错误')}`, 3000);
     }
   } catch (e) {
     setStatus(`激活失败`);
@@ -1167,7 +1346,6 @@ async function handleLogoutWithClear() {
   try {
     await invoke('clear_all_data');
     // 清除本地存储
-    localStorage.removeItem('noticeDismissedVersion');
     localStorage.removeItem('closeBehavior');
   } catch (e) {
     console.error('清理数据失败:', e);
@@ -1188,7 +1366,6 @@ async function handleUnbindAndExit() {
   try {
     const result = await invoke('unbind_and_clear');
     // 清除本地存储
-    localStorage.removeItem('noticeDismissedVersion');
     localStorage.removeItem('closeBehavior');
     
     if (result.results && result.results.length > 0) {
@@ -1337,6 +1514,12 @@ function formatQuota(num) {
   return num.toString();
 }
 
+// 失败信息附带请求 ID，用户反馈问题时提供给客服即可查到对应的服务器日志
+function errorMessage(result, fallback) {
+  const message = result.error || fallback;
+  return result.requestId ? `${message}（请求 ID: ${result.requestId}）` : message;
+}
+
 // 安全转义：防止 XSS 注入（包含 ' " 转义，防止属性/JS字符串上下文注入）
 function escapeHtml(str) {
   if (!str) return '';
@@ -1356,7 +1539,7 @@ function startHeartbeat() {
     if (!state.isLoggedIn) return;
     try {
       const result = await invoke('heartbeat');
-      if (!result.valid) {
+      if (!result.valid && !result.cancelled) {
         state.isLoggedIn = false;
         showLoginPage();
         showError('会话已过期');
@@ -1375,91 +1558,76 @@ function startAutoRefresh() {
   }, 30000);
 }
 
-// ==================== WebSocket 实时同步 ====================
-let ws = null;
-let wsReconnectTimer = null;
-let wsHeartbeatTimer = null;
-const WS_URL = 'wss://dd.776523718.xyz/ws'; // WebSocket 服务器地址
-
-function connectWebSocket() {
-  if (ws && ws.readyState === WebSocket.OPEN) return;
+// ==================== 实时推送 ====================
+// 推送连接由后端维护，这里只处理后端转发的事件
+function setupPushListeners() {
+  listen('push-status', (event) => {
+    const status = event.payload;
+    if (status.connected) {
+      console.log('[Push] 已连接');
+    } else {
+      console.log(`[Push] 连接断开: ${status.error || '未知原因'}，${status.reconnectInMs}ms 后重连`);
+    }
+  });
   
+  // 服务器推送 token 更新后，后端已同步本地 token
+  listen('token-synced', (event) => {
+    const result = event.payload;
+    if (result.refreshed) {
+      console.log('[Push] 本地 token 已同步');
+      showToast('success', 'Token 已自动同步', 2000);
+    } else if (result.error) {
+      console.error('[Push] 同步失败:', result.error);
+    }
+  });
+}
+
+// ==================== 自动更新 ====================
+let updateInfo = null;
+
+async function loadUpdateChannel() {
   try {
-    ws = new WebSocket(WS_URL);
-    
-    ws.onopen = () => {
-      console.log('[WebSocket] 已连接');
-      // 如果有激活的 token，订阅更新
-      if (state.activeTokenId) {
-        subscribeTokenUpdate(state.activeTokenId);
-      }
-    };
-    
-    ws.onmessage = async (event) => {
-      try {
-        const msg = JSON.parse(event.data);
-        console.log('[WebSocket] 收到消息:', msg);
-        
-        if (msg.type === 'token_updated' && msg.token_id === state.activeTokenId) {
-          console.log('[WebSocket] Token 已更新，立即同步...');
-          // 立即同步本地 token
-          try {
-            const result = await invoke('refresh_active_token', { force: true });
-            if (result.refreshed) {
-              console.log('[WebSocket] 本地 token 已同步');
-              showToast('success', 'Token 已自动同步', 2000);
-            }
-          } catch (e) {
-            console.error('[WebSocket] 同步失败:', e);
-          }
-        }
-        
-        if (msg.type === 'pong') {
-          console.log('[WebSocket] 心跳正常');
-        }
-      } catch (e) {
-        console.error('[WebSocket] 消息解析错误:', e);
-      }
-    };
-    
-    ws.onclose = () => {
-      console.log('[WebSocket] 连接关闭，5秒后重连...');
-      ws = null;
-      // 5秒后重连
-      if (wsReconnectTimer) clearTimeout(wsReconnectTimer);
-      wsReconnectTimer = setTimeout(connectWebSocket, 5000);
-    };
-    
-    ws.onerror = (err) => {
-      console.error('[WebSocket] 错误:', err);
-    };
+    const result = await invoke('get_update_channel');
+    document.getElementById('update-channel').value = result.channel || 'stable';
   } catch (e) {
-    console.error('[WebSocket] 连接失败:', e);
+    console.error('获取更新频道失败:', e);
   }
 }
 
-// 订阅特定 token 的更新
-function subscribeTokenUpdate(tokenId) {
-  if (ws && ws.readyState === WebSocket.OPEN && tokenId) {
-    ws.send(JSON.stringify({ type: 'subscribe', token_id: tokenId }));
-    console.log('[WebSocket] 订阅 token:', tokenId);
+// 切换更新频道后立即检查更新；从测试版切回正式版时由用户确认是否降级
+async function changeUpdateChannel(e) {
+  const channel = e.target.value;
+  try {
+    const result = await invoke('set_update_channel', { channel });
+    if (!result.success) {
+      showToast('error', result.error || '设置更新频道失败', 3000);
+      loadUpdateChannel();
+      return;
+    }
+  } catch (err) {
+    console.error('设置更新频道失败:', err);
+    loadUpdateChannel();
+    return;
   }
-}
-
-// WebSocket 心跳
-function startWsHeartbeat() {
-  if (wsHeartbeatTimer) return; // 防止重复创建
-  wsHeartbeatTimer = setInterval(() => {
-    if (ws && ws.readyState === WebSocket.OPEN) {
-      ws.send(JSON.stringify({ type: 'ping' }));
+  
+  try {
+    const result = await invoke('check_update', { manual: true });
+    if (result.hasUpdate) {
+      updateInfo = result;
+      showUpdateModal(result);
+    } else if (result.downgrade) {
+      updateInfo = { ...result.downgrade, downgrade: true };
+      showUpdateModal(updateInfo);
+    } else {
+      showToast('success', channel === 'beta' ? '已切换到测试版频道' : '已切换到正式版频道', 2000);
     }
-  }, 30000);
+  } catch (err) {
+    console.error('[Update] 检查更新失败:', err);
+  }
 }
 
-// ==================== 自动更新 ====================
-let updateInfo = null;
-
-async function checkForUpdate() {
+// manual 为 true 时（点击检查更新）也提示之前安装失败并已回滚的版本
+async function checkForUpdate(manual = false) {
   console.log('[Update] 开始检查更新...');
   
   // 显示检查中状态
@@ -1470,7 +1638,7 @@ async function checkForUpdate() {
   }
   
   try {
-    const result = await invoke('check_update');
+    const result = await invoke('check_update', { manual });
     console.log('[Update] 检查结果:', result);
     if (result.hasUpdate) {
       console.log('[Update] 发现新版本!');
@@ -1498,10 +1666,17 @@ function isMacOS() {
 }
 
 function showUpdateModal(info) {
+  document.getElementById('update-title').textContent = info.downgrade ? '切换到正式版' : '发现新版本';
   document.getElementById('update-version').textContent = 'v' + info.version;
-  document.getElementById('update-size').textContent = formatSize(info.size || 0);
+  // 有增量补丁时显示补丁大小
+  document.getElementById('update-size').textContent = info.patchSize
+    ? `${formatSize(info.patchSize)}（增量，完整包 ${formatSize(info.size || 0)}）`
+    : formatSize(info.size || 0);
   document.getElementById('update-changelog').textContent = info.changelog || '修复已知问题，提升稳定性';
   document.getElementById('modal-update').style.display = 'flex';
+  if (info.previouslyFailed) {
+    showToast('warning', '该版本上次安装后未能正常启动，已恢复旧版本', 4000);
+  }
   
   const btnUpdate = document.getElementById('btn-do-update');
   
@@ -1509,7 +1684,7 @@ function showUpdateModal(info) {
   if (isMacOS()) {
     btnUpdate.textContent = '打开下载页面';
   } else {
-    btnUpdate.textContent = '立即更新';
+    btnUpdate.textContent = info.downgrade ? '安装正式版（降级）' : '立即更新';
   }
   
   // 强制更新时显示退出按钮，隐藏跳过按钮
@@ -1570,12 +1745,13 @@ async function doUpdate() {
   // 监听下载进度
   try {
     updateUnlisten = await listen('update-progress', (event) => {
-      const { downloaded, total, percent } = event.payload;
+      const { downloaded, total, percent, delta } = event.payload;
+      const label = delta ? '增量 ' : '';
       progressBar.style.width = `${percent}%`;
       if (total > 0) {
-        progressText.textContent = `${formatSize(downloaded)} / ${formatSize(total)} (${percent}%)`;
+        progressText.textContent = `${label}${formatSize(downloaded)} / ${formatSize(total)} (${percent}%)`;
       } else {
-        progressText.textContent = `已下载 ${formatSize(downloaded)}`;
+        progressText.textContent = `${label}已下载 ${formatSize(downloaded)}`;
       }
     });
   } catch (e) {
@@ -1585,15 +1761,17 @@ async function doUpdate() {
   // 执行下载和更新
   try {
     progressText.textContent = '正在下载...';
-    await invoke('download_and_update', { downloadUrl: updateInfo.downloadUrl });
+    await invoke('download_and_update', {
+      allowDowngrade: !!updateInfo.downgrade
+    });
     // 如果成功，程序会自动重启，不会执行到这里
   } catch (e) {
     console.error('[Update] 更新失败:', e);
-    showToast('error', '更新失败: ' + e, 5000);
+    showToast('error', '更新失败: ' + errorText(e), 5000);
     
     // 恢复按钮状态
     btnUpdate.disabled = false;
-    btnUpdate.textContent = '立即更新';
+    btnUpdate.textContent = updateInfo.downgrade ? '安装正式版（降级）' : '立即更新';
     btnSkip.style.display = '';
     if (!updateInfo.forceUpdate) {
       btnClose.style.display = '';
@@ -1621,7 +1799,7 @@ async function closeUpdateModal() {
 document.getElementById('btn-do-update')?.addEventListener('click', doUpdate);
 document.getElementById('btn-skip-update')?.addEventListener('click', closeUpdateModal);
 document.getElementById('btn-close-update')?.addEventListener('click', closeUpdateModal);
-document.getElementById('btn-check-update')?.addEventListener('click', checkForUpdate);
+document.getElementById('btn-check-update')?.addEventListener('click', () => checkForUpdate(true));
 
 // 启动
 document.addEventListener('DOMContentLoaded', () => {